@@ -1,4 +1,3 @@
 fn main() {
     lalrpop::process_src().unwrap();
 }
-
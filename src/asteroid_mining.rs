@@ -2,27 +2,46 @@ use std::fmt;
 
 use rust_decimal::prelude::*;
 
-use lalrpop_util::{ParseError, lalrpop_mod};
-use logos::{self, Logos};
+use lalrpop_util::{ErrorRecovery, lalrpop_mod};
+use logos::{self, Lexer, Logos};
 
 use crate::{
     LexicalError,
-    common::{DataParser, GoodConsumes},
+    common::{self, DataParser, GoodConsumes, LexExtras, NumberToken, ParsedEntries, Patchable},
+    printer::{PrintScript, ScriptWriter, decimal, good_amount, quoted},
+    schema::FieldSchema,
 };
 
 //TODO! this number tokenising is inconsistent with other token types I should change the others to split decimal numbers as consistently
 #[derive(Logos, Clone, Debug, PartialEq)]
-#[logos(skip r"[\s\t\f]+", error = LexicalError)]
+#[logos(skip r"[\s\t\f]+", error = LexicalError, extras = LexExtras)]
 #[logos(skip r"//[^\n\r]*")]
+#[logos(skip(r"///([^/\n\r][^\n\r]*)?", common::doc_comment))]
+#[logos(skip(r"/\*", common::block_comment))]
 pub enum AsteroidToken {
-    #[regex(r#""[^"]*""#, |lex| lex.slice().trim_matches('"').to_string())]
+    #[token("replace")]
+    Replace,
+    #[token("patch")]
+    Patch,
+    #[token("delete")]
+    Delete,
+
+    #[regex(r#""([^"\\]|\\.)*""#, common::string)]
+    #[regex(r#""""([^"]|"[^"]|""[^"])*""""#, common::string)]
     String(String),
 
-    #[regex(r"(\d+)", |lex|lex.slice().parse::<u8>().expect("parsing u8"), priority = 5)]
+    #[regex(r"[0-9][0-9_]*([eE]\+?[0-9]+)?", common::integer::<u8>, priority = 5)]
     Number(u8),
 
-    #[regex(r"(-?\d+\.\d*)", |lex| Decimal::from_str(lex.slice()).expect("parsed_decimal"), priority = 4)]
+    #[regex(
+        r"-?[0-9][0-9_]*(\.[0-9_]*)?([eE][+-]?[0-9]+)?",
+        common::decimal,
+        priority = 4
+    )]
     DecimalNumber(Decimal),
+    #[regex(r"\$[A-Za-z_][A-Za-z0-9_]*", common::expression)]
+    #[regex(r"\$\(", common::expression)]
+    Expression,
 
     #[token("=")]
     Equal,
@@ -68,9 +87,20 @@ impl fmt::Display for AsteroidToken {
         write!(f, "{self:?}")
     }
 }
-lalrpop_mod!(pub asteroid_mining);
 
-#[derive(Clone, Default, Debug)]
+impl<'s> NumberToken<'s> for AsteroidToken {
+    fn number(lex: &mut Lexer<'s, Self>, value: Decimal) -> Self {
+        if value.scale() == 0 {
+            AsteroidToken::Number(common::fit(lex, value))
+        } else {
+            AsteroidToken::DecimalNumber(value)
+        }
+    }
+}
+lalrpop_mod!(#[allow(clippy::ptr_arg)] pub asteroid_mining);
+
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AsteroidMiningData {
     pub level: u8,
     pub name: String,
@@ -83,8 +113,11 @@ pub struct AsteroidMiningData {
 
     pub power: Decimal,
     pub time: u8,
+    /// Doc comments written above where the entry is defined or replaced
+    pub doc: Option<String>,
 }
 
+#[derive(Clone, Debug)]
 pub enum Field {
     Name(String),
     DepotAsset(String),
@@ -95,11 +128,84 @@ pub enum Field {
     Power(Decimal),
 }
 
+impl Patchable for AsteroidMiningData {
+    type Key = u8;
+    type Field = Field;
+    fn key(&self) -> &Self::Key {
+        &self.level
+    }
+    fn set_key(&mut self, key: Self::Key) {
+        self.level = key;
+    }
+    fn apply(&mut self, field: Self::Field) {
+        match field {
+            Field::Name(n) => self.name = n,
+            Field::DepotAsset(n) => self.depot_asset = n,
+            Field::ShipAsset(n) => self.ship_asset = n,
+            Field::Consumes(c) => self.costs = c,
+            Field::Produces(c) => self.produces = c,
+            Field::Time(t) => self.time = t,
+            Field::Power(p) => self.power = p,
+        }
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+    fn set_doc(&mut self, doc: Option<String>) {
+        self.doc = doc;
+    }
+}
+
 impl<'s> DataParser<'s> for AsteroidMiningData {
     type Token = AsteroidToken;
+    const KEYWORDS: &'static [&'static str] = &[
+        "replace",
+        "patch",
+        "delete",
+        "name",
+        "depot_asset",
+        "ship_asset",
+        "consumes",
+        "produces",
+        "good_id",
+        "amount",
+        "power",
+        "time",
+    ];
+    const SCHEMA: &'static [FieldSchema<Self>] = &[
+        FieldSchema::required("name"),
+        FieldSchema::required("depot_asset"),
+        FieldSchema::required("ship_asset"),
+        FieldSchema::recommended("time"),
+        FieldSchema::optional("power").range(Some(Decimal::ZERO), None, |a| a.power),
+    ];
     fn parse_tokens(
         tokens: Vec<(usize, Self::Token, usize)>,
-    ) -> Result<Vec<Self>, ParseError<usize, Self::Token, String>> {
-        asteroid_mining::AsteroidMiningDataParser::new().parse(tokens)
+        errors: &mut Vec<ErrorRecovery<usize, Self::Token, String>>,
+    ) -> ParsedEntries<Self, Self::Token> {
+        asteroid_mining::AsteroidMiningDataParser::new().parse(errors, tokens)
+    }
+}
+
+impl PrintScript for AsteroidMiningData {
+    const SECTION: &'static str = "#asteroid_mining";
+    fn print(&self, w: &mut ScriptWriter) {
+        w.block(format_args!("{} =", self.level), |w| {
+            w.field_unless_default("name", quoted(&self.name), self.name.is_empty());
+            w.field_unless_default(
+                "depot_asset",
+                quoted(&self.depot_asset),
+                self.depot_asset.is_empty(),
+            );
+            w.field_unless_default(
+                "ship_asset",
+                quoted(&self.ship_asset),
+                self.ship_asset.is_empty(),
+            );
+            w.list("consumes", &self.costs, good_amount);
+            w.list("produces", &self.produces, good_amount);
+            w.field_unless_default("power", decimal(self.power), self.power.is_zero());
+            w.field_unless_default("time", self.time, self.time == 0);
+        });
     }
 }
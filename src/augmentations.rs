@@ -1,23 +1,42 @@
-use std::{fmt, str::FromStr};
+use std::fmt;
 
-use lalrpop_util::{ParseError, lalrpop_mod};
-use logos::{self, Logos};
+use lalrpop_util::{ErrorRecovery, lalrpop_mod};
+use logos::{self, Lexer, Logos};
 use rust_decimal::Decimal;
 
 use crate::{
     LexicalError,
-    common::{DataParser, GoodConsumes},
+    common::{self, DataParser, GoodConsumes, LexExtras, NumberToken, ParsedEntries, Patchable},
+    printer::{PrintScript, ScriptWriter, good_amount, quoted},
+    schema::FieldSchema,
 };
 
 #[derive(Logos, Clone, Debug, PartialEq)]
-#[logos(skip r"[\s\t\f]+", error = LexicalError)]
+#[logos(skip r"[\s\t\f]+", error = LexicalError, extras = LexExtras)]
 #[logos(skip r"//[^\n\r]*")]
+#[logos(skip(r"///([^/\n\r][^\n\r]*)?", common::doc_comment))]
+#[logos(skip(r"/\*", common::block_comment))]
 pub enum AugmentationToken {
-    #[regex(r#""[^"]*""#, |lex| lex.slice().trim_matches('"').to_string())]
+    #[token("replace")]
+    Replace,
+    #[token("patch")]
+    Patch,
+    #[token("delete")]
+    Delete,
+
+    #[regex(r#""([^"\\]|\\.)*""#, common::string)]
+    #[regex(r#""""([^"]|"[^"]|""[^"])*""""#, common::string)]
     String(String),
 
-    #[regex(r"(\d+\.?\d*)", |lex| Decimal::from_str(lex.slice()).expect("parsed_decimal"), priority = 4)]
+    #[regex(
+        r"-?[0-9][0-9_]*(\.[0-9_]*)?([eE][+-]?[0-9]+)?",
+        common::decimal,
+        priority = 4
+    )]
     DecimalNumber(Decimal),
+    #[regex(r"\$[A-Za-z_][A-Za-z0-9_]*", common::expression)]
+    #[regex(r"\$\(", common::expression)]
+    Expression,
 
     #[token("=")]
     Equal,
@@ -65,9 +84,16 @@ impl fmt::Display for AugmentationToken {
     }
 }
 
-lalrpop_mod!(pub augmentations);
+impl<'s> NumberToken<'s> for AugmentationToken {
+    fn number(_lex: &mut Lexer<'s, Self>, value: Decimal) -> Self {
+        AugmentationToken::DecimalNumber(value)
+    }
+}
 
-#[derive(Clone, Default, Debug)]
+lalrpop_mod!(#[allow(clippy::ptr_arg)] pub augmentations);
+
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AugmentationData {
     pub id: String,
     pub name: String,
@@ -75,15 +101,19 @@ pub struct AugmentationData {
 
     pub consumes: Vec<GoodConsumes>,
     pub effects: Vec<Effect>,
+    /// Doc comments written above where the entry is defined or replaced
+    pub doc: Option<String>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Effect {
     AdaptStarType,
     AddTrait(String),
     RemoveTrait(String),
 }
 
+#[derive(Clone, Debug)]
 pub enum Field {
     Name(String),
     Icon(String),
@@ -91,11 +121,76 @@ pub enum Field {
     Consumes(Vec<GoodConsumes>),
 }
 
+impl Patchable for AugmentationData {
+    type Key = String;
+    type Field = Field;
+    fn key(&self) -> &Self::Key {
+        &self.id
+    }
+    fn set_key(&mut self, key: Self::Key) {
+        self.id = key;
+    }
+    fn apply(&mut self, field: Self::Field) {
+        match field {
+            Field::Name(n) => self.name = n,
+            Field::Icon(i) => self.icon = i,
+            Field::Consumes(c) => self.consumes = c,
+            Field::Effects(e) => self.effects = e,
+        }
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+    fn set_doc(&mut self, doc: Option<String>) {
+        self.doc = doc;
+    }
+}
+
 impl<'s> DataParser<'s> for AugmentationData {
     type Token = AugmentationToken;
+    const KEYWORDS: &'static [&'static str] = &[
+        "replace",
+        "patch",
+        "delete",
+        "good_id",
+        "amount",
+        "name",
+        "icon",
+        "consumes",
+        "effects",
+        "add_trait",
+        "remove_trait",
+        "star_adapt",
+    ];
+    const SCHEMA: &'static [FieldSchema<Self>] =
+        &[FieldSchema::required("name"), FieldSchema::required("icon")];
     fn parse_tokens(
         tokens: Vec<(usize, Self::Token, usize)>,
-    ) -> Result<Vec<Self>, ParseError<usize, Self::Token, String>> {
-        augmentations::AugmentationsParser::new().parse(tokens)
+        errors: &mut Vec<ErrorRecovery<usize, Self::Token, String>>,
+    ) -> ParsedEntries<Self, Self::Token> {
+        augmentations::AugmentationsParser::new().parse(errors, tokens)
+    }
+}
+
+impl PrintScript for AugmentationData {
+    const SECTION: &'static str = "#augmentations";
+    fn print(&self, w: &mut ScriptWriter) {
+        w.line(quoted(&self.id));
+        w.indented(|w| {
+            w.field_unless_default("name", quoted(&self.name), self.name.is_empty());
+            w.field_unless_default("icon", quoted(&self.icon), self.icon.is_empty());
+            w.list("consumes", &self.consumes, good_amount);
+            if !self.effects.is_empty() {
+                w.block("effects =", |w| {
+                    for effect in &self.effects {
+                        match effect {
+                            Effect::AdaptStarType => w.line("star_adapt"),
+                            Effect::AddTrait(t) => w.field("add_trait", quoted(t)),
+                            Effect::RemoveTrait(t) => w.field("remove_trait", quoted(t)),
+                        }
+                    }
+                });
+            }
+        });
     }
 }
@@ -0,0 +1,684 @@
+//! Command line tool for checking, formatting and inspecting script content
+
+use std::{
+    fmt::Debug,
+    fs,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use miette::Report;
+use pronytic_script::{
+    ParseData,
+    common::TRIPLE_QUOTE,
+    constants::ConstantData,
+    loader::{DEFAULT_EXTENSION, LoadReport, LoadedContent, LoadedFile, Loader},
+    parse, parse_with_constants,
+    printer::PrintScript,
+    stellar_system::StellarObject,
+    upgrades::{UpgradeDelta, UpgradeTree},
+};
+use rust_decimal::Decimal;
+use serde::Serialize;
+
+#[derive(Parser)]
+#[command(
+    name = "pronytic",
+    version,
+    about = "Checks, formats and inspects pronytic script content"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Loads and validates a content directory, printing every problem found
+    Check(ContentArgs),
+    /// Prints parsed data as JSON or as debug output
+    Dump {
+        /// A script file or a content directory
+        path: PathBuf,
+        /// Only print this section, such as `goods` or `planet_types`
+        #[arg(long)]
+        section: Option<String>,
+        #[arg(long, value_enum, default_value_t = Format::Json)]
+        format: Format,
+        /// Mod directories loaded on top of a content directory, in order
+        #[arg(long = "mod")]
+        mods: Vec<PathBuf>,
+    },
+    /// Rewrites script files in the canonical format
+    Fmt {
+        files: Vec<PathBuf>,
+        /// Lists the files that are not formatted instead of rewriting them
+        #[arg(long)]
+        check: bool,
+        /// Formats files with comments, which formatting removes
+        #[arg(long)]
+        strip_comments: bool,
+    },
+    /// Counts the entries of each section in every file
+    Stats(ContentArgs),
+    /// Prints which planet types become which as Graphviz DOT,
+    /// listing cycles and planet types no stellar system can lead to
+    Terraform(ContentArgs),
+    /// Prints each building's value per worker and the goods nothing makes or uses,
+    /// along with the buildings needed for any target outputs
+    Economy {
+        #[command(flatten)]
+        content: ContentArgs,
+        /// A good and how much of it to make every tick, such as `food=10`
+        #[arg(long = "target", value_parser = parse_target)]
+        targets: Vec<(String, Decimal)>,
+    },
+    /// Prints every building upgrade chain with what changes at each tier
+    Upgrades(ContentArgs),
+    /// Prints the tech tree with what each tech unlocks, listing techs that need each other
+    Tech {
+        #[command(flatten)]
+        content: ContentArgs,
+        #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+        format: GraphFormat,
+    },
+    /// Prints which files include which as Graphviz DOT
+    Includes(ContentArgs),
+}
+
+#[derive(Args)]
+struct ContentArgs {
+    /// The base content directory
+    root: PathBuf,
+    /// Mod directories loaded after the base content, in order
+    #[arg(long = "mod")]
+    mods: Vec<PathBuf>,
+    /// Extension script files have
+    #[arg(long, default_value = DEFAULT_EXTENSION)]
+    extension: String,
+}
+
+impl ContentArgs {
+    fn loader(&self) -> Loader {
+        self.mods
+            .iter()
+            .fold(Loader::new(&self.root), |l, m| l.with_mod(m))
+            .with_extension(&self.extension)
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    Json,
+    Debug,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum GraphFormat {
+    Dot,
+    Json,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let problems = match cli.command {
+        Command::Check(args) => check(&args),
+        Command::Dump {
+            path,
+            section,
+            format,
+            mods,
+        } => dump(&path, section.as_deref(), format, &mods),
+        Command::Fmt {
+            files,
+            check,
+            strip_comments,
+        } => fmt(&files, check, strip_comments),
+        Command::Stats(args) => stats(&args),
+        Command::Terraform(args) => terraform(&args),
+        Command::Tech { content, format } => tech(&content, format),
+        Command::Upgrades(args) => upgrades(&args),
+        Command::Economy { content, targets } => economy(&content, &targets),
+        Command::Includes(args) => includes(&args),
+    };
+    if problems == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Prints every problem, returning how many there were
+fn report(problems: Vec<Report>) -> usize {
+    let count = problems.len();
+    for problem in problems {
+        eprintln!("{problem:?}");
+    }
+    count
+}
+
+/// Loads content, printing whatever went wrong along the way
+fn load(loader: &Loader) -> (LoadedContent, usize) {
+    match loader.load() {
+        Ok(content) => (content, 0),
+        Err(LoadReport { errors, content }) => (*content, report(errors)),
+    }
+}
+
+fn check(args: &ContentArgs) -> usize {
+    let (content, mut problems) = load(&args.loader());
+    if let Err(validation) = content.data.validate() {
+        problems += report(validation.errors);
+    }
+    for warning in &content.data.warnings {
+        eprintln!("{:?}", Report::new(warning.clone()));
+    }
+
+    let warnings = content.data.warnings.len();
+    if problems == 0 {
+        println!(
+            "{} files checked, no errors and {warnings} warnings",
+            content.files.len()
+        );
+    } else {
+        println!(
+            "{} files checked, {problems} errors and {warnings} warnings",
+            content.files.len()
+        );
+    }
+    problems
+}
+
+fn dump(path: &Path, section: Option<&str>, format: Format, mods: &[PathBuf]) -> usize {
+    let (data, problems) = if path.is_dir() {
+        let loader = mods.iter().fold(Loader::new(path), |l, m| l.with_mod(m));
+        let (content, problems) = load(&loader);
+        (content.data, problems)
+    } else {
+        match fs::read_to_string(path) {
+            Ok(contents) => match parse(&path.display().to_string(), &contents) {
+                Ok(data) => (data, 0),
+                Err(parse_report) => (*parse_report.data, report(parse_report.errors)),
+            },
+            Err(e) => {
+                eprintln!("Could not read {}: {e}", path.display());
+                return 1;
+            }
+        }
+    };
+
+    fn print<T: Debug + Serialize + ?Sized>(value: &T, format: Format) {
+        match format {
+            Format::Json => match serde_json::to_string_pretty(value) {
+                Ok(json) => println!("{json}"),
+                Err(e) => eprintln!("Could not write JSON: {e}"),
+            },
+            Format::Debug => println!("{value:#?}"),
+        }
+    }
+
+    macro_rules! sections {
+        ($($name:literal => $field:ident),* $(,)?) => {
+            match section {
+                None => match format {
+                    Format::Json => print(&data, format),
+                    // The whole of `ParseData` would include every location,
+                    // only the entries themselves are printed under their section's name
+                    Format::Debug => {
+                        $(
+                            println!("{}:", $name);
+                            print(&data.$field, format);
+                        )*
+                    }
+                },
+                $( Some($name) => print(&data.$field, format), )*
+                Some(unknown) => {
+                    let names = data.entry_counts().iter().map(|(n, _)| *n).collect::<Vec<_>>();
+                    eprintln!(
+                        "There is no section `{unknown}`, sections are: {}",
+                        names.join(", ")
+                    );
+                    return problems + 1;
+                }
+            }
+        };
+    }
+    sections! {
+        "asteroid_mining" => asteroid_mining,
+        "augmentations" => augmentations,
+        "buildings" => building_data,
+        "constants" => constants,
+        "designations" => designation_data,
+        "goods" => goods_data,
+        "orbital" => orbital_data,
+        "planet_types" => planet_type_data,
+        "ranks" => rank_data,
+        "specie_traits" => species_trait,
+        "ships" => ships,
+        "shipyard" => shipyard,
+        "shipyard_buildings" => shipyard_buildings,
+        "stapledon_swarm" => stapledon,
+        "stellar_system" => stellar_system,
+        "tech" => tech_data,
+        "tooltips" => tooltips,
+    }
+    problems
+}
+
+fn fmt(files: &[PathBuf], check: bool, strip_comments: bool) -> usize {
+    let mut problems = 0;
+    for path in files {
+        let name = path.display().to_string();
+        let contents = match fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(e) => {
+                eprintln!("Could not read {name}: {e}");
+                problems += 1;
+                continue;
+            }
+        };
+        let data = match parse(&name, &contents) {
+            Ok(data) => data,
+            Err(parse_report) => {
+                eprintln!("{:?}", Report::new(parse_report));
+                problems += 1;
+                continue;
+            }
+        };
+        // Changes to entries in other files are not written back out
+        if !data.changes.is_empty() {
+            eprintln!("{name} changes entries from other files, it can't be formatted");
+            problems += 1;
+            continue;
+        }
+        if has_includes(&contents) {
+            eprintln!("{name} includes other files, it can't be formatted");
+            problems += 1;
+            continue;
+        }
+        if has_extensions(&contents) {
+            eprintln!(
+                "{name} uses templates that formatting would write out in full, it can't be formatted"
+            );
+            problems += 1;
+            continue;
+        }
+        if has_expressions(&contents) {
+            eprintln!(
+                "{name} uses expressions that formatting would replace with their values, it can't be formatted"
+            );
+            problems += 1;
+            continue;
+        }
+        if has_comments(&contents) && !strip_comments {
+            eprintln!(
+                "{name} has comments that formatting would remove, pass --strip-comments to format it anyway"
+            );
+            problems += 1;
+            continue;
+        }
+
+        let formatted = data.to_script();
+        if formatted == contents {
+            continue;
+        }
+        if check {
+            println!("{name}");
+            problems += 1;
+        } else if let Err(e) = fs::write(path, formatted) {
+            eprintln!("Could not write {name}: {e}");
+            problems += 1;
+        }
+    }
+    problems
+}
+
+/// Whether there is a comment outside of a string that formatting would remove,
+/// doc comments are kept along with the entry they document
+fn has_comments(contents: &str) -> bool {
+    find_outside_strings(contents, |rest| {
+        rest.starts_with("//") || rest.starts_with("/*")
+    })
+}
+
+/// Whether an expression is used outside of `#constants`,
+/// formatting would write its value in its place
+fn has_expressions(contents: &str) -> bool {
+    let mut section = "";
+    find_outside_strings(contents, |rest| {
+        if rest.starts_with('#') {
+            section = rest.split_whitespace().next().unwrap_or_default();
+        }
+        rest.starts_with('$') && section != ConstantData::SECTION
+    })
+}
+
+/// Whether an entry extends or is a template,
+/// formatting would write each entry out in full and leave out the templates
+fn has_extensions(contents: &str) -> bool {
+    find_outside_strings(contents, |rest| {
+        rest.starts_with("extends") || rest.starts_with("template")
+    })
+}
+
+/// Whether the file includes another, formatting would leave out the `#include`
+fn has_includes(contents: &str) -> bool {
+    find_outside_strings(contents, |rest| rest.starts_with("#include"))
+}
+
+/// Calls `found` with the rest of the text from each character
+/// that isn't in a string or doc comment, until it returns true.
+/// Comments are skipped once `found` has seen where they start
+fn find_outside_strings<'c>(contents: &'c str, mut found: impl FnMut(&'c str) -> bool) -> bool {
+    let mut rest = contents;
+    while let Some(c) = rest.chars().next() {
+        if let Some(string) = rest.strip_prefix(TRIPLE_QUOTE) {
+            rest = string
+                .find(TRIPLE_QUOTE)
+                .map_or("", |end| &string[end + TRIPLE_QUOTE.len()..]);
+        } else if let Some(string) = rest.strip_prefix('"') {
+            let mut escaped = false;
+            let end = string.char_indices().find(|&(_, c)| {
+                let closes = c == '"' && !escaped;
+                escaped = c == '\\' && !escaped;
+                closes
+            });
+            rest = end.map_or("", |(end, _)| &string[end + 1..]);
+        } else if rest.starts_with("///") && !rest.starts_with("////") {
+            rest = rest.find('\n').map_or("", |end| &rest[end..]);
+        } else if found(rest) {
+            return true;
+        } else if rest.starts_with("//") {
+            rest = rest.find('\n').map_or("", |end| &rest[end..]);
+        } else if let Some(comment) = rest.strip_prefix("/*") {
+            rest = comment.find("*/").map_or("", |end| &comment[end + 2..]);
+        } else {
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    false
+}
+
+fn stats(args: &ContentArgs) -> usize {
+    let files = match args.loader().discover() {
+        Ok(files) => files,
+        Err(e) => return report(vec![e.into()]),
+    };
+
+    let mut problems = 0;
+    let mut totals = ParseData::default();
+    for LoadedFile { name, path, .. } in &files {
+        let data = match fs::read_to_string(path) {
+            Ok(contents) => {
+                match parse_with_constants(name, &contents, &totals.constant_values()) {
+                    Ok(data) => data,
+                    Err(parse_report) => {
+                        problems += report(parse_report.errors);
+                        *parse_report.data
+                    }
+                }
+            }
+            Err(e) => {
+                eprintln!("Could not read {name}: {e}");
+                problems += 1;
+                continue;
+            }
+        };
+        println!("{name}");
+        print_counts(&data);
+        // Changes that don't fit what was loaded before are problems like any other
+        problems += report(totals.combine(data));
+    }
+    if files.len() > 1 {
+        println!("total");
+        print_counts(&totals);
+    }
+    problems
+}
+
+/// Prints one line per section that has entries
+fn print_counts(data: &ParseData) {
+    let counts = data.entry_counts();
+    let width = counts.iter().map(|(n, _)| n.len()).max().unwrap_or(0);
+    if counts.iter().all(|(_, c)| *c == 0) {
+        println!("    no entries");
+    }
+    for (section, count) in counts.into_iter().filter(|(_, c)| *c > 0) {
+        println!("    {section:<width$} {count:>5}");
+    }
+}
+
+fn terraform(args: &ContentArgs) -> usize {
+    let (content, problems) = load(&args.loader());
+    let graph = content.data.terraform_graph();
+    print!("{}", graph.to_dot());
+
+    for cycle in graph.cycles() {
+        eprintln!("Cycle: {}", cycle.join(" <-> "));
+    }
+    let starts = content
+        .data
+        .stellar_system
+        .iter()
+        .flat_map(|s| &s.orbiting)
+        .flat_map(|o| match o {
+            StellarObject::PlanetData(p) => std::slice::from_ref(p),
+            StellarObject::AsteroidBelt(planets) => planets.as_slice(),
+        })
+        .flat_map(|p| {
+            let moons = p.moons.iter().map(|m| m.planet_type.as_str());
+            [p.planet_type.as_str()].into_iter().chain(moons)
+        });
+    for planet_type in graph.unreachable_from(starts) {
+        eprintln!("No stellar system can become `{planet_type}`");
+    }
+    problems
+}
+
+fn tech(args: &ContentArgs, format: GraphFormat) -> usize {
+    let (content, problems) = load(&args.loader());
+    let tree = content.data.tech_tree();
+    match format {
+        GraphFormat::Dot => print!("{}", tree.to_dot()),
+        GraphFormat::Json => match serde_json::to_string_pretty(&tree) {
+            Ok(json) => println!("{json}"),
+            Err(e) => eprintln!("Could not write JSON: {e}"),
+        },
+    }
+
+    for cycle in tree.cycles() {
+        eprintln!("Techs that need each other: {}", cycle.join(", "));
+    }
+    problems
+}
+
+fn includes(args: &ContentArgs) -> usize {
+    let (content, problems) = load(&args.loader());
+    print!("{}", content.includes_to_dot());
+    problems
+}
+
+fn upgrades(args: &ContentArgs) -> usize {
+    let (content, problems) = load(&args.loader());
+    let chains = content.data.upgrade_chains();
+    // Buildings without upgrades aren't chains
+    for tree in chains.trees().iter().filter(|t| !t.upgrades.is_empty()) {
+        print_upgrades(tree, 0);
+    }
+    problems
+}
+
+/// Prints a building then everything that upgrades from it, indented below it
+fn print_upgrades(tree: &UpgradeTree, depth: usize) {
+    let indent = "    ".repeat(depth);
+    match &tree.delta {
+        Some(delta) => println!("{indent}{} {}", tree.building.id, delta_text(delta)),
+        None => println!("{indent}{}", tree.building.id),
+    }
+    for upgrade in &tree.upgrades {
+        print_upgrades(upgrade, depth + 1);
+    }
+}
+
+fn delta_text(delta: &UpgradeDelta) -> String {
+    let mut parts = Vec::new();
+    let goods = [
+        ("costs", &delta.costs),
+        ("consumes", &delta.consumes),
+        ("upkeep", &delta.upkeep),
+        ("produces", &delta.produces),
+    ];
+    for (name, goods) in goods {
+        for (id, amount) in goods {
+            parts.push(format!("{name} {amount:+} {id}"));
+        }
+    }
+    if !delta.energy.is_zero() {
+        parts.push(format!("energy {:+}", delta.energy));
+    }
+    if delta.workers != 0 {
+        parts.push(format!("workers {:+}", delta.workers));
+    }
+    if delta.housing != 0 {
+        parts.push(format!("housing {:+}", delta.housing));
+    }
+    if parts.is_empty() {
+        "(no changes)".to_string()
+    } else {
+        format!("({})", parts.join(", "))
+    }
+}
+
+fn parse_target(target: &str) -> Result<(String, Decimal), String> {
+    let (good, amount) = target
+        .split_once('=')
+        .ok_or_else(|| format!("expected `good=amount`, got `{target}`"))?;
+    let amount = amount
+        .parse()
+        .map_err(|e| format!("`{amount}` is not a number: {e}"))?;
+    Ok((good.to_string(), amount))
+}
+
+fn economy(args: &ContentArgs, targets: &[(String, Decimal)]) -> usize {
+    let (content, mut problems) = load(&args.loader());
+    let economy = content.data.economy();
+    print!("{}", economy.value_table());
+
+    let unproduced = economy.unproduced();
+    if !unproduced.is_empty() {
+        println!("\nUsed but never produced: {}", unproduced.join(", "));
+    }
+    let unconsumed = economy.unconsumed();
+    if !unconsumed.is_empty() {
+        println!("\nProduced but never used: {}", unconsumed.join(", "));
+    }
+
+    if !targets.is_empty() {
+        let targets: Vec<_> = targets.iter().map(|(g, a)| (g.as_str(), *a)).collect();
+        match economy.solve(&targets) {
+            Ok(plan) => print!("\n{}", plan.to_table()),
+            Err(e) => problems += report(vec![e.into()]),
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `files` under a new directory, returning its path
+    fn write(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("pronytic-cli-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (path, contents) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        root
+    }
+
+    fn content(root: PathBuf) -> ContentArgs {
+        ContentArgs {
+            root,
+            mods: Vec::new(),
+            extension: DEFAULT_EXTENSION.to_string(),
+        }
+    }
+
+    #[test]
+    fn check_counts_every_error() {
+        let root = write(
+            "check",
+            &[(
+                "buildings.txt",
+                "#buildings\n\"mine\"\n    name = \"Mine\"\n    produces = [ { \"ore\" 1.0 } ]\n    consumes = [ { \"coal\" 1.0 } ]\n",
+            )],
+        );
+        assert_eq!(check(&content(root)), 2);
+    }
+
+    #[test]
+    fn stats_counts_problems_combining_files() {
+        // The extension is only checked once it meets the template in the other file
+        let root = write(
+            "stats",
+            &[
+                ("a.txt", "#goods\ntemplate \"base\"\n    icon = \"i\"\n"),
+                (
+                    "b.txt",
+                    "#goods\n\"ore\" extends \"base\"\n    buy_value = 2.0\n",
+                ),
+            ],
+        );
+        assert_eq!(stats(&content(root)), 1);
+    }
+
+    #[test]
+    fn fmt_needs_flag_to_strip_comments() {
+        let root = write(
+            "fmt",
+            &[(
+                "goods.txt",
+                "#goods\n/// Dug up\n\"ore\"\n    name = \"Ore\" // for now\n    icon = \"ore\"\n",
+            )],
+        );
+        let path = root.join("goods.txt");
+        let written = fs::read_to_string(&path).unwrap();
+        assert_eq!(fmt(std::slice::from_ref(&path), false, false), 1);
+        assert_eq!(fs::read_to_string(&path).unwrap(), written);
+
+        assert_eq!(fmt(std::slice::from_ref(&path), false, true), 0);
+        let formatted = fs::read_to_string(&path).unwrap();
+        assert!(formatted.contains("/// Dug up") && !formatted.contains("for now"));
+    }
+
+    #[test]
+    fn comment_markers_in_strings_are_not_comments() {
+        assert!(!has_comments("#goods\n\"a\"\n    name = \"a // b /* c\"\n"));
+        assert!(!has_comments("#goods\n/// Kept by formatting\n\"a\"\n"));
+        assert!(has_comments("#goods\n\"a\" // dropped by formatting\n"));
+    }
+
+    #[test]
+    fn expressions_allowed_in_constants() {
+        assert!(!has_expressions(
+            "#constants\n\"a\" = $(1 + 2)\n\"b\" = $a\n"
+        ));
+        assert!(has_expressions(
+            "#goods\n\"ore\"\n    buy_value = $(a * 2)\n"
+        ));
+    }
+
+    #[test]
+    fn target_needs_good_and_amount() {
+        assert_eq!(
+            parse_target("food=10"),
+            Ok(("food".to_string(), Decimal::TEN))
+        );
+        assert!(parse_target("food").is_err());
+        assert!(parse_target("food=lots").is_err());
+    }
+}
@@ -1,21 +1,26 @@
-use std::{
-    fmt::{self},
-    str::FromStr,
-};
+use std::fmt::{self};
 
 use rust_decimal::prelude::*;
 
 use lalrpop_util::lalrpop_mod;
 
-use crate::{LexicalError, common::DataParser};
+use crate::{
+    LexicalError,
+    common::{self, DataParser, LexExtras, NumberToken, ParsedEntries, Patchable},
+    printer::{
+        PrintScript, ScriptWriter, boolean, custom_good, decimal, float, planet_filter, quoted,
+    },
+    schema::FieldSchema,
+};
 
 pub use crate::common::PlanetFilter;
-use logos::{self, Logos};
+use logos::{self, Lexer, Logos};
 
 /// Building data to send to game
 /// this is only made for serialisation
 /// actual data structure in game is different
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct BuildingData {
     pub id: String,
     pub name: String,
@@ -53,6 +58,8 @@ pub struct BuildingData {
     pub upgrades_from: Option<String>,
 
     pub prosperity_per_job: Decimal,
+    /// Doc comments written above where the entry is defined or replaced
+    pub doc: Option<String>,
 }
 
 impl Default for BuildingData {
@@ -88,29 +95,34 @@ impl Default for BuildingData {
             tech_needed: None,
             upgrades_from: None,
             prosperity_per_job: Decimal::ONE,
+            doc: None,
         }
     }
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CustomGood {
     pub id: String,
     pub amount: Decimal,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AtmosphereImpact {
     pub added_equilibrium: Decimal,
     pub rate: Decimal,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MagnetosphereImpact {
     pub added_equilibrium: Decimal,
     pub rate: Decimal,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Station {
     pub right: f32,
     pub up: f32,
@@ -122,16 +134,30 @@ pub struct Station {
     //TODO animation information
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Category {
     Housing,
     Misc,
 }
 
 #[derive(Logos, Clone, Debug, PartialEq)]
-#[logos(skip r"[\s\t\f]+", error = LexicalError)]
+#[logos(skip r"[\s\t\f]+", error = LexicalError, extras = LexExtras)]
 #[logos(skip r"//[^\n\r]*")]
+#[logos(skip(r"///([^/\n\r][^\n\r]*)?", common::doc_comment))]
+#[logos(skip(r"/\*", common::block_comment))]
 pub enum BuildingToken {
+    #[token("replace")]
+    Replace,
+    #[token("patch")]
+    Patch,
+    #[token("delete")]
+    Delete,
+    #[token("template")]
+    Template,
+    #[token("extends")]
+    Extends,
+
     #[token("true")]
     True,
     #[token("false")]
@@ -154,14 +180,22 @@ pub enum BuildingToken {
     #[token(")")]
     RightBracket,
 
-    #[regex(r#""[^"]*""#, |lex| lex.slice().trim_matches('"').to_string())]
+    #[regex(r#""([^"\\]|\\.)*""#, common::string)]
+    #[regex(r#""""([^"]|"[^"]|""[^"])*""""#, common::string)]
     String(String),
 
-    #[regex(r"(\d+)", |lex| lex.slice().parse::<u64>().expect("parsing u8"), priority = 5)]
+    #[regex(r"[0-9][0-9_]*([eE]\+?[0-9]+)?", common::integer::<u64>, priority = 5)]
     Number(u64),
 
-    #[regex(r"(-?\d+\.?\d*)", |lex| Decimal::from_str(lex.slice()).expect("parsed_decimal"), priority = 4)]
+    #[regex(
+        r"-?[0-9][0-9_]*(\.[0-9_]*)?([eE][+-]?[0-9]+)?",
+        common::decimal,
+        priority = 4
+    )]
     DecimalNumber(Decimal),
+    #[regex(r"\$[A-Za-z_][A-Za-z0-9_]*", common::expression)]
+    #[regex(r"\$\(", common::expression)]
+    Expression,
 
     #[token("id")]
     Id,
@@ -259,7 +293,19 @@ impl fmt::Display for BuildingToken {
     }
 }
 
-lalrpop_mod!(pub buildings);
+impl<'s> NumberToken<'s> for BuildingToken {
+    fn number(lex: &mut Lexer<'s, Self>, value: Decimal) -> Self {
+        if value.scale() == 0 {
+            BuildingToken::Number(common::fit(lex, value))
+        } else {
+            BuildingToken::DecimalNumber(value)
+        }
+    }
+}
+
+// The generated symbol enum is only short lived during parsing
+lalrpop_mod!(#[allow(clippy::large_enum_variant, clippy::ptr_arg)] pub buildings);
+#[derive(Clone, Debug)]
 pub enum Field {
     Name(String),
     PlanetFilters(Vec<PlanetFilter>),
@@ -286,6 +332,7 @@ pub enum Field {
     Category(Category),
 }
 
+#[derive(Clone, Debug)]
 pub enum StationField {
     Right(f32),
     Up(f32),
@@ -294,11 +341,230 @@ pub enum StationField {
     Path(String),
 }
 
+impl Patchable for BuildingData {
+    type Key = String;
+    type Field = Field;
+    fn key(&self) -> &Self::Key {
+        &self.id
+    }
+    fn set_key(&mut self, key: Self::Key) {
+        self.id = key;
+    }
+    fn apply(&mut self, field: Self::Field) {
+        match field {
+            Field::Name(n) => {
+                self.name = n;
+            }
+            Field::PlanetFilters(p) => {
+                self.planet_filters = p;
+            }
+            Field::Initial(i) => {
+                self.initial = i;
+            }
+            Field::Unique(u) => {
+                self.unique = u;
+            }
+            Field::Energy(d) => {
+                self.energy = d;
+            }
+            Field::PrivateCosts(d) => {
+                self.private_costs = d;
+            }
+            Field::Costs(c) => self.costs = c,
+            Field::Consumes(c) => self.consumes = c,
+            Field::Upkeep(u) => self.upkeep = u,
+            Field::Produces(p) => self.produces = p,
+            Field::Housing(h) => self.housing = h,
+            Field::Workers(w) => self.workers = w,
+            Field::Category(c) => self.category = c,
+            Field::PrivateSector(p) => {
+                self.private_sector = p;
+            }
+            Field::Magnetosphere(m) => {
+                self.magnetosphere_equilibrium = m;
+            }
+            Field::Atmosphere(a) => {
+                self.atmosphere_equilibrium = a;
+            }
+            Field::TemperatureChange(t) => {
+                self.temperature_change = t;
+            }
+            Field::WaterChange(w) => {
+                self.water_change = w;
+            }
+            Field::BreathableChange(b) => {
+                self.breathable_change = b;
+            }
+            Field::TechNeeded(t) => {
+                self.tech_needed = Some(t);
+            }
+            Field::UpgradesFrom(t) => {
+                self.upgrades_from = Some(t);
+            }
+            Field::ProsperityPerJob(p) => {
+                self.prosperity_per_job = p;
+            }
+            Field::Stations(s) => {
+                self.stations = s;
+            }
+        }
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+    fn set_doc(&mut self, doc: Option<String>) {
+        self.doc = doc;
+    }
+}
+
 impl<'s> DataParser<'s> for BuildingData {
     type Token = BuildingToken;
+    const KEYWORDS: &'static [&'static str] = &[
+        "replace",
+        "patch",
+        "delete",
+        "template",
+        "extends",
+        "true",
+        "false",
+        "id",
+        "name",
+        "orbital",
+        "all_orbitals",
+        "build_planets",
+        "initial",
+        "unique",
+        "energy",
+        "private_cost",
+        "stations",
+        "costs",
+        "consumes",
+        "upkeep",
+        "produces",
+        "category",
+        "housing",
+        "workers",
+        "private_sector",
+        "misc",
+        "magnetosphere_equilibrium",
+        "atmosphere_equilibrium",
+        "temperature_change",
+        "water_change",
+        "breathable_change",
+        "tech_needed",
+        "upgrades_from",
+        "prosperity_per_job",
+        "rate",
+        "added",
+        "right",
+        "up",
+        "back",
+        "scale",
+        "path",
+    ];
+    const SCHEMA: &'static [FieldSchema<Self>] = &[
+        FieldSchema::required("name"),
+        FieldSchema::optional("private_cost").range(Some(Decimal::ZERO), None, |b| b.private_costs),
+    ];
     fn parse_tokens(
         tokens: Vec<(usize, Self::Token, usize)>,
-    ) -> Result<Vec<BuildingData>, lalrpop_util::ParseError<usize, Self::Token, String>> {
-        buildings::BuildingsParser::new().parse(tokens)
+        errors: &mut Vec<lalrpop_util::ErrorRecovery<usize, Self::Token, String>>,
+    ) -> ParsedEntries<Self, Self::Token> {
+        buildings::BuildingsParser::new().parse(errors, tokens)
+    }
+}
+
+impl PrintScript for BuildingData {
+    const SECTION: &'static str = "#buildings";
+    fn print(&self, w: &mut ScriptWriter) {
+        let d = BuildingData::default();
+        w.line(quoted(&self.id));
+        w.indented(|w| {
+            w.field_unless_default("name", quoted(&self.name), self.name == d.name);
+            w.list("build_planets", &self.planet_filters, planet_filter);
+            w.field_unless_default("initial", boolean(self.initial), self.initial == d.initial);
+            w.field_unless_default("unique", boolean(self.unique), self.unique == d.unique);
+            w.field_unless_default("energy", decimal(self.energy), self.energy == d.energy);
+            w.list("costs", &self.costs, custom_good);
+            w.field_unless_default(
+                "private_cost",
+                decimal(self.private_costs),
+                self.private_costs == d.private_costs,
+            );
+            w.list("consumes", &self.consumes, custom_good);
+            w.list("upkeep", &self.upkeep, custom_good);
+            w.list("produces", &self.produces, custom_good);
+            let category = match self.category {
+                Category::Housing => "housing",
+                Category::Misc => "misc",
+            };
+            w.field_unless_default("category", category, self.category == d.category);
+            w.field_unless_default("housing", self.housing, self.housing == d.housing);
+            w.field_unless_default("workers", self.workers, self.workers == d.workers);
+            w.field_unless_default(
+                "private_sector",
+                boolean(self.private_sector),
+                self.private_sector == d.private_sector,
+            );
+            w.list("stations", &self.stations, |s| {
+                format!(
+                    "{{ right = {} up = {} back = {} scale = {} path = {} }}",
+                    float(s.right),
+                    float(s.up),
+                    float(s.back),
+                    float(s.scale),
+                    quoted(&s.path)
+                )
+            });
+            let impacts = [
+                (
+                    "magnetosphere_equilibrium",
+                    &self.magnetosphere_equilibrium.added_equilibrium,
+                    &self.magnetosphere_equilibrium.rate,
+                    self.magnetosphere_equilibrium == d.magnetosphere_equilibrium,
+                ),
+                (
+                    "atmosphere_equilibrium",
+                    &self.atmosphere_equilibrium.added_equilibrium,
+                    &self.atmosphere_equilibrium.rate,
+                    self.atmosphere_equilibrium == d.atmosphere_equilibrium,
+                ),
+            ];
+            for (name, added, rate, is_default) in impacts {
+                let value = format!(
+                    "{{ added = {} rate = {} }}",
+                    decimal(*added),
+                    decimal(*rate)
+                );
+                w.field_unless_default(name, value, is_default);
+            }
+            let changes = [
+                (
+                    "temperature_change",
+                    self.temperature_change,
+                    d.temperature_change,
+                ),
+                ("water_change", self.water_change, d.water_change),
+                (
+                    "breathable_change",
+                    self.breathable_change,
+                    d.breathable_change,
+                ),
+                (
+                    "prosperity_per_job",
+                    self.prosperity_per_job,
+                    d.prosperity_per_job,
+                ),
+            ];
+            for (name, value, default) in changes {
+                w.field_unless_default(name, decimal(value), value == default);
+            }
+            if let Some(t) = &self.tech_needed {
+                w.field("tech_needed", quoted(t));
+            }
+            if let Some(u) = &self.upgrades_from {
+                w.field("upgrades_from", quoted(u));
+            }
+        });
     }
 }
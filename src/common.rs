@@ -1,24 +1,308 @@
-use lalrpop_util::ParseError;
-use rust_decimal::Decimal;
+use std::{fmt, ops::Range, str::FromStr, sync::Arc};
+
+use lalrpop_util::{ErrorRecovery, ParseError};
+use logos::{Lexer, Logos, Skip};
+use miette::{NamedSource, SourceSpan};
+use rust_decimal::{Decimal, prelude::ToPrimitive};
+
+use crate::{
+    LexicalError,
+    expression::{self, Constants, ExpressionFailure, Problem},
+    schema::FieldSchema,
+};
 use rust_decimal_macros::dec;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GoodConsumes {
     pub id: String,
     pub amount: Decimal,
 }
 
+/// Data built up one field at a time,
+/// so a later file can patch individual fields of an entry
+pub trait Patchable {
+    /// The id or level that identifies an entry across files
+    type Key: PartialEq + Clone + fmt::Debug;
+    type Field: Clone + fmt::Debug;
+    fn key(&self) -> &Self::Key;
+    fn set_key(&mut self, key: Self::Key);
+    fn apply(&mut self, field: Self::Field);
+    /// The doc comments written above the entry
+    fn doc(&self) -> Option<&str>;
+    fn set_doc(&mut self, doc: Option<String>);
+}
+
+/// An entry as written in a script,
+/// entries other than definitions change an entry loaded before them
+#[derive(Clone, Debug)]
+pub enum Entry<T: Patchable> {
+    Define(T),
+    /// Takes the place of the entry with the same key
+    Replace(T),
+    /// Sets only the fields written, the rest are left as they were
+    Patch(T::Key, Vec<T::Field>),
+    /// Removes the entry with the same key
+    Delete(T::Key),
+    /// Fields for other entries to extend, it is not loaded as an entry itself
+    Template(T),
+    /// Loaded once the entry or template it extends is found
+    Extend(Extension<T>),
+}
+
+impl<T: Patchable> Entry<T> {
+    pub fn new(data: T, replace: bool) -> Self {
+        if replace {
+            Entry::Replace(data)
+        } else {
+            Entry::Define(data)
+        }
+    }
+
+    pub fn key(&self) -> &T::Key {
+        match self {
+            Entry::Define(data) | Entry::Replace(data) | Entry::Template(data) => data.key(),
+            Entry::Patch(key, _) | Entry::Delete(key) => key,
+            Entry::Extend(extension) => &extension.key,
+        }
+    }
+
+    /// The keyword the entry was written with
+    pub fn keyword(&self) -> &'static str {
+        match self {
+            Entry::Define(_) => "define",
+            Entry::Replace(_) => "replace",
+            Entry::Patch(_, _) => "patch",
+            Entry::Delete(_) => "delete",
+            Entry::Template(_) => "template",
+            Entry::Extend(_) => "extend",
+        }
+    }
+}
+
+/// What an extension is loaded as once the entry or template it extends is found
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ExtensionKind {
+    Define,
+    Replace,
+    Template,
+}
+
+impl ExtensionKind {
+    pub fn new(replace: bool) -> Self {
+        if replace {
+            ExtensionKind::Replace
+        } else {
+            ExtensionKind::Define
+        }
+    }
+}
+
+/// An entry written as `"id" extends "parent"`, which starts as a copy of
+/// the last template or entry loaded with the parent's key
+#[derive(Clone, Debug)]
+pub struct Extension<T: Patchable> {
+    pub key: T::Key,
+    pub parent: T::Key,
+    /// Applied over the parent's fields, so they take the place of what it set
+    pub fields: Vec<T::Field>,
+    pub kind: ExtensionKind,
+    pub doc: Option<String>,
+}
+
+impl<T: Patchable + Clone> Extension<T> {
+    pub fn new(key: T::Key, parent: T::Key, fields: Vec<T::Field>, kind: ExtensionKind) -> Self {
+        Extension {
+            key,
+            parent,
+            fields,
+            kind,
+            doc: None,
+        }
+    }
+
+    /// The entry this becomes when it extends `parent`
+    pub fn resolve(self, parent: &T) -> Entry<T> {
+        let mut data = parent.clone();
+        data.set_key(self.key);
+        for field in self.fields {
+            data.apply(field);
+        }
+        data.set_doc(self.doc);
+        match self.kind {
+            ExtensionKind::Define => Entry::Define(data),
+            ExtensionKind::Replace => Entry::Replace(data),
+            ExtensionKind::Template => Entry::Template(data),
+        }
+    }
+}
+
+/// Splits the fields parsed for an entry from their spans,
+/// recording the spans in the entry's span
+pub fn take_fields<F>(fields: Vec<Option<(F, SourceSpan)>>, span: &mut EntrySpan) -> Vec<F> {
+    fields
+        .into_iter()
+        .flatten()
+        .map(|(field, field_span)| {
+            span.fields.push(field_span);
+            field
+        })
+        .collect()
+}
+
 pub trait DataParser<'s>
 where
-    Self: Sized,
+    Self: Sized + Patchable + 'static,
 {
     type Token;
+    /// Fields that add to a list each time they are written,
+    /// rather than replacing the value written before them
+    const REPEATABLE_FIELDS: &'static [&'static str] = &[];
+    /// Every word the section's lexer reads as a keyword
+    const KEYWORDS: &'static [&'static str] = &[];
+    /// Which fields an entry has to set and the values they allow,
+    /// checked for every entry once it is parsed
+    const SCHEMA: &'static [FieldSchema<Self>] = &[];
+    /// Parses a section's tokens, pushing the errors it was able to
+    /// recover from into `errors` so the remaining entries are still returned
+    /// along with where each of them was written
     fn parse_tokens(
         tokens: Vec<(usize, Self::Token, usize)>,
-    ) -> Result<Vec<Self>, ParseError<usize, Self::Token, String>>;
+        errors: &mut Vec<ErrorRecovery<usize, Self::Token, String>>,
+    ) -> ParsedEntries<Self, Self::Token>;
+}
+
+/// The entries of a section along with where each was written
+pub type ParsedEntries<T, Token> =
+    Result<Vec<(Entry<T>, EntrySpan)>, ParseError<usize, Token, String>>;
+
+/// Where an entry was written in its file
+#[derive(Clone, Debug)]
+pub struct EntrySpan {
+    /// The whole entry including its fields
+    pub entry: SourceSpan,
+    /// The id, level or name the entry is known by
+    pub id: SourceSpan,
+    /// Each field of the entry in the order they were written
+    pub fields: Vec<SourceSpan>,
+}
+
+impl EntrySpan {
+    pub fn new(entry: Range<usize>, id: Range<usize>) -> Self {
+        EntrySpan {
+            entry: entry.into(),
+            id: id.into(),
+            fields: Vec::new(),
+        }
+    }
 }
 
-#[derive(Clone, Default, Debug)]
+/// A script file that entries were parsed from,
+/// shared between the locations of all of its entries
+#[derive(Debug)]
+pub struct SourceFile {
+    pub name: String,
+    pub contents: Arc<str>,
+}
+
+/// The file and span an entry came from
+#[derive(Clone, Debug)]
+pub struct Location {
+    pub file: Arc<SourceFile>,
+    pub span: EntrySpan,
+    /// Patches applied to the entry since, the latest last
+    pub patches: Vec<Location>,
+}
+
+impl Location {
+    /// The source of the file for labelling diagnostics
+    pub fn source(&self) -> NamedSource<Arc<str>> {
+        NamedSource::new(&self.file.name, self.file.contents.clone())
+    }
+
+    /// The names of the fields written in the entry
+    pub fn field_names(&self) -> Vec<String> {
+        let contents = &self.file.contents;
+        self.span
+            .fields
+            .iter()
+            .map(|f| field_name(contents, *f).to_string())
+            .collect()
+    }
+
+    /// The text of the whole entry
+    pub fn text(&self) -> &str {
+        let start = self.span.entry.offset();
+        &self.file.contents[start..start + self.span.entry.len()]
+    }
+
+    /// The latest patch that mentions `id` after its own key, or the entry itself
+    /// when none do, so problems with a patched field point at the patch
+    pub fn written_with(&self, id: &str) -> &Location {
+        let quoted = format!("\"{id}\"");
+        self.patches
+            .iter()
+            .rev()
+            .find(|patch| {
+                let start = patch.span.id.offset() + patch.span.id.len();
+                let end = patch.span.entry.offset() + patch.span.entry.len();
+                let fields = &patch.file.contents[start..end.max(start)];
+                fields.contains(&quoted) || word_position(fields, id).is_some()
+            })
+            .unwrap_or(self)
+    }
+
+    /// Finds where `id` is referenced inside the entry,
+    /// falling back to the entry's own id when it can't be found
+    pub fn find_reference(&self, id: &str) -> SourceSpan {
+        let text = self.text();
+        let quoted = format!("\"{id}\"");
+        match text.find(&quoted) {
+            Some(i) => (self.span.entry.offset() + i, quoted.len()).into(),
+            None => word_position(text, id)
+                .map(|i| (self.span.entry.offset() + i, id.len()).into())
+                .unwrap_or(self.span.id),
+        }
+    }
+}
+
+/// The keyword a field was written with, taken from the start of its span.
+/// Tooltips have no keywords so their quoted key is used instead
+pub fn field_name(contents: &str, span: SourceSpan) -> &str {
+    let text = &contents[span.offset()..span.offset() + span.len()];
+    let end = match text.strip_prefix('"') {
+        Some(rest) => rest.find('"').map(|i| i + 2).unwrap_or(text.len()),
+        None => text
+            .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+            .unwrap_or(text.len()),
+    };
+    &text[..end]
+}
+
+/// The keyword a value at `offset` is assigned to, as in `time = 300`
+pub fn assigned_field(contents: &str, offset: usize) -> Option<&str> {
+    let before = contents[..offset].trim_end().strip_suffix('=')?.trim_end();
+    let start = before
+        .trim_end_matches(|c: char| c.is_alphanumeric() || c == '_')
+        .len();
+    Some(&before[start..]).filter(|name| !name.is_empty())
+}
+
+/// Position of `word` in `text` where it isn't part of a longer word or path
+fn word_position(text: &str, word: &str) -> Option<usize> {
+    word_positions(text, word).next()
+}
+
+/// Every position of `word` in `text` where it isn't part of a longer word or path
+pub fn word_positions<'t>(text: &'t str, word: &'t str) -> impl Iterator<Item = usize> + 't {
+    let is_word = |c: char| c.is_alphanumeric() || matches!(c, '_' | '.' | '/' | '"');
+    text.match_indices(word).map(|(i, _)| i).filter(move |&i| {
+        !text[..i].ends_with(is_word) && !text[i + word.len()..].starts_with(is_word)
+    })
+}
+
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Temperature {
     kelvin: Decimal,
 }
@@ -39,19 +323,497 @@ impl Temperature {
     pub fn kelvin(&self) -> Decimal {
         self.kelvin.trunc_with_scale(2)
     }
+    /// The temperature as it was read, without truncating
+    pub fn exact_kelvin(&self) -> Decimal {
+        self.kelvin
+    }
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GoodAbundance {
     pub id: String,
     pub mean: Decimal,
     pub std_dev: Decimal,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PlanetFilter {
     PlanetSide(String),
     Orbital(String),
     AllOrbitals,
     AllPlanets,
 }
+
+/// The bound a value went past
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Limit {
+    Min(Decimal),
+    Max(Decimal),
+}
+
+impl Limit {
+    /// Describes `value` going past the limit,
+    /// such as "value 300 exceeds maximum 255 for `time`"
+    pub fn message(&self, value: impl fmt::Display, field: Option<&str>) -> String {
+        let message = match self {
+            Limit::Min(min) => format!("value {value} is below minimum {min}"),
+            Limit::Max(max) => format!("value {value} exceeds maximum {max}"),
+        };
+        match field {
+            Some(field) => format!("{message} for `{field}`"),
+            None => message,
+        }
+    }
+}
+
+/// A number literal too large for the type its token holds
+#[derive(Clone, Debug, PartialEq)]
+pub struct OutOfRange {
+    /// Where the literal is in the lexed text
+    pub span: Range<usize>,
+    /// The literal without its underscores
+    pub value: String,
+    pub limit: Limit,
+}
+
+/// State every section lexer keeps while lexing, numbers are read as the nearest
+/// value they can hold and strings keep bad escapes as written, both are reported after lexing
+#[derive(Clone, Debug, Default)]
+pub struct LexExtras {
+    pub out_of_range: Vec<OutOfRange>,
+    /// Backslashes in strings that don't start a known escape, along with what follows them
+    pub invalid_escapes: Vec<Range<usize>>,
+    pub docs: Vec<DocComment>,
+    /// The constants expressions can use
+    pub constants: Constants,
+    /// Expressions that could not be worked out, which are read as zero
+    pub expressions: Vec<ExpressionFailure>,
+}
+
+/// Integer types a number token can hold
+pub trait IntegerLiteral: Copy + TryFrom<u64> {
+    const MIN: Self;
+    const MAX: Self;
+    fn to_decimal(self) -> Decimal;
+}
+
+macro_rules! integer_literal {
+    ($($ty:ty),*) => {
+        $(
+            impl IntegerLiteral for $ty {
+                const MIN: Self = <$ty>::MIN;
+                const MAX: Self = <$ty>::MAX;
+                fn to_decimal(self) -> Decimal {
+                    Decimal::from(self)
+                }
+            }
+        )*
+    };
+}
+
+integer_literal!(u8, u16, u64);
+
+// Every lexer matches numbers with the same patterns, which have to be written out
+// in each token enum since logos only takes literals:
+//  integers `[0-9][0-9_]*([eE]\+?[0-9]+)?` with priority 5
+//  decimals `-?[0-9][0-9_]*(\.[0-9_]*)?([eE][+-]?[0-9]+)?` with priority 4
+// so anything written like an integer is an integer where the section has them.
+// Sections without decimals read a leading `-` as part of an integer
+// so negative numbers are reported as below the minimum, elsewhere they are decimals
+
+/// Reads a number literal, which can have underscores between its digits
+/// and an exponent, `None` when it is too large to be a decimal
+pub fn parse_number(literal: &str) -> Option<Decimal> {
+    let literal = literal.replace('_', "");
+    if literal.contains(['e', 'E']) {
+        Decimal::from_scientific(&literal).ok()
+    } else {
+        Decimal::from_str(&literal).ok()
+    }
+}
+
+/// Lexer callback for integer tokens, numbers too large for `I` are read as its maximum
+pub fn integer<'s, I: IntegerLiteral>(
+    lex: &mut Lexer<'s, impl Logos<'s, Source = str, Extras = LexExtras>>,
+) -> I {
+    match parse_number(lex.slice()) {
+        Some(value) => fit(lex, value),
+        None => {
+            let value = lex.slice().replace('_', "");
+            out_of_range(lex, value, Limit::Max(I::MAX.to_decimal()));
+            I::MAX
+        }
+    }
+}
+
+/// Reads a number as an `I`, numbers it can't hold are read as the nearest one it can
+pub fn fit<'s, I: IntegerLiteral>(
+    lex: &mut Lexer<'s, impl Logos<'s, Source = str, Extras = LexExtras>>,
+    value: Decimal,
+) -> I {
+    if !value.fract().is_zero() {
+        let failure = ExpressionFailure {
+            span: lex.span(),
+            problem: Problem::NotWhole(value),
+        };
+        lex.extras.expressions.push(failure);
+    }
+    let (number, limit) = nearest::<I>(value);
+    if let Some(limit) = limit {
+        out_of_range(lex, value.to_string(), limit);
+    }
+    number
+}
+
+/// Reads a number as an `I` in a grammar action, for sections whose lexer only reads decimals.
+/// Numbers it can't hold are reported at `token` and read as the nearest one it can
+pub fn whole_number<I: IntegerLiteral, T>(
+    token: (usize, T, usize),
+    value: Decimal,
+    field: &str,
+    errors: &mut Vec<ErrorRecovery<usize, T, String>>,
+) -> I {
+    let (number, limit) = nearest::<I>(value);
+    let message = match limit {
+        Some(limit) => limit.message(value, Some(field)),
+        None if !value.fract().is_zero() => format!("{} for `{field}`", Problem::NotWhole(value)),
+        None => return number,
+    };
+    errors.push(ErrorRecovery {
+        error: ParseError::User { error: message },
+        dropped_tokens: vec![token],
+    });
+    number
+}
+
+/// The nearest `I` to the whole part of `value`, along with the limit it goes past
+fn nearest<I: IntegerLiteral>(value: Decimal) -> (I, Option<Limit>) {
+    if value.is_sign_negative() && !value.trunc().is_zero() {
+        return (I::MIN, Some(Limit::Min(I::MIN.to_decimal())));
+    }
+    match value.trunc().to_u64().and_then(|n| I::try_from(n).ok()) {
+        Some(n) => (n, None),
+        None => (I::MAX, Some(Limit::Max(I::MAX.to_decimal()))),
+    }
+}
+
+/// Lexer callback for decimal tokens, numbers too large for a decimal
+/// are read as the largest or smallest one
+pub fn decimal<'s, T>(lex: &mut Lexer<'s, T>) -> Decimal
+where
+    T: Logos<'s, Source = str, Extras = LexExtras>,
+{
+    parse_number(lex.slice()).unwrap_or_else(|| {
+        let limit = if lex.slice().starts_with('-') {
+            Limit::Min(Decimal::MIN)
+        } else {
+            Limit::Max(Decimal::MAX)
+        };
+        out_of_range(lex, lex.slice().replace('_', ""), limit);
+        match limit {
+            Limit::Min(n) | Limit::Max(n) => n,
+        }
+    })
+}
+
+fn out_of_range<'s, T>(lex: &mut Lexer<'s, T>, value: String, limit: Limit)
+where
+    T: Logos<'s, Source = str, Extras = LexExtras>,
+{
+    let number = OutOfRange {
+        span: lex.span(),
+        value,
+        limit,
+    };
+    lex.extras.out_of_range.push(number);
+}
+
+// Every lexer that reads numbers reads expressions in their place:
+//  constants `\$[A-Za-z_][A-Za-z0-9_]*`
+//  expressions starting with `\$\(`, read to their closing bracket by `expression_text`
+
+/// Section tokens that a number worked out from an expression can be read as
+pub trait NumberToken<'s>: Logos<'s, Source = str, Extras = LexExtras> + Sized {
+    /// The token a literal with the same digits would be read as,
+    /// so `2.0` stays a decimal where a section also reads integers
+    fn number(lex: &mut Lexer<'s, Self>, value: Decimal) -> Self;
+}
+
+/// Lexer callback for expressions in sections that read numbers,
+/// expressions that can't be worked out are read as zero
+pub fn expression<'s, T: NumberToken<'s>>(lex: &mut Lexer<'s, T>) -> Result<T, LexicalError> {
+    let written = expression_text(lex)?;
+    let value = match expression::evaluate_written(written, &lex.extras.constants) {
+        Ok(value) => value,
+        Err(failure) => {
+            let failure = failure.shifted(lex.span().start);
+            lex.extras.expressions.push(failure);
+            Decimal::ZERO
+        }
+    };
+    Ok(T::number(lex, value))
+}
+
+/// Reads an expression to the bracket that closes it, returning it as written.
+/// A bracket that is never closed is an error up to the end of the line
+pub fn expression_text<'s, T>(lex: &mut Lexer<'s, T>) -> Result<&'s str, LexicalError>
+where
+    T: Logos<'s, Source = str>,
+{
+    if lex.slice().ends_with('(') {
+        let mut depth = 1;
+        let close = lex.remainder().char_indices().find(|&(_, c)| {
+            match c {
+                '(' => depth += 1,
+                ')' => depth -= 1,
+                _ => {}
+            }
+            depth == 0
+        });
+        match close {
+            Some((i, _)) => lex.bump(i + 1),
+            None => {
+                let line = lex.remainder().find('\n').unwrap_or(lex.remainder().len());
+                lex.bump(line);
+                return Err(LexicalError::UnterminatedExpression);
+            }
+        }
+    }
+    Ok(lex.slice())
+}
+
+// Every lexer matches strings with the same patterns:
+//  quoted strings `"([^"\\]|\\.)*"` which can use the escapes read by `unescape`
+//  triple quoted strings `"""([^"]|"[^"]|""[^"])*"""` which are read as written,
+//  so they can hold quotes and backslashes and span several lines
+
+pub const TRIPLE_QUOTE: &str = "\"\"\"";
+
+/// Lexer callback for string tokens, see the patterns above
+pub fn string<'s, T>(lex: &mut Lexer<'s, T>) -> String
+where
+    T: Logos<'s, Source = str, Extras = LexExtras>,
+{
+    let slice = lex.slice();
+    if let Some(text) = slice
+        .strip_prefix(TRIPLE_QUOTE)
+        .and_then(|s| s.strip_suffix(TRIPLE_QUOTE))
+    {
+        return dedent(text);
+    }
+    let start = lex.span().start + 1;
+    let (text, invalid) = unescape(&slice[1..slice.len() - 1]);
+    lex.extras
+        .invalid_escapes
+        .extend(invalid.into_iter().map(|r| r.start + start..r.end + start));
+    text
+}
+
+/// Replaces `\"`, `\\`, `\n`, `\r`, `\t`, `\0` and `\u{..}` with the characters they stand for,
+/// returning where any other escapes were, which are kept as written
+pub fn unescape(text: &str) -> (String, Vec<Range<usize>>) {
+    let mut out = String::with_capacity(text.len());
+    let mut invalid = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some((_, '"')) => Some('"'),
+            Some((_, '\\')) => Some('\\'),
+            Some((_, 'n')) => Some('\n'),
+            Some((_, 'r')) => Some('\r'),
+            Some((_, 't')) => Some('\t'),
+            Some((_, '0')) => Some('\0'),
+            Some((_, 'u')) => unicode_escape(&text[i + 2..]).map(|(c, len)| {
+                for _ in 0..len {
+                    chars.next();
+                }
+                c
+            }),
+            _ => None,
+        };
+        match escaped {
+            Some(e) => out.push(e),
+            None => {
+                let mut end = chars.peek().map(|(j, _)| *j).unwrap_or(text.len());
+                // A bad unicode escape is reported along with its braces
+                let braces = text[i..].strip_prefix("\\u{").and_then(|r| r.find('}'));
+                if let Some(len) = braces.filter(|len| *len <= 8) {
+                    end = i + len + 4;
+                    while chars.next_if(|(j, _)| *j < end).is_some() {}
+                }
+                out.push_str(&text[i..end]);
+                invalid.push(i..end);
+            }
+        }
+    }
+    (out, invalid)
+}
+
+/// Reads the `{..}` of a `\u{..}` escape, returning the character
+/// and how many characters the braces took up
+fn unicode_escape(text: &str) -> Option<(char, usize)> {
+    let digits = text.strip_prefix('{')?.split_once('}')?.0;
+    if digits.is_empty() || digits.len() > 6 {
+        return None;
+    }
+    let c = char::from_u32(u32::from_str_radix(digits, 16).ok()?)?;
+    Some((c, digits.len() + 2))
+}
+
+/// Removes the line break after the opening quotes of a triple quoted string,
+/// the indentation before the closing quotes and the indentation all lines share
+pub fn dedent(text: &str) -> String {
+    let text = text
+        .strip_prefix("\r\n")
+        .or_else(|| text.strip_prefix('\n'))
+        .unwrap_or(text);
+    let mut lines: Vec<&str> = text.split('\n').map(|l| l.trim_end_matches('\r')).collect();
+    if lines.len() > 1 && lines.last().is_some_and(|l| l.trim().is_empty()) {
+        lines.pop();
+    }
+    let indent = lines
+        .iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_start().len())
+        .min()
+        .unwrap_or_default();
+    let lines: Vec<&str> = lines
+        .iter()
+        .map(|l| l.get(indent..).unwrap_or_default())
+        .collect();
+    lines.join("\n")
+}
+
+// Every lexer skips the same comments:
+//  line comments `//[^\n\r]*`
+//  doc comments `///([^/\n\r][^\n\r]*)?` which are kept by `doc_comment`
+//  block comments starting with `/\*` which are skipped by `block_comment`
+
+/// A `///` comment, which documents the entry written after it
+#[derive(Clone, Debug, PartialEq)]
+pub struct DocComment {
+    pub span: Range<usize>,
+    /// The comment without its slashes or the space after them
+    pub text: String,
+}
+
+/// Lexer callback keeping doc comments for the entries they are written above
+pub fn doc_comment<'s, T>(lex: &mut Lexer<'s, T>) -> Skip
+where
+    T: Logos<'s, Source = str, Extras = LexExtras>,
+{
+    let text = &lex.slice()[3..];
+    let text = text.strip_prefix(' ').unwrap_or(text).trim_end();
+    let doc = DocComment {
+        span: lex.span(),
+        text: text.to_string(),
+    };
+    lex.extras.docs.push(doc);
+    Skip
+}
+
+/// Lexer callback skipping to the `*/` that closes a block comment,
+/// block comments inside it have to be closed first
+pub fn block_comment<'s, T>(lex: &mut Lexer<'s, T>) -> Result<Skip, LexicalError>
+where
+    T: Logos<'s, Source = str>,
+{
+    match block_comment_len(lex.remainder()) {
+        Some(len) => {
+            lex.bump(len);
+            Ok(Skip)
+        }
+        None => {
+            lex.bump(lex.remainder().len());
+            Err(LexicalError::UnterminatedComment)
+        }
+    }
+}
+
+/// How far the `*/` closing a block comment is from just after its `/*`, past the `*/`
+fn block_comment_len(rest: &str) -> Option<usize> {
+    let rest = rest.as_bytes();
+    let mut depth = 1;
+    let mut i = 0;
+    while i < rest.len() {
+        if rest[i..].starts_with(b"/*") {
+            depth += 1;
+            i += 2;
+        } else if rest[i..].starts_with(b"*/") {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return Some(i);
+            }
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
+
+/// Where the `"` closing a string is from just after the `"` opening it,
+/// skipping escaped quotes
+pub fn closing_quote(rest: &str) -> Option<usize> {
+    let mut escaped = false;
+    rest.char_indices()
+        .find(|&(_, c)| {
+            let closes = c == '"' && !escaped;
+            escaped = c == '\\' && !escaped;
+            closes
+        })
+        .map(|(i, _)| i)
+}
+
+/// Lexer callback for the text of a section, which runs until a `#` at the start of a line
+/// or after whitespace, `#` in strings and comments doesn't end it
+pub fn section_contents<'s, T>(lex: &mut Lexer<'s, T>) -> String
+where
+    T: Logos<'s, Source = str>,
+{
+    let text = &lex.source()[lex.span().start..];
+    let mut i = 0;
+    let mut after_space = false;
+    while let Some(c) = text[i..].chars().next() {
+        let rest = &text[i..];
+        if c == '#' && after_space {
+            break;
+        }
+        let skipped = if let Some(string) = rest.strip_prefix(TRIPLE_QUOTE) {
+            string.find(TRIPLE_QUOTE).map(|end| end + 6)
+        } else if c == '"' {
+            closing_quote(&rest[1..]).map(|end| end + 2)
+        } else if rest.starts_with("//") {
+            Some(rest.find('\n').unwrap_or(rest.len()))
+        } else {
+            // The section lexer reports a comment that is never closed
+            rest.strip_prefix("/*")
+                .map(|comment| block_comment_len(comment).map_or(rest.len(), |len| len + 2))
+        };
+        after_space = skipped.is_none() && c.is_whitespace();
+        i += skipped.unwrap_or(c.len_utf8());
+    }
+    lex.bump(i - lex.slice().len());
+    lex.slice().to_string()
+}
+
+/// The doc comments written directly above `offset`, a line for each comment
+pub fn doc_before(contents: &str, docs: &[DocComment], offset: usize) -> Option<String> {
+    let mut end = offset;
+    let mut lines = Vec::new();
+    for doc in docs.iter().rev().skip_while(|d| d.span.end > offset) {
+        if !contents[doc.span.end..end].trim().is_empty() {
+            break;
+        }
+        lines.push(doc.text.as_str());
+        end = doc.span.start;
+    }
+    lines.reverse();
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
@@ -0,0 +1,126 @@
+use std::fmt;
+
+use lalrpop_util::lalrpop_mod;
+use rust_decimal::Decimal;
+
+use logos::{self, Logos};
+
+use crate::{
+    LexicalError, ParseData,
+    common::{self, DataParser, LexExtras, ParsedEntries, Patchable},
+    expression::Constants,
+    printer::{PrintScript, ScriptWriter, quoted},
+};
+
+/// A named value that numbers in other sections can be worked out from
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConstantData {
+    pub id: String,
+    /// Worked out once the constants before it are known
+    pub value: Decimal,
+    /// The expression the value was written as, `None` for a plain number
+    pub expression: Option<String>,
+    /// Doc comments written above where the entry is defined or replaced
+    pub doc: Option<String>,
+}
+
+impl ParseData {
+    /// The value of every constant by name, for parsing files loaded after this
+    pub fn constant_values(&self) -> Constants {
+        self.constants
+            .iter()
+            .map(|c| (c.id.clone(), c.value))
+            .collect()
+    }
+}
+
+#[derive(Logos, Clone, Debug, PartialEq)]
+#[logos(skip r"[\s\t\f]+", error = LexicalError, extras = LexExtras)]
+#[logos(skip r"//[^\n\r]*")]
+#[logos(skip(r"///([^/\n\r][^\n\r]*)?", common::doc_comment))]
+#[logos(skip(r"/\*", common::block_comment))]
+pub enum ConstantToken {
+    #[token("replace")]
+    Replace,
+    #[token("patch")]
+    Patch,
+    #[token("delete")]
+    Delete,
+
+    #[token("=")]
+    Equal,
+    #[regex(r#""([^"\\]|\\.)*""#, common::string)]
+    #[regex(r#""""([^"]|"[^"]|""[^"])*""""#, common::string)]
+    String(String),
+    #[regex(
+        r"-?[0-9][0-9_]*(\.[0-9_]*)?([eE][+-]?[0-9]+)?",
+        common::decimal,
+        priority = 4
+    )]
+    DecimalNumber(Decimal),
+    /// Kept as written since it can use the constants defined before it
+    #[regex(r"\$[A-Za-z_][A-Za-z0-9_]*", |lex| common::expression_text(lex).map(str::to_string))]
+    #[regex(r"\$\(", |lex| common::expression_text(lex).map(str::to_string))]
+    Expression(String),
+}
+
+impl fmt::Display for ConstantToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Field {
+    /// The value along with the expression it is worked out from
+    Value(Decimal, Option<String>),
+}
+
+lalrpop_mod!(#[allow(clippy::ptr_arg)] pub constants);
+
+impl Patchable for ConstantData {
+    type Key = String;
+    type Field = Field;
+    fn key(&self) -> &Self::Key {
+        &self.id
+    }
+    fn set_key(&mut self, key: Self::Key) {
+        self.id = key;
+    }
+    fn apply(&mut self, field: Self::Field) {
+        match field {
+            Field::Value(value, expression) => {
+                self.value = value;
+                self.expression = expression;
+            }
+        }
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+    fn set_doc(&mut self, doc: Option<String>) {
+        self.doc = doc;
+    }
+}
+
+impl<'s> DataParser<'s> for ConstantData {
+    type Token = ConstantToken;
+    const KEYWORDS: &'static [&'static str] = &["replace", "patch", "delete"];
+    fn parse_tokens(
+        tokens: Vec<(usize, Self::Token, usize)>,
+        errors: &mut Vec<lalrpop_util::ErrorRecovery<usize, Self::Token, String>>,
+    ) -> ParsedEntries<Self, Self::Token> {
+        constants::ConstantsParser::new().parse(errors, tokens)
+    }
+}
+
+impl PrintScript for ConstantData {
+    const SECTION: &'static str = "#constants";
+    fn print(&self, w: &mut ScriptWriter) {
+        match &self.expression {
+            Some(expression) => w.line(format!("{} = {expression}", quoted(&self.id))),
+            None => w.line(format!("{} = {}", quoted(&self.id), self.value)),
+        }
+    }
+}
@@ -1,31 +1,54 @@
 use std::fmt;
 
 use lalrpop_util::lalrpop_mod;
-use logos::Logos;
+use logos::{Lexer, Logos};
 
 use crate::{
     LexicalError,
-    common::{DataParser, PlanetFilter},
+    common::{self, DataParser, LexExtras, NumberToken, ParsedEntries, Patchable, PlanetFilter},
+    printer::{PrintScript, ScriptWriter, decimal, quoted},
+    schema::FieldSchema,
 };
 
 use rust_decimal::prelude::*;
 
 #[derive(Logos, Clone, Debug, PartialEq)]
-#[logos(skip r"[\s\t\f]+", error = LexicalError)]
+#[logos(skip r"[\s\t\f]+", error = LexicalError, extras = LexExtras)]
 #[logos(skip r"//[^\n\r]*")]
+#[logos(skip(r"///([^/\n\r][^\n\r]*)?", common::doc_comment))]
+#[logos(skip(r"/\*", common::block_comment))]
 pub enum DesignationToken {
+    #[token("replace")]
+    Replace,
+    #[token("patch")]
+    Patch,
+    #[token("delete")]
+    Delete,
+    #[token("template")]
+    Template,
+    #[token("extends")]
+    Extends,
+
     #[token("true")]
     True,
     #[token("false")]
     False,
-    #[regex(r#""[^"]*""#, |lex| lex.slice().trim_matches('"').to_string())]
+    #[regex(r#""([^"\\]|\\.)*""#, common::string)]
+    #[regex(r#""""([^"]|"[^"]|""[^"])*""""#, common::string)]
     String(String),
 
-    #[regex(r"(\d+)", |lex|lex.slice().parse::<u8>().expect("parsing u8"), priority = 5)]
+    #[regex(r"[0-9][0-9_]*([eE]\+?[0-9]+)?", common::integer::<u8>, priority = 5)]
     Number(u8),
 
-    #[regex(r"(-?\d+\.\d*)", |lex| Decimal::from_str(lex.slice()).expect("parsed_decimal"), priority = 4)]
+    #[regex(
+        r"-?[0-9][0-9_]*(\.[0-9_]*)?([eE][+-]?[0-9]+)?",
+        common::decimal,
+        priority = 4
+    )]
     DecimalNumber(Decimal),
+    #[regex(r"\$[A-Za-z_][A-Za-z0-9_]*", common::expression)]
+    #[regex(r"\$\(", common::expression)]
+    Expression,
 
     #[token("=")]
     Equal,
@@ -100,10 +123,21 @@ impl fmt::Display for DesignationToken {
     }
 }
 
-lalrpop_mod!(pub designation);
+impl<'s> NumberToken<'s> for DesignationToken {
+    fn number(lex: &mut Lexer<'s, Self>, value: Decimal) -> Self {
+        if value.scale() == 0 {
+            DesignationToken::Number(common::fit(lex, value))
+        } else {
+            DesignationToken::DecimalNumber(value)
+        }
+    }
+}
+
+lalrpop_mod!(#[allow(clippy::ptr_arg)] pub designation);
 
 ///Parsed serialisation data to send to the game
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DesignationData {
     pub id: String,
 
@@ -119,11 +153,14 @@ pub struct DesignationData {
     pub planet_filters: Vec<PlanetFilter>,
 
     pub private_buildings: PrivateBuildings,
+    /// Doc comments written above where the entry is defined or replaced
+    pub doc: Option<String>,
 }
 
 /// Designations can have population limits the idea behind this is have
 /// low output planets that can largely be in a finished state to avoid micromanaging
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum BuildingLimit {
     #[default]
     Unlimited,
@@ -133,7 +170,8 @@ pub enum BuildingLimit {
 /// If housing should be required for this planet,
 /// with population limited planets requiring the building of housing
 /// doesn't feel as interesting for gameplay reasons.
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Housing {
     #[default]
     Managed,
@@ -143,7 +181,8 @@ pub enum Housing {
 /// Private buildings are a way companies expand their revenue
 /// independantly of the player, this is to stop the players plans
 /// getting disrupted at the early stages of the buildings
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PrivateBuildings {
     #[default]
     None,
@@ -154,12 +193,14 @@ pub enum PrivateBuildings {
 /// This is a way to simulate urban population decline
 /// This is there to stop perputual population growth along
 /// with giving you a reason to no hyper develop every planet
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PopulationImpact {
     pub growth: Decimal,
     pub min_population: u8,
 }
 
+#[derive(Clone, Debug)]
 pub enum Field {
     Name(String),
     Description(String),
@@ -171,12 +212,125 @@ pub enum Field {
     TechRequired(String),
 }
 
+impl Patchable for DesignationData {
+    type Key = String;
+    type Field = Field;
+    fn key(&self) -> &Self::Key {
+        &self.id
+    }
+    fn set_key(&mut self, key: Self::Key) {
+        self.id = key;
+    }
+    fn apply(&mut self, field: Self::Field) {
+        match field {
+            Field::Name(n) => self.name = n,
+            Field::Description(d) => self.description = d,
+            Field::Housing(h) => self.housing = h,
+            Field::BuildingLimit(b) => self.building_limit = b,
+            Field::PopulationImpact(p) => self.population_impact = p,
+            Field::PlanetFilters(p) => self.planet_filters = p,
+            Field::PrivateBuildings(p) => self.private_buildings = p,
+            Field::TechRequired(t) => self.tech_required = Some(t),
+        }
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+    fn set_doc(&mut self, doc: Option<String>) {
+        self.doc = doc;
+    }
+}
+
 impl<'s> DataParser<'s> for DesignationData {
     type Token = DesignationToken;
+    const KEYWORDS: &'static [&'static str] = &[
+        "replace",
+        "patch",
+        "delete",
+        "template",
+        "extends",
+        "true",
+        "false",
+        "orbital",
+        "all_orbitals",
+        "all_planets",
+        "build_planets",
+        "name",
+        "description",
+        "building_limit",
+        "Unlimited",
+        "limited",
+        "population_impact",
+        "tech_required",
+        "growth",
+        "min_population",
+        "housing",
+        "managend",
+        "unmanaged",
+        "private_buildings",
+        "none",
+        "always",
+    ];
+    const SCHEMA: &'static [FieldSchema<Self>] = &[
+        FieldSchema::required("name"),
+        FieldSchema::recommended("description"),
+    ];
 
     fn parse_tokens(
         tokens: Vec<(usize, Self::Token, usize)>,
-    ) -> Result<Vec<Self>, lalrpop_util::ParseError<usize, Self::Token, String>> {
-        designation::DesignationDataParser::new().parse(tokens)
+        errors: &mut Vec<lalrpop_util::ErrorRecovery<usize, Self::Token, String>>,
+    ) -> ParsedEntries<Self, Self::Token> {
+        designation::DesignationDataParser::new().parse(errors, tokens)
+    }
+}
+
+impl PrintScript for DesignationData {
+    const SECTION: &'static str = "#designations";
+    fn print(&self, w: &mut ScriptWriter) {
+        let d = DesignationData::default();
+        w.line(quoted(&self.id));
+        w.indented(|w| {
+            w.field_unless_default("name", quoted(&self.name), self.name == d.name);
+            w.field_unless_default(
+                "description",
+                quoted(&self.description),
+                self.description == d.description,
+            );
+            let building_limit = match self.building_limit {
+                BuildingLimit::Unlimited => "Unlimited".to_string(),
+                BuildingLimit::Limited(n) => format!("limited ( {n} )"),
+            };
+            w.field_unless_default(
+                "building_limit",
+                building_limit,
+                self.building_limit == d.building_limit,
+            );
+            let housing = match self.housing {
+                Housing::Managed => "managend",
+                Housing::Unmanaged => "unmanaged",
+            };
+            w.field_unless_default("housing", housing, self.housing == d.housing);
+            if self.population_impact != d.population_impact {
+                w.line(format_args!(
+                    "population_impact {{ growth = {} min_population = {} }}",
+                    decimal(self.population_impact.growth),
+                    self.population_impact.min_population
+                ));
+            }
+            let private_buildings = match self.private_buildings {
+                PrivateBuildings::None => "none".to_string(),
+                PrivateBuildings::Always => "always".to_string(),
+                PrivateBuildings::MinPopulation(n) => format!("min_population ( {n} )"),
+            };
+            w.field_unless_default(
+                "private_buildings",
+                private_buildings,
+                self.private_buildings == d.private_buildings,
+            );
+            if let Some(t) = &self.tech_required {
+                w.field("tech_required", quoted(t));
+            }
+            // planet_filters has no syntax in the designations grammar yet
+        });
     }
 }
@@ -0,0 +1,431 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use miette::Diagnostic;
+use rust_decimal::{Decimal, RoundingStrategy};
+use thiserror::Error;
+
+use crate::{
+    ParseData,
+    building::{BuildingData, CustomGood},
+    goods::GoodData,
+};
+
+/// How many times the solver refines the building mix before giving up
+const MAX_ROUNDS: usize = 1000;
+/// Decimal places building counts are rounded to while solving
+const PRECISION: u32 = 6;
+
+/// Which buildings make and use a good, every amount is per tick
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GoodFlow<'d> {
+    pub good: &'d str,
+    pub producers: Vec<(&'d str, Decimal)>,
+    /// Both what buildings consume and their upkeep
+    pub consumers: Vec<(&'d str, Decimal)>,
+}
+
+/// The buildings needed to sustain a target output
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Plan<'d> {
+    /// How many of each building, which can be fractional
+    pub buildings: BTreeMap<&'d str, Decimal>,
+    /// Goods no building produces that have to be bought every tick
+    pub imports: BTreeMap<&'d str, Decimal>,
+    /// Goods made beyond what the targets and the buildings need
+    pub surplus: BTreeMap<&'d str, Decimal>,
+}
+
+/// A target output that could not be planned for
+#[derive(Error, Debug, Diagnostic, Clone, PartialEq)]
+pub enum SolveError {
+    #[error("There is no good `{0}`")]
+    #[diagnostic(code(pronytic::economy::unknown_good))]
+    UnknownGood(String),
+    #[error("The buildings making {} need more of them than they make", .0.join(", "))]
+    #[diagnostic(
+        code(pronytic::economy::unsustainable),
+        help("These goods feed back into their own production faster than they are made")
+    )]
+    Unsustainable(Vec<String>),
+}
+
+/// Buildings and goods looked at together, every amount is per tick
+/// and build costs are left out
+#[derive(Clone, Copy, Debug)]
+pub struct Economy<'d> {
+    pub buildings: &'d [BuildingData],
+    pub goods: &'d [GoodData],
+}
+
+impl ParseData {
+    /// The goods flowing between all buildings
+    pub fn economy(&self) -> Economy<'_> {
+        Economy {
+            buildings: &self.building_data,
+            goods: &self.goods_data,
+        }
+    }
+}
+
+impl<'d> Economy<'d> {
+    /// Every good that is defined, produced or used, ordered by id
+    pub fn flows(&self) -> Vec<GoodFlow<'d>> {
+        let mut flows: BTreeMap<&str, GoodFlow> = self
+            .goods
+            .iter()
+            .map(|g| {
+                let flow = GoodFlow {
+                    good: &g.id,
+                    ..Default::default()
+                };
+                (g.id.as_str(), flow)
+            })
+            .collect();
+        for building in self.buildings {
+            for (good, amount) in outputs(building) {
+                let flow = flows.entry(good).or_insert_with(|| GoodFlow {
+                    good,
+                    ..Default::default()
+                });
+                flow.producers.push((&building.id, amount));
+            }
+            for (good, amount) in inputs(building) {
+                let flow = flows.entry(good).or_insert_with(|| GoodFlow {
+                    good,
+                    ..Default::default()
+                });
+                flow.consumers.push((&building.id, amount));
+            }
+        }
+        flows.into_values().collect()
+    }
+
+    /// Goods that buildings use but no building produces
+    pub fn unproduced(&self) -> Vec<&'d str> {
+        self.flows()
+            .into_iter()
+            .filter(|f| f.producers.is_empty() && !f.consumers.is_empty())
+            .map(|f| f.good)
+            .collect()
+    }
+
+    /// Goods that buildings produce but no building uses
+    pub fn unconsumed(&self) -> Vec<&'d str> {
+        self.flows()
+            .into_iter()
+            .filter(|f| !f.producers.is_empty() && f.consumers.is_empty())
+            .map(|f| f.good)
+            .collect()
+    }
+
+    /// What a building's output sells for less what its inputs cost to buy,
+    /// goods that are not defined are worth nothing
+    pub fn net_value(&self, building: &BuildingData) -> Decimal {
+        let price = |id: &str, value: fn(&GoodData) -> Decimal| {
+            self.goods
+                .iter()
+                .find(|g| g.id == id)
+                .map(value)
+                .unwrap_or_default()
+        };
+        let earned: Decimal = outputs(building)
+            .map(|(id, amount)| amount * price(id, |g| g.sell_value))
+            .sum();
+        let spent: Decimal = inputs(building)
+            .map(|(id, amount)| amount * price(id, |g| g.buy_value))
+            .sum();
+        earned - spent
+    }
+
+    /// [`Economy::net_value`] split between the building's workers,
+    /// `None` for buildings without workers
+    pub fn value_per_worker(&self, building: &BuildingData) -> Option<Decimal> {
+        if building.workers == 0 {
+            return None;
+        }
+        Some(self.net_value(building) / Decimal::from(building.workers))
+    }
+
+    /// A table of each building's net value and value per worker, best first
+    pub fn value_table(&self) -> String {
+        let mut values: Vec<_> = self
+            .buildings
+            .iter()
+            .map(|b| (b, self.net_value(b), self.value_per_worker(b)))
+            .collect();
+        values.sort_by(|a, b| b.2.cmp(&a.2).then(b.1.cmp(&a.1)));
+        let rows = values
+            .into_iter()
+            .map(|(b, net, per_worker)| {
+                let per_worker = per_worker.map(|v| format!("{:.2}", v.round_dp(2)));
+                vec![
+                    b.id.clone(),
+                    b.workers.to_string(),
+                    format!("{:.2}", net.round_dp(2)),
+                    per_worker.unwrap_or_else(|| "-".to_string()),
+                ]
+            })
+            .collect();
+        table(&["building", "workers", "net value", "per worker"], rows)
+    }
+
+    /// Finds how many of each building are needed to make `targets` every tick,
+    /// along with everything those buildings need in turn.
+    /// Each good is made by the building that produces the most of it,
+    /// the first one loaded when several produce the same amount
+    pub fn solve(&self, targets: &[(&str, Decimal)]) -> Result<Plan<'d>, SolveError> {
+        let known: BTreeSet<&str> = self.flows().iter().map(|f| f.good).collect();
+        for (good, _) in targets {
+            if !known.contains(good) {
+                return Err(SolveError::UnknownGood(good.to_string()));
+            }
+        }
+
+        let mut producer: BTreeMap<&str, (&BuildingData, Decimal)> = BTreeMap::new();
+        for building in self.buildings {
+            for (good, amount) in outputs(building).filter(|(_, a)| *a > Decimal::ZERO) {
+                match producer.get(good) {
+                    Some((_, best)) if *best >= amount => {}
+                    _ => {
+                        producer.insert(good, (building, amount));
+                    }
+                }
+            }
+        }
+
+        let mut counts: BTreeMap<&'d str, Decimal> = BTreeMap::new();
+        let mut needed = BTreeMap::new();
+        for _ in 0..MAX_ROUNDS {
+            // Amounts too large for a decimal come from goods feeding back into themselves
+            let Some(now_needed) = self.needed(targets, &counts) else {
+                break;
+            };
+            needed = now_needed;
+            let Some(next) = building_counts(&needed, &producer) else {
+                break;
+            };
+            if next == counts {
+                return Ok(self.plan(counts, needed));
+            }
+            counts = next;
+        }
+
+        let mut growing: Vec<String> = needed
+            .keys()
+            .filter(|g| producer.contains_key(*g))
+            .map(|g| g.to_string())
+            .collect();
+        growing.sort();
+        Err(SolveError::Unsustainable(growing))
+    }
+
+    /// Works out what has to be bought and what is left over once the counts are settled
+    fn plan(
+        &self,
+        buildings: BTreeMap<&'d str, Decimal>,
+        needed: BTreeMap<&'d str, Decimal>,
+    ) -> Plan<'d> {
+        let mut balance: BTreeMap<&'d str, Decimal> = needed
+            .into_iter()
+            .map(|(good, amount)| (good, -amount))
+            .collect();
+        for building in self.buildings {
+            let Some(count) = buildings.get(building.id.as_str()) else {
+                continue;
+            };
+            for (good, amount) in outputs(building) {
+                *balance.entry(good).or_default() += amount * count;
+            }
+        }
+        let mut plan = Plan {
+            buildings,
+            ..Default::default()
+        };
+        for (good, amount) in balance {
+            // Rounding the counts up leaves tiny amounts over
+            let amount = amount.round_dp(PRECISION - 2);
+            if amount < Decimal::ZERO {
+                plan.imports.insert(good, -amount);
+            } else if amount > Decimal::ZERO {
+                plan.surplus.insert(good, amount);
+            }
+        }
+        plan
+    }
+
+    /// What the targets and the inputs of `counts` buildings add up to, by good.
+    /// `None` when an amount is too large for a decimal
+    fn needed(
+        &self,
+        targets: &[(&str, Decimal)],
+        counts: &BTreeMap<&'d str, Decimal>,
+    ) -> Option<BTreeMap<&'d str, Decimal>> {
+        let mut needed: BTreeMap<&'d str, Decimal> = BTreeMap::new();
+        for flow in self.flows() {
+            if let Some((_, amount)) = targets.iter().find(|(g, _)| *g == flow.good) {
+                let total = needed.entry(flow.good).or_default();
+                *total = total.checked_add(*amount)?;
+            }
+        }
+        for building in self.buildings {
+            let Some(count) = counts.get(building.id.as_str()) else {
+                continue;
+            };
+            for (good, amount) in inputs(building) {
+                let total = needed.entry(good).or_default();
+                *total = total.checked_add(amount.checked_mul(*count)?)?;
+            }
+        }
+        Some(needed)
+    }
+}
+
+/// How many of each building make the `needed` goods,
+/// `None` when a count is too large for a decimal
+fn building_counts<'d>(
+    needed: &BTreeMap<&'d str, Decimal>,
+    producer: &BTreeMap<&str, (&'d BuildingData, Decimal)>,
+) -> Option<BTreeMap<&'d str, Decimal>> {
+    let mut counts: BTreeMap<&'d str, Decimal> = BTreeMap::new();
+    for (good, amount) in needed {
+        if let Some((building, made)) = producer.get(good) {
+            let count = amount
+                .checked_div(*made)?
+                .round_dp_with_strategy(PRECISION, RoundingStrategy::AwayFromZero);
+            let current = counts.entry(building.id.as_str()).or_default();
+            *current = (*current).max(count);
+        }
+    }
+    Some(counts)
+}
+
+impl Plan<'_> {
+    /// The buildings then the goods bought and left over, as aligned tables
+    pub fn to_table(&self) -> String {
+        let row = |(id, amount): (&&str, &Decimal)| {
+            vec![id.to_string(), format!("{:.2}", amount.round_dp(2))]
+        };
+        let mut text = table(
+            &["building", "count"],
+            self.buildings.iter().map(row).collect(),
+        );
+        if !self.imports.is_empty() {
+            text.push('\n');
+            text.push_str(&table(
+                &["import", "per tick"],
+                self.imports.iter().map(row).collect(),
+            ));
+        }
+        if !self.surplus.is_empty() {
+            text.push('\n');
+            text.push_str(&table(
+                &["surplus", "per tick"],
+                self.surplus.iter().map(row).collect(),
+            ));
+        }
+        text
+    }
+}
+
+/// Goods a building produces along with how many
+fn outputs(building: &BuildingData) -> impl Iterator<Item = (&str, Decimal)> {
+    goods(&building.produces)
+}
+
+/// Goods a building consumes or needs for upkeep along with how many
+fn inputs(building: &BuildingData) -> impl Iterator<Item = (&str, Decimal)> {
+    goods(&building.consumes).chain(goods(&building.upkeep))
+}
+
+fn goods(goods: &[CustomGood]) -> impl Iterator<Item = (&str, Decimal)> {
+    goods.iter().map(|g| (g.id.as_str(), g.amount))
+}
+
+/// Left aligned columns with the headers underlined
+fn table(headers: &[&str], rows: Vec<Vec<String>>) -> String {
+    let widths: Vec<usize> = headers
+        .iter()
+        .enumerate()
+        .map(|(i, h)| {
+            rows.iter()
+                .map(|r| r[i].len())
+                .chain([h.len()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    let line = |cells: Vec<String>| {
+        let cells: Vec<_> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(c, w)| format!("{c:<w$}"))
+            .collect();
+        format!("{}\n", cells.join("  ").trim_end())
+    };
+    let mut text = line(headers.iter().map(|h| h.to_string()).collect());
+    text.push_str(&line(widths.iter().map(|w| "-".repeat(*w)).collect()));
+    for row in rows {
+        text.push_str(&line(row));
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn good(id: &str, amount: Decimal) -> CustomGood {
+        CustomGood {
+            id: id.to_string(),
+            amount,
+        }
+    }
+
+    fn building(id: &str, consumes: Vec<CustomGood>, produces: Vec<CustomGood>) -> BuildingData {
+        BuildingData {
+            id: id.to_string(),
+            consumes,
+            produces,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn solves_chain() {
+        let buildings = [
+            building("mine", vec![], vec![good("ore", dec!(2))]),
+            building(
+                "smelter",
+                vec![good("ore", dec!(4))],
+                vec![good("iron", dec!(1))],
+            ),
+        ];
+        let economy = Economy {
+            buildings: &buildings,
+            goods: &[],
+        };
+        let plan = economy.solve(&[("iron", dec!(3))]).unwrap();
+        assert_eq!(plan.buildings["smelter"], dec!(3));
+        assert_eq!(plan.buildings["mine"], dec!(6));
+        assert!(plan.imports.is_empty());
+    }
+
+    #[test]
+    fn self_amplifying_recipe_is_unsustainable() {
+        let buildings = [building(
+            "loop",
+            vec![good("a", dec!(2))],
+            vec![good("a", dec!(1))],
+        )];
+        let economy = Economy {
+            buildings: &buildings,
+            goods: &[],
+        };
+        assert_eq!(
+            economy.solve(&[("a", dec!(1))]),
+            Err(SolveError::Unsustainable(vec!["a".to_string()]))
+        );
+    }
+}
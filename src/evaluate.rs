@@ -0,0 +1,395 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use miette::Diagnostic;
+use rand::Rng;
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::{
+    common::Temperature,
+    planet_types::{Action, Branch, Condition, PlanetTypeData, Value},
+};
+
+/// What is known about a planet when its planet type's rules are run
+#[derive(Clone, Debug, Default)]
+pub struct PlanetContext {
+    pub star_type: String,
+    pub oxygen_level: Decimal,
+    pub temperature: Temperature,
+    pub water_level: Decimal,
+    pub magnetosphere: Decimal,
+    pub atmosphere: Decimal,
+    /// How much of each good the planet has by good id, goods left out have none
+    pub goods_abundance: HashMap<String, Decimal>,
+    /// Strings stored by earlier rules, by key
+    pub stored: HashMap<String, String>,
+}
+
+/// What running a planet type's rules decided
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Outcome {
+    /// The asset last set, if any rule set one
+    pub asset: Option<String>,
+    /// The planet type last set, if any rule set one
+    pub planet_type: Option<String>,
+    /// The stored strings of the context along with every string the rules stored
+    pub stored: HashMap<String, String>,
+}
+
+/// A rule that could not be run against the planet it was given
+#[derive(Error, Debug, Diagnostic, Clone, PartialEq)]
+pub enum EvaluationError {
+    #[error("Nothing is stored under `{0}`")]
+    #[diagnostic(
+        code(pronytic::evaluate::unknown_stored),
+        help("Store a value under this key before it is read")
+    )]
+    UnknownStored(String),
+    #[error("Can't compare the text `{text}` with the number {number}")]
+    #[diagnostic(code(pronytic::evaluate::mismatched_values))]
+    MismatchedValues { text: String, number: Decimal },
+}
+
+/// A value once it has been looked up in the context
+#[derive(Clone, Debug, PartialEq)]
+enum Resolved<'c> {
+    Number(Decimal),
+    Text(&'c str),
+}
+
+impl PlanetTypeData {
+    /// Runs every setup block in order
+    pub fn setup(
+        &self,
+        context: &PlanetContext,
+        rng: &mut impl Rng,
+    ) -> Result<Outcome, EvaluationError> {
+        evaluate(&self.setup_conditions, context, rng)
+    }
+
+    /// Runs every branch of the terraform block in order
+    pub fn terraform(
+        &self,
+        context: &PlanetContext,
+        rng: &mut impl Rng,
+    ) -> Result<Outcome, EvaluationError> {
+        evaluate(&self.terraform_conditions, context, rng)
+    }
+}
+
+/// Runs branches one after another, each sees what the ones before it stored.
+/// When several actions set the asset or planet type the last one wins
+pub fn evaluate(
+    branches: &[Branch],
+    context: &PlanetContext,
+    rng: &mut impl Rng,
+) -> Result<Outcome, EvaluationError> {
+    let mut outcome = Outcome {
+        stored: context.stored.clone(),
+        ..Default::default()
+    };
+    for branch in branches {
+        run_branch(branch, context, &mut outcome, rng)?;
+    }
+    Ok(outcome)
+}
+
+/// Runs the actions of the first arm whose condition holds,
+/// or the else actions when none of them do
+fn run_branch(
+    branch: &Branch,
+    context: &PlanetContext,
+    outcome: &mut Outcome,
+    rng: &mut impl Rng,
+) -> Result<(), EvaluationError> {
+    let mut actions = &branch.else_actions;
+    for arm in &branch.if_conditions {
+        if holds(&arm.condition, context, outcome)? {
+            actions = &arm.actions;
+            break;
+        }
+    }
+    for action in actions {
+        run_action(action, context, outcome, rng)?;
+    }
+    Ok(())
+}
+
+/// `&` and `|` stop at the first condition that decides them,
+/// so later conditions are not looked up
+fn holds(
+    condition: &Condition,
+    context: &PlanetContext,
+    outcome: &Outcome,
+) -> Result<bool, EvaluationError> {
+    let (first, second, allowed): (_, _, &[Ordering]) = match condition {
+        Condition::Eq(a, b) => (a, b, &[Ordering::Equal]),
+        Condition::Ne(a, b) => (a, b, &[Ordering::Less, Ordering::Greater]),
+        Condition::Gt(a, b) => (a, b, &[Ordering::Greater]),
+        Condition::Ge(a, b) => (a, b, &[Ordering::Greater, Ordering::Equal]),
+        Condition::Lt(a, b) => (a, b, &[Ordering::Less]),
+        Condition::Le(a, b) => (a, b, &[Ordering::Less, Ordering::Equal]),
+        Condition::In(value, values) => {
+            let value = resolve(value, context, outcome)?;
+            for candidate in values {
+                if compare(&value, &resolve(candidate, context, outcome)?)? == Ordering::Equal {
+                    return Ok(true);
+                }
+            }
+            return Ok(false);
+        }
+        Condition::Not(c) => return Ok(!holds(c, context, outcome)?),
+        Condition::And(conditions) => {
+            for c in conditions {
+                if !holds(c, context, outcome)? {
+                    return Ok(false);
+                }
+            }
+            return Ok(true);
+        }
+        Condition::Or(conditions) => {
+            for c in conditions {
+                if holds(c, context, outcome)? {
+                    return Ok(true);
+                }
+            }
+            return Ok(false);
+        }
+    };
+    let ordering = compare(
+        &resolve(first, context, outcome)?,
+        &resolve(second, context, outcome)?,
+    )?;
+    Ok(allowed.contains(&ordering))
+}
+
+fn compare(first: &Resolved, second: &Resolved) -> Result<Ordering, EvaluationError> {
+    match (first, second) {
+        (Resolved::Number(a), Resolved::Number(b)) => Ok(a.cmp(b)),
+        (Resolved::Text(a), Resolved::Text(b)) => Ok(a.cmp(b)),
+        (Resolved::Text(text), Resolved::Number(number))
+        | (Resolved::Number(number), Resolved::Text(text)) => {
+            Err(EvaluationError::MismatchedValues {
+                text: text.to_string(),
+                number: *number,
+            })
+        }
+    }
+}
+
+fn resolve<'c>(
+    value: &'c Value,
+    context: &'c PlanetContext,
+    outcome: &'c Outcome,
+) -> Result<Resolved<'c>, EvaluationError> {
+    Ok(match value {
+        Value::Decimal(d) => Resolved::Number(*d),
+        Value::OxygenLevel => Resolved::Number(context.oxygen_level),
+        Value::TemperatureCelsius => Resolved::Number(context.temperature.celsius()),
+        Value::TemperatureKelvin => Resolved::Number(context.temperature.kelvin()),
+        Value::WaterLevel => Resolved::Number(context.water_level),
+        Value::Magnetosphere => Resolved::Number(context.magnetosphere),
+        Value::Atmosphere => Resolved::Number(context.atmosphere),
+        Value::GoodsAbundance(id) => {
+            Resolved::Number(context.goods_abundance.get(id).copied().unwrap_or_default())
+        }
+        Value::StarType => Resolved::Text(&context.star_type),
+        Value::String(s) => Resolved::Text(s),
+        Value::StringLookup(key) => Resolved::Text(
+            outcome
+                .stored
+                .get(key)
+                .ok_or_else(|| EvaluationError::UnknownStored(key.clone()))?,
+        ),
+    })
+}
+
+fn run_action(
+    action: &Action,
+    context: &PlanetContext,
+    outcome: &mut Outcome,
+    rng: &mut impl Rng,
+) -> Result<(), EvaluationError> {
+    match action {
+        Action::None => {}
+        Action::SetAsset(asset) => outcome.asset = Some(asset.clone()),
+        Action::SetPlanetType(planet_type) => outcome.planet_type = Some(planet_type.clone()),
+        Action::Branch(branch) => run_branch(branch, context, outcome, rng)?,
+        Action::SetStored(key, value) => {
+            outcome.stored.insert(key.clone(), value.clone());
+        }
+        Action::SetStoredRandom(key, values) => {
+            if !values.is_empty() {
+                let value = &values[rng.random_range(0..values.len())];
+                outcome.stored.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::StdRng};
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::planet_types::IfCondition;
+
+    fn planet() -> PlanetContext {
+        PlanetContext {
+            star_type: "G".to_string(),
+            oxygen_level: dec!(0.2),
+            temperature: Temperature::from_celsius(dec!(15)),
+            water_level: dec!(0.7),
+            goods_abundance: HashMap::from([("ore".to_string(), dec!(3))]),
+            ..Default::default()
+        }
+    }
+
+    fn check(condition: Condition) -> Result<bool, EvaluationError> {
+        holds(&condition, &planet(), &Outcome::default())
+    }
+
+    fn number(value: Decimal) -> Value {
+        Value::Decimal(value)
+    }
+
+    fn text(value: &str) -> Value {
+        Value::String(value.to_string())
+    }
+
+    /// A branch that sets the asset to the first arm whose condition holds
+    fn pick(arms: Vec<(Condition, &str)>, otherwise: &str) -> Branch {
+        Branch {
+            if_conditions: arms
+                .into_iter()
+                .map(|(condition, asset)| IfCondition {
+                    condition,
+                    actions: vec![Action::SetAsset(asset.to_string())],
+                })
+                .collect(),
+            else_actions: vec![Action::SetAsset(otherwise.to_string())],
+        }
+    }
+
+    #[test]
+    fn comparisons_at_boundary() {
+        let oxygen = || Value::OxygenLevel;
+        let at = || number(dec!(0.2));
+        assert!(check(Condition::Eq(oxygen(), at())).unwrap());
+        assert!(!check(Condition::Ne(oxygen(), at())).unwrap());
+        assert!(!check(Condition::Gt(oxygen(), at())).unwrap());
+        assert!(check(Condition::Ge(oxygen(), at())).unwrap());
+        assert!(!check(Condition::Lt(oxygen(), at())).unwrap());
+        assert!(check(Condition::Le(oxygen(), at())).unwrap());
+        assert!(check(Condition::Gt(oxygen(), number(dec!(0.19)))).unwrap());
+        assert!(check(Condition::Lt(oxygen(), number(dec!(0.21)))).unwrap());
+    }
+
+    #[test]
+    fn temperature_in_either_scale() {
+        let celsius = Condition::Eq(Value::TemperatureCelsius, number(dec!(15)));
+        let kelvin = Condition::Eq(Value::TemperatureKelvin, number(dec!(288.15)));
+        assert!(check(celsius).unwrap());
+        assert!(check(kelvin).unwrap());
+        let below = Condition::Lt(Value::TemperatureKelvin, number(dec!(288.15)));
+        assert!(!check(below).unwrap());
+    }
+
+    #[test]
+    fn missing_goods_have_none() {
+        let ore = Condition::Eq(Value::GoodsAbundance("ore".to_string()), number(dec!(3)));
+        let coal = Condition::Eq(Value::GoodsAbundance("coal".to_string()), number(dec!(0)));
+        assert!(check(ore).unwrap());
+        assert!(check(coal).unwrap());
+    }
+
+    #[test]
+    fn in_matches_any_value() {
+        let star = |types: &[&str]| {
+            Condition::In(Value::StarType, types.iter().map(|t| text(t)).collect())
+        };
+        assert!(check(star(&["K", "G"])).unwrap());
+        assert!(!check(star(&["K", "M"])).unwrap());
+        assert!(!check(star(&[])).unwrap());
+    }
+
+    #[test]
+    fn not_and_or() {
+        let yes = || Condition::Eq(Value::StarType, text("G"));
+        let no = || Condition::Eq(Value::StarType, text("M"));
+        assert!(check(Condition::Not(Box::new(no()))).unwrap());
+        assert!(check(Condition::And(vec![yes(), yes()])).unwrap());
+        assert!(!check(Condition::And(vec![yes(), no()])).unwrap());
+        assert!(check(Condition::Or(vec![no(), yes()])).unwrap());
+        assert!(!check(Condition::Or(vec![no(), no()])).unwrap());
+    }
+
+    #[test]
+    fn and_or_stop_once_decided() {
+        let unset = || Condition::Eq(Value::StringLookup("biome".to_string()), text("ice"));
+        let yes = Condition::Eq(Value::StarType, text("G"));
+        let no = Condition::Eq(Value::StarType, text("M"));
+        assert!(check(Condition::Or(vec![yes, unset()])).unwrap());
+        assert!(!check(Condition::And(vec![no, unset()])).unwrap());
+        assert_eq!(
+            check(unset()),
+            Err(EvaluationError::UnknownStored("biome".to_string()))
+        );
+    }
+
+    #[test]
+    fn text_and_number_do_not_compare() {
+        let mixed = Condition::Eq(Value::StarType, number(dec!(1)));
+        assert_eq!(
+            check(mixed),
+            Err(EvaluationError::MismatchedValues {
+                text: "G".to_string(),
+                number: dec!(1),
+            })
+        );
+    }
+
+    #[test]
+    fn first_arm_that_holds_is_taken() {
+        let water = |level| Condition::Ge(Value::WaterLevel, number(level));
+        let branch = pick(
+            vec![
+                (water(dec!(0.9)), "ocean"),
+                (water(dec!(0.7)), "wet"),
+                (water(dec!(0)), "dry"),
+            ],
+            "none",
+        );
+        let mut rng = StdRng::seed_from_u64(0);
+        let outcome = evaluate(&[branch], &planet(), &mut rng).unwrap();
+        assert_eq!(outcome.asset.as_deref(), Some("wet"));
+
+        let branch = pick(vec![(water(dec!(0.71)), "wet")], "dry");
+        let outcome = evaluate(&[branch], &planet(), &mut rng).unwrap();
+        assert_eq!(outcome.asset.as_deref(), Some("dry"));
+    }
+
+    #[test]
+    fn later_branches_see_what_earlier_ones_stored() {
+        let store = Branch {
+            if_conditions: vec![],
+            else_actions: vec![Action::SetStoredRandom(
+                "biome".to_string(),
+                vec!["ice".to_string()],
+            )],
+        };
+        let read = pick(
+            vec![(
+                Condition::Eq(Value::StringLookup("biome".to_string()), text("ice")),
+                "frozen",
+            )],
+            "thawed",
+        );
+        let mut rng = StdRng::seed_from_u64(0);
+        let outcome = evaluate(&[store, read], &planet(), &mut rng).unwrap();
+        assert_eq!(outcome.asset.as_deref(), Some("frozen"));
+        assert_eq!(outcome.stored["biome"], "ice");
+    }
+}
@@ -0,0 +1,274 @@
+use std::{collections::BTreeMap, fmt, ops::Range};
+
+use rust_decimal::Decimal;
+
+use crate::common::parse_number;
+
+/// Values by name, as defined in `#constants` sections
+pub type Constants = BTreeMap<String, Decimal>;
+
+/// Why an expression could not be worked out
+#[derive(Clone, Debug, PartialEq)]
+pub enum Problem {
+    DivisionByZero,
+    UnknownConstant(String),
+    /// Part of the expression is too large for a decimal
+    Overflow,
+    /// A value with a fraction where only whole numbers are read
+    NotWhole(Decimal),
+    /// Something out of place, described by the message
+    Invalid(String),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::DivisionByZero => write!(f, "Division by zero"),
+            Problem::UnknownConstant(name) => write!(f, "There is no constant `{name}`"),
+            Problem::Overflow => write!(f, "The result is too large for a decimal"),
+            Problem::NotWhole(value) => write!(f, "value {value} is not a whole number"),
+            Problem::Invalid(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+/// A problem along with the part of the expression it is about
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExpressionFailure {
+    pub span: Range<usize>,
+    pub problem: Problem,
+}
+
+impl ExpressionFailure {
+    fn new(span: Range<usize>, problem: Problem) -> Self {
+        ExpressionFailure { span, problem }
+    }
+
+    /// The same failure `by` bytes further into the text
+    pub fn shifted(self, by: usize) -> Self {
+        ExpressionFailure {
+            span: self.span.start + by..self.span.end + by,
+            ..self
+        }
+    }
+}
+
+/// Works out an expression as written in a script, either `$name` or `$( expression )`.
+/// Spans are relative to the `$`
+pub fn evaluate_written(
+    written: &str,
+    constants: &Constants,
+) -> Result<Decimal, ExpressionFailure> {
+    let text = written.strip_prefix('$').unwrap_or(written);
+    evaluate(text, constants).map_err(|f| f.shifted(written.len() - text.len()))
+}
+
+/// Works out `+`, `-`, `*` and `/` between numbers and constants, along with brackets.
+/// Multiplying and dividing happen before adding and subtracting,
+/// otherwise it goes from left to right
+pub fn evaluate(text: &str, constants: &Constants) -> Result<Decimal, ExpressionFailure> {
+    let mut reader = Reader {
+        text,
+        position: 0,
+        constants,
+    };
+    let value = reader.sum()?;
+    match reader.peek() {
+        None => Ok(value),
+        Some(c) => Err(reader.unexpected(c)),
+    }
+}
+
+struct Reader<'e> {
+    text: &'e str,
+    position: usize,
+    constants: &'e Constants,
+}
+
+impl Reader<'_> {
+    /// The next character after any whitespace, which is skipped
+    fn peek(&mut self) -> Option<char> {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+        self.text[self.position..].chars().next()
+    }
+
+    fn unexpected(&self, c: char) -> ExpressionFailure {
+        let span = self.position..self.position + c.len_utf8();
+        ExpressionFailure::new(span, Problem::Invalid(format!("Unexpected `{c}`")))
+    }
+
+    fn sum(&mut self) -> Result<Decimal, ExpressionFailure> {
+        self.peek();
+        let start = self.position;
+        let mut value = self.product()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.position += 1;
+            let rhs = self.product()?;
+            let result = match op {
+                '+' => value.checked_add(rhs),
+                _ => value.checked_sub(rhs),
+            };
+            value = result
+                .ok_or_else(|| ExpressionFailure::new(start..self.position, Problem::Overflow))?;
+        }
+        Ok(value)
+    }
+
+    fn product(&mut self) -> Result<Decimal, ExpressionFailure> {
+        self.peek();
+        let start = self.position;
+        let mut value = self.negation()?;
+        while let Some(op @ ('*' | '/')) = self.peek() {
+            self.position += 1;
+            self.peek();
+            let rhs_start = self.position;
+            let rhs = self.negation()?;
+            let result = match op {
+                '*' => value.checked_mul(rhs),
+                _ if rhs.is_zero() => {
+                    let span = rhs_start..self.position;
+                    return Err(ExpressionFailure::new(span, Problem::DivisionByZero));
+                }
+                _ => value.checked_div(rhs),
+            };
+            value = result
+                .ok_or_else(|| ExpressionFailure::new(start..self.position, Problem::Overflow))?;
+        }
+        Ok(value)
+    }
+
+    fn negation(&mut self) -> Result<Decimal, ExpressionFailure> {
+        if self.peek() == Some('-') {
+            self.position += 1;
+            return Ok(-self.negation()?);
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Decimal, ExpressionFailure> {
+        let next = self.peek();
+        let start = self.position;
+        match next {
+            Some('(') => {
+                self.position += 1;
+                let value = self.sum()?;
+                match self.peek() {
+                    Some(')') => {
+                        self.position += 1;
+                        Ok(value)
+                    }
+                    Some(c) => Err(self.unexpected(c)),
+                    None => Err(ExpressionFailure::new(
+                        start..self.position,
+                        Problem::Invalid("This bracket is never closed".to_string()),
+                    )),
+                }
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let literal = self.number();
+                parse_number(literal)
+                    .ok_or_else(|| ExpressionFailure::new(start..self.position, Problem::Overflow))
+            }
+            Some(c) if c.is_alphabetic() || c == '_' => {
+                let rest = &self.text[self.position..];
+                let len = rest
+                    .find(|c: char| !(c.is_alphanumeric() || c == '_'))
+                    .unwrap_or(rest.len());
+                self.position += len;
+                let name = &rest[..len];
+                self.constants.get(name).copied().ok_or_else(|| {
+                    let problem = Problem::UnknownConstant(name.to_string());
+                    ExpressionFailure::new(start..self.position, problem)
+                })
+            }
+            Some(c) => Err(self.unexpected(c)),
+            None => Err(ExpressionFailure::new(
+                start..start,
+                Problem::Invalid("Expected a number, constant or `(`".to_string()),
+            )),
+        }
+    }
+
+    /// Reads a number literal, written the same way as anywhere else in a script
+    fn number(&mut self) -> &str {
+        let rest = &self.text[self.position..];
+        let mut len = rest
+            .find(|c: char| !(c.is_ascii_digit() || c == '_' || c == '.'))
+            .unwrap_or(rest.len());
+        if let Some(exponent) = rest[len..].strip_prefix(['e', 'E']) {
+            let digits = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+            let count = digits
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(digits.len());
+            if count > 0 {
+                len += 1 + exponent.len() - digits.len() + count;
+            }
+        }
+        self.position += len;
+        &rest[..len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+
+    fn constants() -> Constants {
+        Constants::from([
+            ("base".to_string(), dec!(4)),
+            ("half".to_string(), dec!(0.5)),
+        ])
+    }
+
+    #[test]
+    fn products_before_sums() {
+        let constants = constants();
+        assert_eq!(evaluate("1 + base * 2", &constants), Ok(dec!(9)));
+        assert_eq!(evaluate("(1 + base) * 2", &constants), Ok(dec!(10)));
+        assert_eq!(evaluate("8 - 2 - 1", &constants), Ok(dec!(5)));
+        assert_eq!(evaluate("-base / -half", &constants), Ok(dec!(8)));
+    }
+
+    #[test]
+    fn decimals_keep_precision() {
+        assert_eq!(evaluate("0.1 + 0.2", &Constants::new()), Ok(dec!(0.3)));
+    }
+
+    #[test]
+    fn failures_point_at_problem() {
+        let constants = constants();
+        assert_eq!(
+            evaluate("base / (half - 0.5)", &constants),
+            Err(ExpressionFailure::new(7..19, Problem::DivisionByZero))
+        );
+        assert_eq!(
+            evaluate("base + bsae", &constants),
+            Err(ExpressionFailure::new(
+                7..11,
+                Problem::UnknownConstant("bsae".to_string())
+            ))
+        );
+        assert_eq!(
+            evaluate("(base", &constants),
+            Err(ExpressionFailure::new(
+                0..5,
+                Problem::Invalid("This bracket is never closed".to_string())
+            ))
+        );
+    }
+
+    #[test]
+    fn written_spans_count_the_dollar() {
+        assert_eq!(
+            evaluate_written("$missing", &Constants::new()),
+            Err(ExpressionFailure::new(
+                1..8,
+                Problem::UnknownConstant("missing".to_string())
+            ))
+        );
+        assert_eq!(evaluate_written("$(base * 3)", &constants()), Ok(dec!(12)));
+    }
+}
@@ -1,16 +1,34 @@
-use std::{fmt, str::FromStr};
+use std::fmt;
 
 use lalrpop_util::lalrpop_mod;
 use rust_decimal::Decimal;
 
-use logos::{self, Logos};
+use logos::{self, Lexer, Logos};
 
-use crate::{LexicalError, common::DataParser};
+use crate::{
+    LexicalError,
+    common::{self, DataParser, LexExtras, NumberToken, ParsedEntries, Patchable},
+    printer::{PrintScript, ScriptWriter, boolean, decimal, quoted},
+    schema::FieldSchema,
+};
 
 #[derive(Logos, Clone, Debug, PartialEq)]
-#[logos(skip r"[\s\t\f]+", error = LexicalError)]
+#[logos(skip r"[\s\t\f]+", error = LexicalError, extras = LexExtras)]
 #[logos(skip r"//[^\n\r]*")]
+#[logos(skip(r"///([^/\n\r][^\n\r]*)?", common::doc_comment))]
+#[logos(skip(r"/\*", common::block_comment))]
 pub enum GoodToken {
+    #[token("replace")]
+    Replace,
+    #[token("patch")]
+    Patch,
+    #[token("delete")]
+    Delete,
+    #[token("template")]
+    Template,
+    #[token("extends")]
+    Extends,
+
     #[token("true")]
     True,
     #[token("false")]
@@ -23,11 +41,19 @@ pub enum GoodToken {
     #[token("}")]
     RightCurly,
 
-    #[regex(r#""[^"]*""#, |lex| lex.slice().trim_matches('"').to_string())]
+    #[regex(r#""([^"\\]|\\.)*""#, common::string)]
+    #[regex(r#""""([^"]|"[^"]|""[^"])*""""#, common::string)]
     String(String),
 
-    #[regex(r"(-?\d+\.?\d*)", |lex| Decimal::from_str(lex.slice()).expect("parsed_decimal"), priority = 4)]
+    #[regex(
+        r"-?[0-9][0-9_]*(\.[0-9_]*)?([eE][+-]?[0-9]+)?",
+        common::decimal,
+        priority = 4
+    )]
     DecimalNumber(Decimal),
+    #[regex(r"\$[A-Za-z_][A-Za-z0-9_]*", common::expression)]
+    #[regex(r"\$\(", common::expression)]
+    Expression,
 
     #[token("icon")]
     Icon,
@@ -95,9 +121,16 @@ impl fmt::Display for GoodToken {
     }
 }
 
-lalrpop_mod!(pub goods);
+impl<'s> NumberToken<'s> for GoodToken {
+    fn number(_lex: &mut Lexer<'s, Self>, value: Decimal) -> Self {
+        GoodToken::DecimalNumber(value)
+    }
+}
 
-#[derive(Clone, Default, Debug)]
+lalrpop_mod!(#[allow(clippy::large_enum_variant, clippy::ptr_arg)] pub goods);
+
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum GoodType {
     #[default]
     Public,
@@ -105,7 +138,8 @@ pub enum GoodType {
     Tender,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GoodData {
     pub id: String,
     pub hardcoded_id: Option<u8>,
@@ -118,6 +152,8 @@ pub struct GoodData {
     pub prosperity_cost: Decimal,
     pub buy_value: Decimal,
     pub sell_value: Decimal,
+    /// Doc comments written above where the entry is defined or replaced
+    pub doc: Option<String>,
 }
 
 impl Default for GoodData {
@@ -134,11 +170,13 @@ impl Default for GoodData {
             prosperity_cost: Default::default(),
             buy_value: Default::default(),
             sell_value: Default::default(),
+            doc: Default::default(),
         }
     }
 }
 
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ConsumptionType {
     #[default]
     None,
@@ -154,6 +192,7 @@ pub enum ConsumptionType {
 ///For triggerering when the good is needed these are thresholds on
 /// when to stop
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SurvivalConditions {
     pub magnetosphere: Option<Decimal>,
     pub atmosphere: Option<Decimal>,
@@ -180,6 +219,7 @@ impl Default for SurvivalConditions {
     }
 }
 
+#[derive(Clone, Debug)]
 pub enum Field {
     Icon(String),
     Name(String),
@@ -193,6 +233,7 @@ pub enum Field {
     Vendible(bool),
 }
 
+#[derive(Clone, Debug)]
 pub enum SurvivalField {
     Magnetosphere(Decimal),
     Atmosphere(Decimal),
@@ -203,11 +244,151 @@ pub enum SurvivalField {
     LackServicePenalty(Decimal),
 }
 
+impl Patchable for GoodData {
+    type Key = String;
+    type Field = Field;
+    fn key(&self) -> &Self::Key {
+        &self.id
+    }
+    fn set_key(&mut self, key: Self::Key) {
+        self.id = key;
+    }
+    fn apply(&mut self, field: Self::Field) {
+        match field {
+            Field::Icon(i) => self.icon = i,
+            Field::Name(n) => self.name = n,
+            Field::BuyValue(b) => self.buy_value = b,
+            Field::SellValue(s) => self.sell_value = s,
+            Field::GoodType(g) => self.good_type = g,
+            Field::HardcodedId(i) => self.hardcoded_id = Some(i),
+            Field::ConsumptionType(c) => self.consumption_type = c,
+            Field::ProsperityBonus(p) => self.prosperity_bonus = p,
+            Field::ProsperityCost(p) => self.prosperity_cost = p,
+            Field::Vendible(v) => self.vendible = v,
+        }
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+    fn set_doc(&mut self, doc: Option<String>) {
+        self.doc = doc;
+    }
+}
+
 impl<'s> DataParser<'s> for GoodData {
     type Token = GoodToken;
+    const KEYWORDS: &'static [&'static str] = &[
+        "replace",
+        "patch",
+        "delete",
+        "template",
+        "extends",
+        "true",
+        "false",
+        "icon",
+        "name",
+        "buy_value",
+        "sell_value",
+        "good_type",
+        "public",
+        "private",
+        "tender",
+        "hardcoded_id",
+        "consumption_type",
+        "prosperity_bonus",
+        "prosperity_cost",
+        "vendible",
+        "none",
+        "amenity",
+        "survival",
+        "essential",
+        "magnetosphere",
+        "atmosphere",
+        "temperature",
+        "water",
+        "breathability",
+        "served_step",
+        "lack_of_service_penalty",
+    ];
+    const SCHEMA: &'static [FieldSchema<Self>] = &[
+        FieldSchema::required("name"),
+        FieldSchema::required("icon"),
+        FieldSchema::optional("buy_value").range(Some(Decimal::ZERO), None, |g| g.buy_value),
+        FieldSchema::optional("sell_value").range(Some(Decimal::ZERO), None, |g| g.sell_value),
+    ];
     fn parse_tokens(
         tokens: Vec<(usize, Self::Token, usize)>,
-    ) -> Result<Vec<GoodData>, lalrpop_util::ParseError<usize, Self::Token, String>> {
-        goods::GoodsParser::new().parse(tokens)
+        errors: &mut Vec<lalrpop_util::ErrorRecovery<usize, Self::Token, String>>,
+    ) -> ParsedEntries<Self, Self::Token> {
+        goods::GoodsParser::new().parse(errors, tokens)
+    }
+}
+
+impl PrintScript for GoodData {
+    const SECTION: &'static str = "#goods";
+    fn print(&self, w: &mut ScriptWriter) {
+        let d = GoodData::default();
+        w.line(quoted(&self.id));
+        w.indented(|w| {
+            w.field_unless_default("name", quoted(&self.name), self.name == d.name);
+            w.field_unless_default("icon", quoted(&self.icon), self.icon == d.icon);
+            if let Some(h) = self.hardcoded_id {
+                w.field("hardcoded_id", h);
+            }
+            w.field_unless_default(
+                "vendible",
+                boolean(self.vendible),
+                self.vendible == d.vendible,
+            );
+            let good_type = match self.good_type {
+                GoodType::Public => "public",
+                GoodType::Private => "private",
+                GoodType::Tender => "tender",
+            };
+            w.field_unless_default("good_type", good_type, self.good_type == d.good_type);
+            match &self.consumption_type {
+                ConsumptionType::None => {}
+                ConsumptionType::Amenity => w.field("consumption_type", "amenity"),
+                ConsumptionType::Essential => w.field("consumption_type", "essential"),
+                ConsumptionType::Survival(s) => w.block("consumption_type = survival", |w| {
+                    let conditions = [
+                        ("magnetosphere", s.magnetosphere),
+                        ("atmosphere", s.atmosphere),
+                        ("temperature", s.temperature),
+                        ("water", s.water),
+                        ("breathability", s.breathability),
+                    ];
+                    for (name, value) in conditions {
+                        if let Some(v) = value {
+                            w.field(name, decimal(v));
+                        }
+                    }
+                    let sd = SurvivalConditions::default();
+                    w.field_unless_default(
+                        "served_step",
+                        decimal(s.served_step),
+                        s.served_step == sd.served_step,
+                    );
+                    w.field_unless_default(
+                        "lack_of_service_penalty",
+                        decimal(s.lack_of_service_penalty),
+                        s.lack_of_service_penalty == sd.lack_of_service_penalty,
+                    );
+                }),
+            }
+            let values = [
+                (
+                    "prosperity_bonus",
+                    self.prosperity_bonus,
+                    d.prosperity_bonus,
+                ),
+                ("prosperity_cost", self.prosperity_cost, d.prosperity_cost),
+                ("buy_value", self.buy_value, d.buy_value),
+                ("sell_value", self.sell_value, d.sell_value),
+            ];
+            for (name, value, default) in values {
+                w.field_unless_default(name, decimal(value), value == default);
+            }
+        });
     }
 }
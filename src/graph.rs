@@ -0,0 +1,49 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+/// The ids each id leads to
+pub type Edges<'d> = BTreeMap<&'d str, BTreeSet<&'d str>>;
+
+/// Every id that can be reached from `starts` by following any number of edges,
+/// including the starts themselves
+pub fn reachable<'d>(
+    edges: &Edges<'d>,
+    starts: impl IntoIterator<Item = &'d str>,
+) -> BTreeSet<&'d str> {
+    let mut reached = BTreeSet::new();
+    let mut queue: Vec<&str> = starts.into_iter().collect();
+    while let Some(id) = queue.pop() {
+        if reached.insert(id) {
+            queue.extend(edges.get(id).into_iter().flatten());
+        }
+    }
+    reached
+}
+
+/// Groups of ids that can each reach the others and back again,
+/// an id with an edge to itself is a group on its own
+pub fn cycles<'d>(edges: &Edges<'d>) -> Vec<Vec<&'d str>> {
+    let onward: BTreeMap<&str, BTreeSet<&str>> = edges
+        .iter()
+        .map(|(from, to)| (*from, reachable(edges, to.iter().copied())))
+        .collect();
+    let mut grouped = BTreeSet::new();
+    let mut cycles = Vec::new();
+    for (from, reached) in &onward {
+        if grouped.contains(from) || !reached.contains(from) {
+            continue;
+        }
+        let cycle: Vec<&'d str> = reached
+            .iter()
+            .copied()
+            .filter(|other| onward.get(other).is_some_and(|r| r.contains(from)))
+            .collect();
+        grouped.extend(cycle.iter().copied());
+        cycles.push(cycle);
+    }
+    cycles
+}
+
+/// Text as a quoted Graphviz DOT id or label
+pub fn dot_quoted(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,101 @@
+use miette::{NamedSource, SourceSpan};
+
+use crate::{
+    Section, SectionBody, SyntaxError, Token,
+    common::{TRIPLE_QUOTE, closing_quote, unescape},
+    expression::Constants,
+    lex, lib,
+};
+
+/// An `#include "path"` directive, the file it names is loaded
+/// before the file it is written in
+#[derive(Clone, Debug, PartialEq)]
+pub struct Include {
+    /// As written, relative to the directory of the file it is written in
+    pub path: String,
+    /// Where the path is written, quotes included
+    pub span: SourceSpan,
+}
+
+/// The files a script includes in the order they are written.
+/// Directives that can't be read are left out, parsing the file reports them
+pub fn includes(file_name: &str, contents: &str) -> Vec<Include> {
+    let mut errors = Vec::new();
+    let (tokens, _) = lex::<Token>(
+        file_name,
+        contents,
+        contents,
+        0,
+        &Constants::new(),
+        &mut errors,
+    );
+    let sections = lib::SectionsParser::new()
+        .parse(&mut Vec::new(), tokens)
+        .unwrap_or_default();
+    sections
+        .iter()
+        .filter_map(|s| match s {
+            Section::Include(body) => read_include(file_name, contents, body).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Reads the quoted path after `#include`, which can only be followed by comments
+pub(crate) fn read_include(
+    file_name: &str,
+    contents: &str,
+    body: &SectionBody,
+) -> Result<Include, Box<SyntaxError>> {
+    let text = body.contents.trim_start();
+    let start = body.offset + body.contents.len() - text.len();
+    let quoted = text
+        .strip_prefix('"')
+        .filter(|_| !text.starts_with(TRIPLE_QUOTE))
+        .and_then(closing_quote)
+        .map(|end| end + 2);
+    let rest = quoted.map_or(text, |len| &text[len..]);
+    let only_comments = rest.lines().all(|line| {
+        let line = line.trim();
+        line.is_empty() || line.starts_with("//")
+    });
+    match quoted {
+        Some(len) if only_comments => {
+            let (path, _) = unescape(&text[1..len - 1]);
+            Ok(Include {
+                path,
+                span: (start, len).into(),
+            })
+        }
+        _ => Err(Box::new(SyntaxError {
+            src: NamedSource::new(file_name, contents.to_string()),
+            message: "Expected the path of a file to include".to_string(),
+            bad_bit: Some((start, text.trim_end().len()).into()),
+            expected: vec!["string".to_string()],
+            advice: Some(
+                "Write one quoted path after `#include`, such as `#include \"shared/goods.txt\"`"
+                    .to_string(),
+            ),
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn include_paths_in_order_written() {
+        let contents = "#include \"a.txt\" // shared goods\n#include \"sub/b.txt\"\n#goods\n";
+        let found = includes("test.txt", contents);
+        let paths: Vec<_> = found.iter().map(|i| i.path.as_str()).collect();
+        assert_eq!(paths, ["a.txt", "sub/b.txt"]);
+        assert_eq!(Some(found[1].span.offset()), contents.find("\"sub"));
+    }
+
+    #[test]
+    fn unreadable_directive_is_left_out() {
+        let contents = "#include a.txt\n#include \"b.txt\" \"c.txt\"\n";
+        assert!(includes("test.txt", contents).is_empty());
+    }
+}
@@ -1,10 +1,13 @@
-use std::fmt::{self, Display};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Display},
+    sync::Arc,
+};
 
 use building::BuildingData;
 use goods::GoodData;
 use lalrpop_util::lalrpop_mod;
 use planet_types::PlanetTypeData;
-use regex::Regex;
 use tech::TechData;
 
 use miette::{Diagnostic, NamedSource, SourceSpan};
@@ -14,22 +17,49 @@ use thiserror::Error;
 use logos::{self, Logos};
 
 use crate::{
-    asteroid_mining::AsteroidMiningData, augmentations::AugmentationData, common::DataParser,
-    designation::DesignationData, orbital::OrbitalData, ranks::RankData, ship::ShipData,
-    shipyard::ShipyardData, shipyard_buildings::ShipyardBuildingData,
-    species_trait::SpeciesTraitData, stapledon_swarm::StapledonSwarmData,
-    stellar_system::StellarData, tooltips::ToolTipsData,
+    asteroid_mining::AsteroidMiningData,
+    augmentations::AugmentationData,
+    common::{
+        DataParser, DocComment, Entry, EntrySpan, LexExtras, Location, ParsedEntries, SourceFile,
+        assigned_field, doc_before, field_name,
+    },
+    constants::{ConstantData, Field as ConstantField},
+    designation::DesignationData,
+    expression::{Constants, ExpressionFailure, Problem, evaluate_written},
+    orbital::OrbitalData,
+    printer::PrintScript,
+    ranks::RankData,
+    schema::{MissingFieldWarning, OutOfRangeError},
+    ship::ShipData,
+    shipyard::ShipyardData,
+    shipyard_buildings::ShipyardBuildingData,
+    species_trait::SpeciesTraitData,
+    stapledon_swarm::StapledonSwarmData,
+    stellar_system::StellarData,
+    tooltips::ToolTipsData,
 };
 
 pub mod asteroid_mining;
 pub mod augmentations;
 pub mod building;
 pub mod common;
+pub mod constants;
 pub mod designation;
+pub mod economy;
+pub mod evaluate;
+pub mod expression;
 pub mod goods;
+pub mod graph;
+pub mod include;
+pub mod loader;
+#[cfg(feature = "lsp")]
+pub mod lsp;
 pub mod orbital;
 pub mod planet_types;
+pub mod printer;
 pub mod ranks;
+pub mod rule_check;
+pub mod schema;
 pub mod ship;
 pub mod shipyard;
 pub mod shipyard_buildings;
@@ -37,56 +67,280 @@ pub mod species_trait;
 pub mod stapledon_swarm;
 pub mod stellar_system;
 pub mod tech;
+pub mod tech_tree;
+pub mod terraform;
 pub mod tooltips;
+pub mod upgrades;
+pub mod validate;
+
+pub use diagnostics::*;
+
+mod diagnostics {
+    // The miette derive trips a false positive on nightly for every field of a diagnostic.
+    // Its impls belong to the module they are derived in, so the lint can only be
+    // allowed for a whole module and the diagnostics are kept in one of their own
+    #![allow(unused_assignments)]
+
+    use super::*;
+
+    /// A section that could not be parsed by its grammar
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("{message}")]
+    #[diagnostic(code(pronytic::syntax))]
+    pub struct SyntaxError {
+        #[source_code]
+        #[allow(unused_assignments)]
+        pub src: NamedSource<String>,
+        pub message: String,
+        #[label("Problem started here")]
+        pub bad_bit: Option<SourceSpan>,
+        /// Tokens the grammar would have accepted instead
+        pub expected: Vec<String>,
+        #[help]
+        pub advice: Option<String>,
+    }
+
+    ///Token errors
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("I came across an invalid token")]
+    #[diagnostic(code(pronytic::token))]
+    pub struct TokenErrorReport {
+        #[source_code]
+        pub src: NamedSource<String>,
+        #[label("Problem started here")]
+        pub bad_bit: SourceSpan,
+        #[help]
+        pub advice: Option<String>,
+    }
+
+    /// A backslash in a quoted string that doesn't start an escape the scripts know
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("Unknown escape `{escape}` in string")]
+    #[diagnostic(
+        code(pronytic::invalid_escape),
+        help(
+            "Strings can use \\\", \\\\, \\n, \\r, \\t, \\0 and \\u{{..}}, \
+            or be written between triple quotes to keep backslashes as they are"
+        )
+    )]
+    pub struct InvalidEscapeError {
+        #[source_code]
+        pub src: NamedSource<Arc<str>>,
+        pub escape: String,
+        #[label("Not a known escape")]
+        pub bad_bit: SourceSpan,
+    }
+
+    /// An expression that could not be worked out, it is read as zero
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("{message}")]
+    #[diagnostic(code(pronytic::expression))]
+    pub struct ExpressionError {
+        #[source_code]
+        pub src: NamedSource<Arc<str>>,
+        pub message: String,
+        #[label("Here")]
+        pub bad_bit: SourceSpan,
+        #[help]
+        pub advice: Option<String>,
+    }
 
-/// Placeholder for better syntax errors
-#[derive(Error, Debug, Diagnostic)]
-#[error("oops!")]
-#[diagnostic(code(oops::my::bad), url(docsrs))]
-struct SyntaxError {
-    // The Source that we're gonna be printing snippets out of.
-    // This can be a String if you don't have or care about file names.
-    #[source_code]
-    #[allow(unused)]
-    src: NamedSource<String>,
-    // Snippets and highlights can be included in the diagnostic!
-    #[label("Problem started here")]
-    bad_bit: SourceSpan,
-    #[help]
-    advice: Option<String>,
+    /// A field written more than once in the same entry,
+    /// only the last value would be kept
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("`{field}` is set more than once")]
+    #[diagnostic(
+        code(pronytic::duplicate_field),
+        help("Remove one of them, only the last value would be used")
+    )]
+    pub struct DuplicateFieldError {
+        #[source_code]
+        pub src: NamedSource<String>,
+        pub field: String,
+        #[label("First set here")]
+        pub first: SourceSpan,
+        #[label("Set again here")]
+        pub duplicate: SourceSpan,
+    }
+
+    /// Every problem found while parsing a file,
+    /// returned instead of the data when anything went wrong
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("{file_name} could not be parsed ({} problems found)", errors.len())]
+    #[diagnostic(code(pronytic::parse))]
+    pub struct ParseReport {
+        pub file_name: String,
+        #[related]
+        pub errors: Vec<miette::Report>,
+        /// Entries that parsed successfully despite the errors
+        pub data: Box<ParseData>,
+    }
 }
 
-///Token errors
-#[derive(Error, Debug, Diagnostic)]
-#[error("I came across an invalid token")]
-struct TokenErrorReport {
-    // The Source that we're gonna be printing snippets out of.
-    // This can be a String if you don't have or care about file names.
-    #[source_code]
-    src: NamedSource<String>,
-    // Snippets and highlights can be included in the diagnostic!
-    #[label("Problem started here")]
-    bad_bit: SourceSpan,
-    #[help]
-    advice: Option<String>,
+impl ExpressionError {
+    fn new(file_name: &str, contents: &str, failure: ExpressionFailure) -> Self {
+        let advice = match failure.problem {
+            Problem::UnknownConstant(_) => Some(
+                "Constants have to be defined in a `#constants` section \
+                before they are used, either earlier in the file or in a file loaded before it",
+            ),
+            Problem::NotWhole(_) => Some("This field only takes whole numbers"),
+            _ => None,
+        };
+        ExpressionError {
+            src: NamedSource::new(file_name, Arc::from(contents)),
+            message: failure.problem.to_string(),
+            bad_bit: failure.span.into(),
+            advice: advice.map(str::to_string),
+        }
+    }
 }
 
 #[derive(Default, Debug, Clone, PartialEq)]
 pub enum LexicalError {
     #[default]
     InvalidToken,
+    /// A `/*` without the `*/` that closes it
+    UnterminatedComment,
+    /// A `$(` without the `)` that closes it
+    UnterminatedExpression,
 }
 
 macro_rules! create_parse_data {
-  ({ $( pub $field:ident : $ty:ty ),* $(,)? }) => {
+  ({ $( pub $field:ident : Vec<$ty:ty> ),* $(,)? }) => {
     ///This is the stored results from a given string of data
-    ///typically a file
+    ///typically a file.
+    ///
+    ///With the `serde` feature only the data itself is serialised,
+    ///locations, pending changes and warnings are left empty when deserialising
+    #[derive(Clone, Default, Debug)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+    pub struct ParseData {
+       $( pub $field: Vec<$ty>, )*
+       /// Where each entry was written
+       #[cfg_attr(feature = "serde", serde(skip))]
+       pub locations: Locations,
+       /// Changes to entries that have not been loaded yet
+       #[cfg_attr(feature = "serde", serde(skip))]
+       pub changes: Changes,
+       /// Templates for entries to extend, which are not entries themselves
+       #[cfg_attr(feature = "serde", serde(skip))]
+       pub templates: Templates,
+       /// Problems that don't stop the data being used,
+       /// such as leaving out a field that should usually be set
+       #[cfg_attr(feature = "serde", serde(skip))]
+       pub warnings: Vec<MissingFieldWarning>,
+    }
+
+    /// The location of every entry in [`ParseData`],
+    /// each list lines up index for index with the data it describes
+    #[derive(Clone, Default, Debug)]
+    pub struct Locations { $( pub $field: Vec<Location>, )* }
+
+    /// Replacements, patches and deletions that did not find the entry
+    /// they change yet, they are applied to data combined before them
+    #[derive(Clone, Default, Debug)]
+    pub struct Changes { $( pub $field: Vec<(Entry<$ty>, Location)>, )* }
+
+    /// Every template by the type of entry it is for, in the order they were loaded,
+    /// along with the names of the fields it sets including those of the templates it extends
     #[derive(Clone, Default, Debug)]
-    pub struct ParseData { $( pub $field: $ty, )* }
+    pub struct Templates { $( pub $field: Vec<($ty, Vec<String>)>, )* }
+
+    impl Changes {
+       /// Whether every change has found the entry it changes
+       pub fn is_empty(&self) -> bool {
+          true $( && self.$field.is_empty() )*
+       }
+    }
 
     impl ParseData {
-       pub fn combine(&mut self, mut other: ParseData) {
+       /// Adds the data from a file loaded after this,
+       /// its pending changes are applied to this data first.
+       /// Returns the problems found with extensions of entries in this data
+       pub fn combine(&mut self, mut other: ParseData) -> Vec<miette::Report> {
+          let mut errors = Vec::new();
+          $(
+             for (change, location) in other.changes.$field.drain(..) {
+                if let Err(pending) = apply_change(
+                   &mut self.$field,
+                   &mut self.locations.$field,
+                   &mut self.templates.$field,
+                   change,
+                   location,
+                   &mut errors,
+                   &mut self.warnings,
+                ) {
+                   self.changes.$field.push(*pending);
+                }
+             }
+          )*
           $( self.$field.append(&mut other.$field); )*
+          $( self.templates.$field.append(&mut other.templates.$field); )*
+          $( self.locations.$field.append(&mut other.locations.$field); )*
+          self.warnings.append(&mut other.warnings);
+          errors
+       }
+
+       /// Loads extensions written above the entry or template they extend,
+       /// which are retried until none of them can be loaded
+       fn resolve_extensions(&mut self, errors: &mut Vec<miette::Report>) {
+          $(
+             loop {
+                let mut resolved = false;
+                for (change, location) in std::mem::take(&mut self.changes.$field) {
+                   if !matches!(change, Entry::Extend(_)) {
+                      self.changes.$field.push((change, location));
+                      continue;
+                   }
+                   match apply_change(
+                      &mut self.$field,
+                      &mut self.locations.$field,
+                      &mut self.templates.$field,
+                      change,
+                      location,
+                      errors,
+                      &mut self.warnings,
+                   ) {
+                      Ok(()) => resolved = true,
+                      Err(pending) => self.changes.$field.push(*pending),
+                   }
+                }
+                if !resolved {
+                   break;
+                }
+             }
+          )*
+       }
+
+       /// Takes out the extensions that extend each other in a loop, which can never be loaded,
+       /// returning an error for each loop
+       pub fn take_extension_cycles(&mut self) -> Vec<miette::Report> {
+          let mut errors = Vec::new();
+          $(
+             let (cycles, in_cycle) = validate::extension_cycles(&self.changes.$field);
+             errors.extend(cycles);
+             self.changes.$field.retain(|(change, _)| match change {
+                Entry::Extend(e) => !in_cycle.contains(&e.key.to_string()),
+                _ => true,
+             });
+          )*
+          errors
+       }
+
+       /// How many entries there are of each type, named after their section
+       pub fn entry_counts(&self) -> Vec<(&'static str, usize)> {
+          vec![ $( (<$ty as PrintScript>::SECTION.trim_start_matches('#'), self.$field.len()), )* ]
+       }
+
+       /// The keywords of the section with this header, such as `#goods`
+       pub fn section_keywords(header: &str) -> Option<&'static [&'static str]> {
+          $(
+             if header == <$ty as PrintScript>::SECTION {
+                return Some(<$ty as DataParser<'static>>::KEYWORDS);
+             }
+          )*
+          None
        }
     }
   }
@@ -96,6 +350,7 @@ create_parse_data!({
     pub asteroid_mining: Vec<AsteroidMiningData>,
     pub augmentations: Vec<AugmentationData>,
     pub building_data: Vec<BuildingData>,
+    pub constants: Vec<ConstantData>,
     pub designation_data:Vec<DesignationData>,
     pub goods_data: Vec<GoodData>,
     pub orbital_data: Vec<OrbitalData>,
@@ -112,7 +367,7 @@ create_parse_data!({
 });
 
 #[derive(Logos, Clone, Debug, PartialEq)]
-#[logos(error=LexicalError)]
+#[logos(error = LexicalError, extras = LexExtras)]
 pub enum Token {
     #[token("#asteroid_mining")]
     AsteroidMining,
@@ -120,10 +375,14 @@ pub enum Token {
     Augmentations,
     #[token("#buildings")]
     Buildings,
+    #[token("#constants")]
+    Constants,
     #[token("#designations")]
     Designations,
     #[token("#goods")]
     Goods,
+    #[token("#include")]
+    Include,
     #[token("#orbital")]
     Orbital,
     #[token("#planet_types")]
@@ -146,7 +405,7 @@ pub enum Token {
     Tech,
     #[token("#tooltips")]
     ToolTips,
-    #[regex(r#"[^#]+"#, |lex| lex.slice().trim_matches('"').to_string())]
+    #[regex(r#"[^#]"#, common::section_contents)]
     SectionContents(String),
 }
 
@@ -156,210 +415,943 @@ impl fmt::Display for Token {
     }
 }
 
-lalrpop_mod!(pub lib);
+lalrpop_mod!(#[allow(clippy::ptr_arg)] pub lib);
+
+/// The text of a section and the byte offset it starts at in its file,
+/// so errors inside the section can point at the right place
+pub struct SectionBody {
+    pub contents: String,
+    pub offset: usize,
+}
 
 pub enum Section {
-    AsteroidMining(String),
-    Augmentations(String),
-    Buildings(String),
-    Designations(String),
-    Goods(String),
-    Orbital(String),
-    PlanetTypes(String),
-    Ranks(String),
-    SpecieTraits(String),
-    Ships(String),
-    Shipyard(String),
-    ShipyardBuildings(String),
-    Stapledon(String),
-    StellarSystem(String),
-    Tech(String),
-    ToolTips(String),
+    AsteroidMining(SectionBody),
+    Augmentations(SectionBody),
+    Buildings(SectionBody),
+    Constants(SectionBody),
+    Designations(SectionBody),
+    Goods(SectionBody),
+    /// The path of a file to load first, read by the loader
+    Include(SectionBody),
+    Orbital(SectionBody),
+    PlanetTypes(SectionBody),
+    Ranks(SectionBody),
+    SpecieTraits(SectionBody),
+    Ships(SectionBody),
+    Shipyard(SectionBody),
+    ShipyardBuildings(SectionBody),
+    Stapledon(SectionBody),
+    StellarSystem(SectionBody),
+    Tech(SectionBody),
+    ToolTips(SectionBody),
 }
 
-fn lex<'s, T>(file_name: &str, input: &'s str) -> Vec<(usize, T, usize)>
+/// Lexes `input` which starts `offset` bytes into the file `contents`,
+/// returning the tokens along with the doc comments between them.
+/// Their spans are relative to the start of the file
+fn lex<'s, T>(
+    file_name: &str,
+    contents: &str,
+    input: &'s str,
+    offset: usize,
+    constants: &Constants,
+    errors: &mut Vec<miette::Report>,
+) -> (Vec<(usize, T, usize)>, Vec<DocComment>)
 where
-    T: Logos<'s, Source = str, Error = LexicalError>,
-    T::Extras: Default,
+    T: Logos<'s, Source = str, Error = LexicalError, Extras = LexExtras>,
 {
-    let mut lex = T::lexer(input);
+    let extras = LexExtras {
+        constants: constants.clone(),
+        ..Default::default()
+    };
+    let mut lex = T::lexer_with_extras(input, extras);
     let mut tokens = Vec::new();
+    // Consecutive bad characters are reported once
+    let mut last_error_end = None;
     while let Some(tok) = lex.next() {
-        let token = match tok {
-            Ok(t) => t,
-            Err(e) => match e {
-                LexicalError::InvalidToken => {
-                    let last: usize = tokens.last().map(|(_, _, x)| *x).unwrap_or_default();
-
-                    handle_lexical_errors(file_name, e, input, last);
-                }
-            },
-        };
         let span = lex.span();
-        tokens.push((span.start, token, span.end));
+        match tok {
+            Ok(token) => tokens.push((span.start + offset, token, span.end + offset)),
+            Err(e) => {
+                if last_error_end != Some(span.start) {
+                    let start = span.start + offset;
+                    errors.push(handle_lexical_errors(file_name, e, contents, start).into());
+                }
+                last_error_end = Some(span.end);
+            }
+        }
+        for number in lex.extras.out_of_range.drain(..) {
+            let start = number.span.start + offset;
+            let field = assigned_field(contents, start);
+            errors.push(
+                OutOfRangeError {
+                    src: NamedSource::new(file_name, Arc::from(contents)),
+                    message: number.limit.message(&number.value, field),
+                    bad_bit: (start, number.span.len()).into(),
+                }
+                .into(),
+            );
+        }
+        for escape in lex.extras.invalid_escapes.drain(..) {
+            let (start, end) = (escape.start + offset, escape.end + offset);
+            errors.push(
+                InvalidEscapeError {
+                    src: NamedSource::new(file_name, Arc::from(contents)),
+                    escape: contents[start..end].to_string(),
+                    bad_bit: (start..end).into(),
+                }
+                .into(),
+            );
+        }
+        for failure in lex.extras.expressions.drain(..) {
+            let failure = failure.shifted(offset);
+            errors.push(ExpressionError::new(file_name, contents, failure).into());
+        }
+    }
+    let docs = lex
+        .extras
+        .docs
+        .into_iter()
+        .map(|doc| DocComment {
+            span: doc.span.start + offset..doc.span.end + offset,
+            ..doc
+        })
+        .collect();
+    (tokens, docs)
+}
+
+/// Converts a lalrpop error into a diagnostic pointing at the problem
+fn syntax_error<T: Display>(
+    file_name: &str,
+    input: &str,
+    error: lalrpop_util::ParseError<usize, T, String>,
+) -> SyntaxError {
+    let src = NamedSource::new(file_name, input.to_string());
+    match error {
+        lalrpop_util::ParseError::InvalidToken { location } => SyntaxError {
+            src,
+            message: "Invalid token".to_string(),
+            bad_bit: Some(location.into()),
+            expected: Vec::new(),
+            advice: None,
+        },
+        lalrpop_util::ParseError::UnrecognizedEof { location, expected } => SyntaxError {
+            src,
+            message: "Unexpected end of section".to_string(),
+            bad_bit: Some(location.into()),
+            advice: Some(format!("Expected {}", expected.join(","))),
+            expected,
+        },
+        lalrpop_util::ParseError::UnrecognizedToken { token, expected } => SyntaxError {
+            src,
+            message: format!("Unexpected {}", token.1),
+            bad_bit: Some((token.0, token.2 - token.0).into()),
+            advice: Some(format!("Expected {} found {}", expected.join(","), token.1)),
+            expected,
+        },
+        lalrpop_util::ParseError::ExtraToken { token } => SyntaxError {
+            src,
+            message: format!("Unexpected {} after the end of the section", token.1),
+            bad_bit: Some((token.0, token.2 - token.0).into()),
+            expected: Vec::new(),
+            advice: None,
+        },
+        lalrpop_util::ParseError::User { error } => SyntaxError {
+            src,
+            message: error,
+            bad_bit: None,
+            expected: Vec::new(),
+            advice: None,
+        },
     }
-    tokens
 }
 
-fn parse_section<'s, Token, Data>(file_name: &'s str, input: &'s str) -> Vec<Data>
+/// Parses a section's entries and checks each of them against its schema
+fn parse_section<'s, Token, Data>(
+    file: &'s SourceFile,
+    section: &'s SectionBody,
+    constants: &Constants,
+    errors: &mut Vec<miette::Report>,
+    warnings: &mut Vec<MissingFieldWarning>,
+) -> Vec<(Entry<Data>, EntrySpan)>
 where
     Data: DataParser<'s, Token = Token>,
-    Token: Logos<'s, Source = str, Error = LexicalError> + Display,
-    Token::Extras: Default,
+    Token: Logos<'s, Source = str, Error = LexicalError, Extras = LexExtras> + Display + Clone,
 {
-    let tokens = lex::<Token>(file_name, input);
-    let data_parse = Data::parse_tokens(tokens);
+    let (file_name, contents) = (file.name.as_str(), &*file.contents);
+    let lexed_from = errors.len();
+    let (tokens, docs) = lex::<Token>(
+        file_name,
+        contents,
+        &section.contents,
+        section.offset,
+        constants,
+        errors,
+    );
+
+    // Numbers the lexer already reported aren't checked against the schema again
+    let reported: Vec<SourceSpan> = errors[lexed_from..]
+        .iter()
+        .filter_map(|e| e.labels())
+        .flatten()
+        .map(|label| *label.inner())
+        .collect();
+
+    let (data_parse, recovered) = parse_tokens::<Data, Token>(contents, tokens);
 
+    errors.extend(recovered.into_iter().map(|r| {
+        // Errors raised by grammar actions point at the tokens they give back
+        let dropped = r.dropped_tokens.first().zip(r.dropped_tokens.last());
+        let mut error = syntax_error(file_name, contents, r.error);
+        error.bad_bit = error
+            .bad_bit
+            .or(dropped.map(|((l, _, _), (_, _, r))| (*l..*r).into()));
+        error.into()
+    }));
     match data_parse {
-        Ok(list) => list,
-        //TODO's are on errors because I have not triggered them and don't know how to
-        // if I come across them I will give them proper implementations
-        Err(e) => match e {
-            lalrpop_util::ParseError::InvalidToken { location } => {
-                let problem = SyntaxError {
-                    src: NamedSource::new(file_name, input.to_string()),
-                    bad_bit: (location).into(),
-                    advice: Some("Skill issue".to_string()),
-                };
+        Ok(mut list) => {
+            for (entry, span) in &mut list {
+                duplicate_fields(file_name, contents, span, Data::REPEATABLE_FIELDS, errors);
+                let doc = doc_before(contents, &docs, span.entry.offset());
+                // Patches, extensions and templates only hold some of the fields
+                // so can't be checked on their own
+                match entry {
+                    Entry::Extend(extension) => extension.doc = doc,
+                    Entry::Template(data) => data.set_doc(doc),
+                    Entry::Patch(_, _) | Entry::Delete(_) => {}
+                    Entry::Define(data) | Entry::Replace(data) => {
+                        data.set_doc(doc);
+                        let (schema_errors, schema_warnings) = schema::check(
+                            Data::SCHEMA,
+                            data,
+                            span,
+                            &[],
+                            &reported,
+                            file_name,
+                            &file.contents,
+                        );
+                        errors.extend(schema_errors);
+                        warnings.extend(schema_warnings);
+                    }
+                }
+            }
+            list
+        }
+        Err(e) => {
+            errors.push(syntax_error(file_name, contents, e).into());
+            Vec::new()
+        }
+    }
+}
+
+type Recovered<Token> = Vec<lalrpop_util::ErrorRecovery<usize, Token, String>>;
+
+/// Parses a section's tokens, recovering from errors at the next field or entry.
+/// Recovery can carry on from values left over from a broken field,
+/// which aren't entries when they are on the same line as the field.
+/// Every one of them is dropped at once so the section is parsed at most twice
+fn parse_tokens<'s, Data, Token>(
+    contents: &str,
+    mut tokens: Vec<(usize, Token, usize)>,
+) -> (ParsedEntries<Data, Token>, Recovered<Token>)
+where
+    Data: DataParser<'s, Token = Token>,
+    Token: Clone,
+{
+    let mut recovered = Vec::new();
+    let parsed = Data::parse_tokens(tokens.clone(), &mut recovered);
+    let Ok(entries) = &parsed else {
+        return (parsed, recovered);
+    };
+    let strays: HashSet<usize> = recovered
+        .iter()
+        .filter_map(|r| {
+            let lalrpop_util::ParseError::UnrecognizedToken { token, .. } = &r.error else {
+                return None;
+            };
+            // An error at the start of a line isn't part of a field written before it
+            let field = tokens.iter().rev().find(|t| t.2 <= token.0)?;
+            let line_end = contents[token.0..]
+                .find('\n')
+                .map_or(contents.len(), |i| token.0 + i);
+            (!contents[field.2..token.0].contains('\n')).then_some((token.0, line_end))
+        })
+        .flat_map(|(start, line_end)| {
+            entries
+                .iter()
+                .map(|(_, s)| s.entry.offset())
+                .filter(move |offset| (start + 1..line_end).contains(offset))
+        })
+        .collect();
+    if strays.is_empty() {
+        return (parsed, recovered);
+    }
+    tokens.retain(|t| !strays.contains(&t.0));
+    let mut recovered = Vec::new();
+    let parsed = Data::parse_tokens(tokens, &mut recovered);
+    (parsed, recovered)
+}
 
-                panic!("{:?}", miette::Error::new(problem));
+/// Applies a replacement, patch or deletion to the last entry with the same key,
+/// giving the change back if there is no such entry yet.
+/// Extensions are given back until the template or entry they extend is loaded,
+/// then checked against the schema now they have every field
+fn apply_change<T: DataParser<'static> + Clone>(
+    entries: &mut Vec<T>,
+    locations: &mut Vec<Location>,
+    templates: &mut Vec<(T, Vec<String>)>,
+    change: Entry<T>,
+    location: Location,
+    errors: &mut Vec<miette::Report>,
+    warnings: &mut Vec<MissingFieldWarning>,
+) -> Result<(), Box<(Entry<T>, Location)>> {
+    let found = entries.iter().rposition(|e| e.key() == change.key());
+    match (change, found) {
+        (Entry::Template(data), _) => templates.push((data, location.field_names())),
+        (Entry::Extend(extension), _) => {
+            // Templates come first so an entry can extend the template it shares a key with.
+            // Entries were checked when they were loaded so have every field they need
+            let parent = templates
+                .iter()
+                .rev()
+                .map(|(t, fields)| (t, fields.clone()))
+                .chain(entries.iter().rev().map(|e| {
+                    let fields = T::SCHEMA.iter().map(|f| f.name.to_string()).collect();
+                    (e, fields)
+                }))
+                .find(|(p, _)| *p.key() == extension.parent)
+                .map(|(p, fields)| (p.clone(), fields));
+            let Some((parent, mut inherited)) = parent else {
+                return Err(Box::new((Entry::Extend(extension), location)));
+            };
+            return match extension.resolve(&parent) {
+                Entry::Template(data) => {
+                    inherited.extend(location.field_names());
+                    templates.push((data, inherited));
+                    Ok(())
+                }
+                entry => {
+                    if let Entry::Define(data) | Entry::Replace(data) = &entry {
+                        let (schema_errors, schema_warnings) = schema::check(
+                            T::SCHEMA,
+                            data,
+                            &location.span,
+                            &inherited,
+                            &[],
+                            &location.file.name,
+                            &location.file.contents,
+                        );
+                        errors.extend(schema_errors);
+                        warnings.extend(schema_warnings);
+                    }
+                    apply_change(
+                        entries, locations, templates, entry, location, errors, warnings,
+                    )
+                }
+            };
+        }
+        (Entry::Define(data), _) => {
+            entries.push(data);
+            locations.push(location);
+        }
+        (Entry::Replace(data), Some(i)) => {
+            entries[i] = data;
+            if let Some(l) = locations.get_mut(i) {
+                *l = location;
             }
-            lalrpop_util::ParseError::UnrecognizedEof { .. } => todo!(),
-            lalrpop_util::ParseError::UnrecognizedToken { token, expected } => {
-                let problem = SyntaxError {
-                    src: NamedSource::new(file_name, input.to_string()),
-                    bad_bit: (token.0, token.2).into(),
-                    advice: Some(format!(
-                        //redunancy for out of bounds
-                        "{file_name} Expected {} found {}",
-                        expected.join(","),
-                        token.1
-                    )),
-                };
-                panic!("{:?}", miette::Error::new(problem));
+        }
+        (Entry::Patch(_, fields), Some(i)) => {
+            for field in fields {
+                entries[i].apply(field);
             }
-            lalrpop_util::ParseError::ExtraToken { .. } => todo!(),
-            lalrpop_util::ParseError::User { .. } => todo!(),
-        },
+            if let Some(l) = locations.get_mut(i) {
+                l.patches.push(location);
+            }
+        }
+        (Entry::Delete(_), Some(i)) => {
+            entries.remove(i);
+            if i < locations.len() {
+                locations.remove(i);
+            }
+        }
+        (change, None) => return Err(Box::new((change, location))),
     }
+    Ok(())
 }
 
-pub fn parse(file_name: &str, contents: &str) -> ParseData {
-    let tokens = lex::<Token>(file_name, contents);
+/// Reports fields that were written more than once in an entry
+fn duplicate_fields(
+    file_name: &str,
+    contents: &str,
+    span: &EntrySpan,
+    repeatable: &[&str],
+    errors: &mut Vec<miette::Report>,
+) {
+    let mut seen: HashMap<&str, SourceSpan> = HashMap::new();
+    for field_span in &span.fields {
+        let field = field_name(contents, *field_span);
+        if repeatable.contains(&field) {
+            continue;
+        }
+        match seen.get(field) {
+            Some(first) => errors.push(
+                DuplicateFieldError {
+                    src: NamedSource::new(file_name, contents.to_string()),
+                    field: field.trim_matches('"').to_string(),
+                    first: *first,
+                    duplicate: *field_span,
+                }
+                .into(),
+            ),
+            None => {
+                seen.insert(field, *field_span);
+            }
+        }
+    }
+}
+
+/// Parses the contents of a script file, returning every problem
+/// found instead of the data if anything went wrong
+pub fn parse(file_name: &str, contents: &str) -> Result<ParseData, ParseReport> {
+    parse_with_constants(file_name, contents, &Constants::new())
+}
 
-    let main_parse = lib::SectionsParser::new().parse(tokens);
+/// Parses the contents of a script file, where expressions can use `constants`
+/// along with the constants the file defines itself
+pub fn parse_with_constants(
+    file_name: &str,
+    contents: &str,
+    constants: &Constants,
+) -> Result<ParseData, ParseReport> {
+    let mut errors = Vec::new();
+    let (tokens, _) = lex::<Token>(file_name, contents, contents, 0, constants, &mut errors);
+
+    let mut recovered = Vec::new();
+    let main_parse = lib::SectionsParser::new().parse(&mut recovered, tokens);
+    errors.extend(recovered.into_iter().map(|r| {
+        // Errors raised by grammar actions point at the tokens they give back
+        let dropped = r.dropped_tokens.first().zip(r.dropped_tokens.last());
+        let mut error = syntax_error(file_name, contents, r.error);
+        error.bad_bit = error
+            .bad_bit
+            .or(dropped.map(|((l, _, _), (_, _, r))| (*l..*r).into()));
+        error.into()
+    }));
     let mut parse_data = ParseData::default();
+    let mut constants = constants.clone();
+
+    let file = Arc::new(SourceFile {
+        name: file_name.to_string(),
+        contents: contents.into(),
+    });
+    // Adds a section's entries to their list, remembering where each entry came from.
+    // Changes to entries earlier in the file are applied straight away
+    macro_rules! add_entries {
+        ($field:ident, $entries:expr) => {
+            for (entry, span) in $entries {
+                let location = Location {
+                    file: file.clone(),
+                    span,
+                    patches: Vec::new(),
+                };
+                match entry {
+                    Entry::Define(data) => {
+                        parse_data.$field.push(data);
+                        parse_data.locations.$field.push(location);
+                    }
+                    change => {
+                        if let Err(pending) = apply_change(
+                            &mut parse_data.$field,
+                            &mut parse_data.locations.$field,
+                            &mut parse_data.templates.$field,
+                            change,
+                            location,
+                            &mut errors,
+                            &mut parse_data.warnings,
+                        ) {
+                            parse_data.changes.$field.push(*pending);
+                        }
+                    }
+                }
+            }
+        };
+    }
+    macro_rules! parse_into {
+        ($field:ident, $section:expr) => {
+            add_entries!(
+                $field,
+                parse_section(
+                    &file,
+                    &$section,
+                    &constants,
+                    &mut errors,
+                    &mut parse_data.warnings,
+                )
+            )
+        };
+    }
 
     match main_parse {
         Ok(sections) => {
-            for s in sections {
+            // Constants are known before the rest of the file is lexed,
+            // so they can be used in sections written above them
+            let (constant_sections, sections): (Vec<_>, Vec<_>) = sections
+                .into_iter()
+                .partition(|s| matches!(s, Section::Constants(_)));
+            for s in constant_sections.into_iter().chain(sections) {
                 match s {
-                    Section::AsteroidMining(s) => parse_data
-                        .asteroid_mining
-                        .append(&mut parse_section(file_name, &s)),
-                    Section::Augmentations(s) => parse_data
-                        .augmentations
-                        .append(&mut parse_section(file_name, &s)),
-                    Section::Buildings(s) => parse_data
-                        .building_data
-                        .append(&mut parse_section(file_name, &s)),
-                    Section::Designations(s) => parse_data
-                        .designation_data
-                        .append(&mut parse_section(file_name, &s)),
-                    Section::Goods(s) => parse_data
-                        .goods_data
-                        .append(&mut parse_section(file_name, &s)),
-                    Section::Orbital(o) => parse_data
-                        .orbital_data
-                        .append(&mut parse_section(file_name, &o)),
-                    Section::PlanetTypes(s) => parse_data
-                        .planet_type_data
-                        .append(&mut parse_section(file_name, &s)),
-                    Section::Ranks(s) => parse_data
-                        .rank_data
-                        .append(&mut parse_section(file_name, &s)),
-                    Section::Ships(s) => parse_data.ships.append(&mut parse_section(file_name, &s)),
-                    Section::Shipyard(s) => parse_data
-                        .shipyard
-                        .append(&mut parse_section(file_name, &s)),
-                    Section::ShipyardBuildings(s) => parse_data
-                        .shipyard_buildings
-                        .append(&mut parse_section(file_name, &s)),
-                    Section::SpecieTraits(s) => parse_data
-                        .species_trait
-                        .append(&mut parse_section(file_name, &s)),
-                    Section::Stapledon(s) => parse_data
-                        .stapledon
-                        .append(&mut parse_section(file_name, &s)),
-                    Section::StellarSystem(s) => parse_data
-                        .stellar_system
-                        .append(&mut parse_section(file_name, &s)),
-                    Section::Tech(s) => parse_data
-                        .tech_data
-                        .append(&mut parse_section(file_name, &s)),
-                    Section::ToolTips(s) => parse_data
-                        .tooltips
-                        .append(&mut parse_section(file_name, &s)),
+                    Section::AsteroidMining(s) => parse_into!(asteroid_mining, s),
+                    Section::Augmentations(s) => parse_into!(augmentations, s),
+                    Section::Buildings(s) => parse_into!(building_data, s),
+                    Section::Constants(s) => {
+                        let mut entries = parse_section(
+                            &file,
+                            &s,
+                            &constants,
+                            &mut errors,
+                            &mut parse_data.warnings,
+                        );
+                        evaluate_constants(&file, &mut entries, &mut constants, &mut errors);
+                        add_entries!(constants, entries)
+                    }
+                    Section::Designations(s) => parse_into!(designation_data, s),
+                    Section::Goods(s) => parse_into!(goods_data, s),
+                    Section::Include(s) => {
+                        if let Err(e) = include::read_include(file_name, contents, &s) {
+                            errors.push((*e).into());
+                        }
+                    }
+                    Section::Orbital(s) => parse_into!(orbital_data, s),
+                    Section::PlanetTypes(s) => parse_into!(planet_type_data, s),
+                    Section::Ranks(s) => parse_into!(rank_data, s),
+                    Section::Ships(s) => parse_into!(ships, s),
+                    Section::Shipyard(s) => parse_into!(shipyard, s),
+                    Section::ShipyardBuildings(s) => parse_into!(shipyard_buildings, s),
+                    Section::SpecieTraits(s) => parse_into!(species_trait, s),
+                    Section::Stapledon(s) => parse_into!(stapledon, s),
+                    Section::StellarSystem(s) => parse_into!(stellar_system, s),
+                    Section::Tech(s) => parse_into!(tech_data, s),
+                    Section::ToolTips(s) => parse_into!(tooltips, s),
                 }
             }
         }
-        Err(e) => match e {
-            lalrpop_util::ParseError::InvalidToken { location } => {
-                let problem = SyntaxError {
-                    src: NamedSource::new(file_name, contents.to_string()),
-                    bad_bit: (location).into(),
-                    advice: Some("Skill issue".to_string()),
-                };
+        Err(e) => errors.push(syntax_error(file_name, contents, e).into()),
+    }
+    parse_data.resolve_extensions(&mut errors);
+    errors.extend(parse_data.take_extension_cycles());
 
-                panic!("{:?}", miette::Error::new(problem));
+    if errors.is_empty() {
+        Ok(parse_data)
+    } else {
+        Err(ParseReport {
+            file_name: file_name.to_string(),
+            errors,
+            data: Box::new(parse_data),
+        })
+    }
+}
+
+/// Works out the value of each constant in the order they are written,
+/// each can use the constants in `constants` which it is then added to
+fn evaluate_constants(
+    file: &SourceFile,
+    entries: &mut [(Entry<ConstantData>, EntrySpan)],
+    constants: &mut Constants,
+    errors: &mut Vec<miette::Report>,
+) {
+    for (entry, span) in entries {
+        let (id, value, expression) = match entry {
+            Entry::Define(data) | Entry::Replace(data) => {
+                (&data.id, &mut data.value, &data.expression)
             }
-            lalrpop_util::ParseError::UnrecognizedEof { .. } => todo!(),
-            lalrpop_util::ParseError::UnrecognizedToken { token, expected } => {
-                let problem = SyntaxError {
-                    src: NamedSource::new(file_name, contents.to_string()),
-                    bad_bit: (token.0, token.2).into(),
-                    advice: Some(format!("Expected {} found {}", expected.join(","), token.1)),
-                };
-                panic!("{:?}", miette::Error::new(problem));
+            Entry::Patch(id, fields) => match fields.first_mut() {
+                Some(ConstantField::Value(value, expression)) => (&*id, value, &*expression),
+                None => continue,
+            },
+            Entry::Delete(id) => {
+                constants.remove(id);
+                continue;
             }
-            lalrpop_util::ParseError::ExtraToken { .. } => todo!(),
-            lalrpop_util::ParseError::User { .. } => todo!(),
-        },
+            // Constants can't be templates or extend each other
+            Entry::Template(_) | Entry::Extend(_) => continue,
+        };
+        if let (Some(expression), Some(field_span)) = (expression, span.fields.first()) {
+            match evaluate_written(expression, constants) {
+                Ok(v) => *value = v,
+                Err(failure) => {
+                    let failure = failure.shifted(field_span.offset());
+                    errors.push(ExpressionError::new(&file.name, &file.contents, failure).into());
+                }
+            }
+        }
+        constants.insert(id.clone(), *value);
     }
-    parse_data
 }
 
-/// Pretty prints the lexical error message to try and give the user
+/// Builds the lexical error message to try and give the user
 /// The clearest indication where the error likely is
 fn handle_lexical_errors(
     file_name: &str,
     lexical_error: LexicalError,
-    input: &str,
-    last: usize,
-) -> ! {
-    // Needs to be in sync with actual skip tokens, unfortunately
-    // cannot be done in const context as macros require string
-    let skip_texts: [&str; 2] = [r"//[^\n\r]*", r"[\s\t\f]+"];
-    match lexical_error {
-        LexicalError::InvalidToken => {
-            let regexes = skip_texts
-                .into_iter()
-                .flat_map(Regex::new)
-                .collect::<Vec<_>>();
-
-            let error = input
-                .char_indices()
-                .skip(last)
-                .skip_while(|(_, c)| regexes.iter().any(|r| r.is_match(c.to_string().as_str())))
-                .map(|(i, _)| i)
-                .next()
-                .unwrap();
-            let problem = TokenErrorReport {
-                src: NamedSource::new(file_name, input.to_string()),
-                bad_bit: (error).into(),
-                advice: Some("I don't have a definition for this word\nNote:File numbers are relative to the '#' sections".to_string()),
-            };
-            panic!("{:?}", miette::Error::new(problem));
+    contents: &str,
+    start: usize,
+) -> TokenErrorReport {
+    let advice = match lexical_error {
+        LexicalError::InvalidToken => "I don't have a definition for this word",
+        LexicalError::UnterminatedComment => "This comment is never closed with `*/`",
+        LexicalError::UnterminatedExpression => "This expression is never closed with `)`",
+    };
+    TokenErrorReport {
+        src: NamedSource::new(file_name, contents.to_string()),
+        bad_bit: start.into(),
+        advice: Some(advice.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn goods(contents: &str) -> Vec<GoodData> {
+        match parse("test.txt", contents) {
+            Ok(data) => data.goods_data,
+            Err(report) => panic!("{:?}", miette::Report::new(report)),
+        }
+    }
+
+    /// The message of every error found parsing `contents`
+    fn errors(contents: &str) -> Vec<String> {
+        match parse("test.txt", contents) {
+            Ok(_) => Vec::new(),
+            Err(report) => report.errors.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+
+    /// What parsed along with the message of every error found
+    fn parse_with_errors(contents: &str) -> (ParseData, Vec<String>) {
+        match parse("test.txt", contents) {
+            Ok(data) => (data, Vec::new()),
+            Err(report) => {
+                let errors = report.errors.iter().map(|e| e.to_string()).collect();
+                (*report.data, errors)
+            }
+        }
+    }
+
+    #[test]
+    fn value_left_from_broken_field_is_not_an_entry() {
+        let (data, errors) = parse_with_errors(
+            r##"#goods
+"a"
+    icon = = "i"
+    name = "A"
+"b" name = "B" icon = = "j"
+"##,
+        );
+        let ids: Vec<_> = data.goods_data.iter().map(|g| g.id.as_str()).collect();
+        assert_eq!(ids, ["a", "b"]);
+        assert_eq!(data.goods_data[0].name, "A");
+        assert_eq!(
+            errors,
+            [
+                "Unexpected Equal",
+                "Unexpected Equal",
+                "`a` is missing the required field `icon`",
+                "`b` is missing the required field `icon`",
+            ]
+        );
+    }
+
+    #[test]
+    fn values_left_from_broken_fields_are_dropped_together() {
+        let (data, errors) = parse_with_errors(
+            r##"#goods
+"a"
+    icon = = "i" "j" "k"
+    name = "A"
+"b"
+    name = = "x" "y"
+    icon = "b"
+"##,
+        );
+        let ids: Vec<_> = data.goods_data.iter().map(|g| g.id.as_str()).collect();
+        assert_eq!(ids, ["a", "b"]);
+        assert_eq!(data.goods_data[0].name, "A");
+        assert_eq!(data.goods_data[1].icon, "b");
+        assert_eq!(errors.len(), 4);
+    }
+
+    #[test]
+    fn broken_token_before_first_entry_keeps_section() {
+        let (data, errors) = parse_with_errors(
+            r##"#goods
+= "a"
+    name = "A"
+    icon = "a"
+"b"
+    name = "B"
+    icon = "b"
+"##,
+        );
+        let ids: Vec<_> = data.goods_data.iter().map(|g| g.id.as_str()).collect();
+        assert_eq!(ids, ["a", "b"]);
+        assert_eq!(errors, ["Unexpected Equal"]);
+    }
+
+    #[test]
+    fn extension_checked_once_resolved() {
+        let (data, errors) = parse_with_errors(
+            r##"#goods
+template "base"
+    icon = "i"
+"a" extends "base"
+    buy_value = 2.0
+"##,
+        );
+        assert_eq!(data.goods_data.len(), 1);
+        assert_eq!(errors, ["`a` is missing the required field `name`"]);
+    }
+
+    #[test]
+    fn extension_cycle_reported_while_parsing() {
+        let (data, errors) = parse_with_errors(
+            r##"#goods
+"a" extends "b"
+    name = "A"
+"b" extends "a"
+    name = "B"
+"##,
+        );
+        assert!(data.goods_data.is_empty());
+        assert!(data.changes.is_empty());
+        assert_eq!(errors, ["`a`, `b` extend each other in a loop"]);
+    }
+
+    #[test]
+    fn hardcoded_id_out_of_range() {
+        for (written, message) in [
+            ("300", "value 300 exceeds maximum 255 for `hardcoded_id`"),
+            ("-1", "value -1 is below minimum 0 for `hardcoded_id`"),
+            ("1.5", "value 1.5 is not a whole number for `hardcoded_id`"),
+        ] {
+            let contents = format!(
+                "#goods\n\"a\"\n    name = \"A\"\n    icon = \"a\"\n    hardcoded_id = {written}\n"
+            );
+            assert_eq!(errors(&contents), [message]);
         }
     }
+
+    #[test]
+    fn negative_integer_below_minimum() {
+        let contents = "#tech\n\"t\"\n    name = \"T\"\n    time = -5\n";
+        assert_eq!(errors(contents), ["value -5 is below minimum 0 for `time`"]);
+    }
+
+    #[test]
+    fn hash_in_string_stays_in_section() {
+        let parsed = goods(
+            r##"#goods
+"item"
+    name = "Item #1"
+    icon = """#tech"""
+"##,
+        );
+        assert_eq!(parsed[0].name, "Item #1");
+        assert_eq!(parsed[0].icon, "#tech");
+    }
+
+    #[test]
+    fn hash_in_escaped_string_stays_in_section() {
+        let parsed = goods(
+            r##"#goods
+"item"
+    name = "say \"hi\" #tech"
+    icon = "i"
+"##,
+        );
+        assert_eq!(parsed[0].name, "say \"hi\" #tech");
+    }
+
+    #[test]
+    fn hash_in_block_comment_stays_in_section() {
+        let parsed = goods(
+            r##"#goods
+/* see #tech /* and #goods */ */
+"a"
+    name = "A"
+    icon = "a"
+"b"
+    name = "B"
+    icon = "b"
+"##,
+        );
+        assert_eq!(parsed.len(), 2);
+    }
+
+    #[test]
+    fn hash_in_comments_stays_in_section() {
+        let parsed = goods(
+            r##"#goods
+/// Used by #1 factories
+"a"
+    name = "A" // not #tech
+    icon = "a"
+"##,
+        );
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].doc.as_deref(), Some("Used by #1 factories"));
+    }
+
+    #[test]
+    fn hash_after_whitespace_starts_section() {
+        let data = parse(
+            "test.txt",
+            "#goods\n\"a\"\n    name = \"A\"\n    icon = \"a\"\n  #tech\n",
+        )
+        .unwrap();
+        assert_eq!(data.goods_data.len(), 1);
+    }
+
+    /// What parsed along with where the first label of each error starts
+    fn parse_with_labels(contents: &str) -> (ParseData, Vec<Option<usize>>) {
+        match parse("test.txt", contents) {
+            Ok(data) => (data, Vec::new()),
+            Err(report) => {
+                let labels = report
+                    .errors
+                    .iter()
+                    .map(|e| Some(e.labels()?.next()?.offset()))
+                    .collect();
+                (*report.data, labels)
+            }
+        }
+    }
+
+    #[test]
+    fn empty_survival_block_keeps_section() {
+        let contents = r##"#goods
+"air"
+    name = "Air"
+    icon = "air"
+    consumption_type = survival { }
+"food"
+    name = "Food"
+    icon = "food"
+"##;
+        let (data, labels) = parse_with_labels(contents);
+        assert_eq!(data.goods_data.len(), 2);
+        assert_eq!(labels, [contents.find("survival")]);
+    }
+
+    #[test]
+    fn unclosed_tooltip_term_keeps_section() {
+        let contents = r##"#tooltips
+"ore": "Dug up by a `mine"
+"coal": "Burnt for `power`"
+"##;
+        let (data, labels) = parse_with_labels(contents);
+        assert_eq!(data.tooltips[0].map.len(), 2);
+        assert_eq!(labels, [contents.find("\"Dug")]);
+    }
+
+    #[test]
+    fn repeated_field_labels_both() {
+        let contents = r##"#goods
+"a"
+    name = "A"
+    icon = "a"
+    name = "B"
+"##;
+        let report = parse("test.txt", contents).unwrap_err();
+        let [error] = &report.errors[..] else {
+            panic!("expected one error, found {:?}", report.errors);
+        };
+        assert_eq!(error.to_string(), "`name` is set more than once");
+        let labels: Vec<_> = error.labels().unwrap().map(|l| l.offset()).collect();
+        assert_eq!(
+            labels,
+            [contents.find("name"), contents.rfind("name")].map(Option::unwrap)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip_keeps_data() {
+        let data = parse(
+            "test.txt",
+            r##"#goods
+"ore"
+    name = "Ore"
+    icon = "ore"
+    buy_value = 2.5
+#planet_types
+"hot"
+    class = rocky
+    set_asset = "planet"
+    setup {
+        if temperature_celsius GT 50 {
+            set_asset = "hot"
+        } else {
+            set_planet_type = "hot"
+        }
+    }
+"##,
+        )
+        .unwrap();
+        let json = serde_json::to_value(&data).unwrap();
+        let back: ParseData = serde_json::from_value(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(&back).unwrap(), json);
+        assert_eq!(
+            format!("{:?}", back.goods_data),
+            format!("{:?}", data.goods_data)
+        );
+        assert_eq!(
+            format!("{:?}", back.planet_type_data),
+            format!("{:?}", data.planet_type_data)
+        );
+    }
+
+    #[test]
+    fn constants_are_used_by_later_sections() {
+        let contents = r##"#constants
+"price" = 2.5
+"double" = $(price * 2)
+#goods
+"ore"
+    name = "Ore"
+    icon = "ore"
+    buy_value = $double
+    sell_value = $(price / 0)
+"##;
+        let (data, labels) = parse_with_labels(contents);
+        assert_eq!(data.goods_data[0].buy_value, rust_decimal_macros::dec!(5));
+        assert_eq!(labels, [contents.rfind('0')]);
+    }
+
+    #[test]
+    fn syntax_error_points_into_whole_file() {
+        let contents = r##"#tooltips
+"ore": "Dug up"
+#goods
+"a"
+    icon = = "a"
+    name = "A"
+"##;
+        let (_, labels) = parse_with_labels(contents);
+        // The icon is dropped along with the broken field
+        assert_eq!(labels, [contents.find("= \"a\""), contents.find("\"a\"")]);
+    }
+
+    #[test]
+    fn token_error_points_into_whole_file() {
+        let contents = r##"#tooltips
+"ore": "Dug up"
+#goods
+"a"
+    icon = "a" @
+    name = "A"
+"##;
+        let (_, labels) = parse_with_labels(contents);
+        assert_eq!(labels, [contents.find('@')]);
+    }
 }
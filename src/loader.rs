@@ -0,0 +1,520 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    fs, io,
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use miette::{Diagnostic, NamedSource, SourceSpan};
+use thiserror::Error;
+
+use crate::{
+    ParseData,
+    common::Location,
+    graph::{Edges, dot_quoted},
+    include::{Include, includes},
+    parse_with_constants,
+};
+
+/// Extension script files are expected to have unless told otherwise
+pub const DEFAULT_EXTENSION: &str = "txt";
+
+pub use diagnostics::*;
+
+mod diagnostics {
+    // Kept apart for the same reason as the diagnostics in the crate root
+    #![allow(unused_assignments)]
+
+    use super::*;
+
+    /// A file or directory that could not be read
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("Could not read {}", path.display())]
+    #[diagnostic(code(pronytic::io))]
+    pub struct ReadError {
+        pub path: PathBuf,
+        #[source]
+        pub source: io::Error,
+    }
+
+    /// An `#include` naming a file that could not be read
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("Could not include {}", path.display())]
+    #[diagnostic(code(pronytic::include::missing))]
+    pub struct MissingIncludeError {
+        #[source_code]
+        pub src: NamedSource<Arc<str>>,
+        pub path: PathBuf,
+        #[label("Included here")]
+        pub bad_bit: SourceSpan,
+        #[source]
+        pub source: io::Error,
+    }
+
+    /// Files that include each other in a loop, the include that closes it is skipped
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("Files include each other in a loop: {}", files.join(" -> "))]
+    #[diagnostic(
+        code(pronytic::include::cycle),
+        help("Move what both files need into a file that neither of them includes")
+    )]
+    pub struct IncludeCycleError {
+        #[source_code]
+        pub src: NamedSource<Arc<str>>,
+        /// From the file first included to the one included again
+        pub files: Vec<String>,
+        #[label("Included again here")]
+        pub bad_bit: SourceSpan,
+    }
+
+    /// Every problem found while loading content,
+    /// returned instead of the content if anything went wrong
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("Content could not be loaded ({} problems found)", errors.len())]
+    #[diagnostic(code(pronytic::load))]
+    pub struct LoadReport {
+        #[related]
+        pub errors: Vec<miette::Report>,
+        /// Everything that loaded successfully despite the errors
+        pub content: Box<LoadedContent>,
+    }
+}
+
+/// Where a script file was loaded from
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Origin {
+    /// The root content directory
+    Base,
+    /// A mod directory, named after the directory
+    Mod(String),
+}
+
+/// A script file that was loaded, in the order it was loaded
+#[derive(Clone, Debug)]
+pub struct LoadedFile {
+    /// The name given to the parser, also the name in a [`Location`]
+    pub name: String,
+    pub path: PathBuf,
+    pub origin: Origin,
+}
+
+/// The combined data of every file loaded along with where each came from
+#[derive(Clone, Debug, Default)]
+pub struct LoadedContent {
+    pub data: ParseData,
+    /// Every file in load order, later files are applied on top of earlier ones.
+    /// Included files come before the file that includes them
+    pub files: Vec<LoadedFile>,
+    /// The names of the files each file includes, in the order they are written
+    pub includes: BTreeMap<String, Vec<String>>,
+}
+
+impl LoadedContent {
+    /// The file an entry was loaded from
+    pub fn provenance(&self, location: &Location) -> Option<&LoadedFile> {
+        self.files.iter().find(|f| f.name == location.file.name)
+    }
+
+    /// The files each file includes
+    pub fn include_graph(&self) -> Edges<'_> {
+        self.includes
+            .iter()
+            .map(|(file, included)| (file.as_str(), included.iter().map(String::as_str).collect()))
+            .collect()
+    }
+
+    /// The include graph in Graphviz DOT, files point at the files they include
+    pub fn includes_to_dot(&self) -> String {
+        let mut dot = String::from("digraph includes {\n");
+        for file in &self.files {
+            let _ = writeln!(dot, "    {};", dot_quoted(&file.name));
+        }
+        for (file, included) in &self.includes {
+            for include in included {
+                let _ = writeln!(dot, "    {} -> {};", dot_quoted(file), dot_quoted(include));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// Finds and parses the script files of the base content and any mods.
+///
+/// The base content is loaded first then each mod in the order they were added.
+/// Inside a directory files are loaded in order of their path,
+/// so the same content always loads the same way
+#[derive(Clone, Debug)]
+pub struct Loader {
+    root: PathBuf,
+    mods: Vec<PathBuf>,
+    extension: String,
+}
+
+impl Loader {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Loader {
+            root: root.into(),
+            mods: Vec::new(),
+            extension: DEFAULT_EXTENSION.to_string(),
+        }
+    }
+
+    /// Adds a mod directory to load after everything added before it
+    pub fn with_mod(mut self, directory: impl Into<PathBuf>) -> Self {
+        self.mods.push(directory.into());
+        self
+    }
+
+    /// Only files with this extension are treated as scripts
+    pub fn with_extension(mut self, extension: impl Into<String>) -> Self {
+        self.extension = extension.into();
+        self
+    }
+
+    /// The script files that would be loaded, in load order
+    pub fn discover(&self) -> Result<Vec<LoadedFile>, ReadError> {
+        let mut files = Vec::new();
+        let directories = std::iter::once((&self.root, Origin::Base))
+            .chain(self.mods.iter().map(|m| (m, Origin::Mod(mod_name(m)))));
+        for (directory, origin) in directories {
+            let mut paths = Vec::new();
+            self.find_scripts(directory, &mut paths)?;
+            // Named the same way as included files so each file only has one name
+            let mut paths: Vec<_> = paths.iter().map(|p| normalize(p)).collect();
+            paths.sort();
+            files.extend(paths.into_iter().map(|path| LoadedFile {
+                name: path.display().to_string(),
+                path,
+                origin: origin.clone(),
+            }));
+        }
+        Ok(files)
+    }
+
+    /// Parses every script file and combines them in load order.
+    /// Problems are collected from every file,
+    /// whatever could still be parsed is kept in the report's content
+    pub fn load(&self) -> Result<LoadedContent, LoadReport> {
+        let mut content = LoadedContent::default();
+        let mut errors = Vec::new();
+        let files = match self.discover() {
+            Ok(files) => files,
+            Err(e) => {
+                return Err(LoadReport {
+                    errors: vec![e.into()],
+                    content: Box::new(content),
+                });
+            }
+        };
+
+        for file in files {
+            self.load_file(file, &mut content, &mut errors, &mut Vec::new());
+        }
+        errors.extend(content.data.take_extension_cycles());
+
+        if errors.is_empty() {
+            Ok(content)
+        } else {
+            Err(LoadReport {
+                errors,
+                content: Box::new(content),
+            })
+        }
+    }
+
+    /// Loads a file after the files it includes, unless it was already loaded.
+    /// `including` holds the files whose includes are being loaded, outermost first
+    fn load_file(
+        &self,
+        file: LoadedFile,
+        content: &mut LoadedContent,
+        errors: &mut Vec<miette::Report>,
+        including: &mut Vec<LoadedFile>,
+    ) {
+        if content
+            .files
+            .iter()
+            .any(|f| normalize(&f.path) == normalize(&file.path))
+        {
+            return;
+        }
+        let contents = match fs::read_to_string(&file.path) {
+            Ok(c) => c,
+            Err(source) => {
+                errors.push(
+                    ReadError {
+                        path: file.path,
+                        source,
+                    }
+                    .into(),
+                );
+                return;
+            }
+        };
+
+        let directory = file.path.parent().unwrap_or(Path::new(""));
+        for Include { path, span } in includes(&file.name, &contents) {
+            let path = normalize(&directory.join(path));
+            let src = || NamedSource::new(&file.name, Arc::from(contents.as_str()));
+            content
+                .includes
+                .entry(file.name.clone())
+                .or_default()
+                .push(path.display().to_string());
+            if let Some(i) = including
+                .iter()
+                .chain([&file])
+                .position(|f| normalize(&f.path) == path)
+            {
+                let files = including[i..]
+                    .iter()
+                    .chain([&file])
+                    .map(|f| f.name.clone())
+                    .chain([path.display().to_string()])
+                    .collect();
+                errors.push(
+                    IncludeCycleError {
+                        src: src(),
+                        files,
+                        bad_bit: span,
+                    }
+                    .into(),
+                );
+                continue;
+            }
+            if let Err(source) = fs::metadata(&path) {
+                errors.push(
+                    MissingIncludeError {
+                        src: src(),
+                        path,
+                        bad_bit: span,
+                        source,
+                    }
+                    .into(),
+                );
+                continue;
+            }
+            let included = LoadedFile {
+                name: path.display().to_string(),
+                path,
+                origin: file.origin.clone(),
+            };
+            including.push(file.clone());
+            self.load_file(included, content, errors, including);
+            including.pop();
+        }
+
+        let constants = content.data.constant_values();
+        match parse_with_constants(&file.name, &contents, &constants) {
+            Ok(data) => errors.extend(content.data.combine(data)),
+            Err(mut report) => {
+                let combined = content.data.combine(std::mem::take(&mut *report.data));
+                errors.push(report.into());
+                errors.extend(combined);
+            }
+        }
+        content.files.push(file);
+    }
+
+    fn find_scripts(&self, directory: &Path, paths: &mut Vec<PathBuf>) -> Result<(), ReadError> {
+        let read_error = |source| ReadError {
+            path: directory.to_path_buf(),
+            source,
+        };
+        for entry in fs::read_dir(directory).map_err(read_error)? {
+            let path = entry.map_err(read_error)?.path();
+            if path.is_dir() {
+                self.find_scripts(&path, paths)?;
+            } else if path
+                .extension()
+                .is_some_and(|e| e == self.extension.as_str())
+            {
+                paths.push(path);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn mod_name(directory: &Path) -> String {
+    directory
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| directory.display().to_string())
+}
+
+/// Resolves `.` and `..` in a path without touching the file system,
+/// so a file has the same path however it is included
+fn normalize(path: &Path) -> PathBuf {
+    let mut normal = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir if normal.file_name().is_some() => {
+                normal.pop();
+            }
+            other => normal.push(other),
+        }
+    }
+    normal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ParseReport;
+
+    /// Writes `files` under a new directory, returning its path
+    fn write(name: &str, files: &[(&str, &str)]) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("pronytic-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for (path, contents) in files {
+            let path = root.join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn included_file_has_same_name_as_discovered() {
+        let root = write(
+            "same-name",
+            &[
+                ("a.txt", "#include \"b.txt\"\n"),
+                (
+                    "b.txt",
+                    "#goods\n\"ore\"\n    name = \"Ore\"\n    icon = \"ore\"\n",
+                ),
+            ],
+        );
+        let content = Loader::new(root.join(".")).load().unwrap();
+        let names: Vec<_> = content.files.iter().map(|f| f.name.clone()).collect();
+        let b = root.join("b.txt").display().to_string();
+        assert_eq!(names, [b.clone(), root.join("a.txt").display().to_string()]);
+        assert_eq!(
+            content.includes.values().flatten().collect::<Vec<_>>(),
+            [&b]
+        );
+        assert_eq!(content.data.goods_data.len(), 1);
+    }
+
+    #[test]
+    fn mods_load_after_base_in_order_added() {
+        let good = |name: &str, replace: &str| {
+            format!("#goods\n{replace}\"ore\"\n    name = \"{name}\"\n    icon = \"ore\"\n")
+        };
+        let base = write(
+            "base",
+            &[
+                ("b.txt", &good("Ore", "")),
+                (
+                    "a/c.txt",
+                    "#goods\n\"coal\"\n    name = \"Coal\"\n    icon = \"coal\"\n",
+                ),
+            ],
+        );
+        let first = write("first-mod", &[("goods.txt", &good("Rock", "replace "))]);
+        let second = write("second-mod", &[("goods.txt", &good("Stone", "replace "))]);
+
+        let content = Loader::new(&base)
+            .with_mod(&first)
+            .with_mod(&second)
+            .load()
+            .unwrap();
+        let files: Vec<_> = content
+            .files
+            .iter()
+            .map(|f| (f.path.clone(), f.origin.clone()))
+            .collect();
+        assert_eq!(
+            files,
+            [
+                (base.join("a/c.txt"), Origin::Base),
+                (base.join("b.txt"), Origin::Base),
+                (first.join("goods.txt"), Origin::Mod(mod_name(&first))),
+                (second.join("goods.txt"), Origin::Mod(mod_name(&second))),
+            ]
+        );
+
+        let i = content
+            .data
+            .goods_data
+            .iter()
+            .position(|g| g.id == "ore")
+            .unwrap();
+        assert_eq!(content.data.goods_data[i].name, "Stone");
+        let loaded_from = content
+            .provenance(&content.data.locations.goods_data[i])
+            .unwrap();
+        assert_eq!(loaded_from.path, second.join("goods.txt"));
+    }
+
+    #[test]
+    fn include_cycle_reported_once() {
+        let root = write(
+            "cycle",
+            &[
+                ("a.txt", "#include \"b.txt\"\n"),
+                ("b.txt", "#include \"a.txt\"\n"),
+            ],
+        );
+        let report = Loader::new(&root).load().unwrap_err();
+        let [error] = &report.errors[..] else {
+            panic!("expected one error, found {:?}", report.errors);
+        };
+        let cycle = error.downcast_ref::<IncludeCycleError>().unwrap();
+        let name = |file: &str| root.join(file).display().to_string();
+        assert_eq!(cycle.files, [name("a.txt"), name("b.txt"), name("a.txt")]);
+        assert_eq!(cycle.src.name(), name("b.txt"));
+        assert_eq!(cycle.bad_bit.offset(), "#include ".len());
+        assert_eq!(report.content.files.len(), 2);
+    }
+
+    #[test]
+    fn missing_include_points_at_path() {
+        let contents =
+            "#include \"gone.txt\"\n#goods\n\"ore\"\n    name = \"Ore\"\n    icon = \"ore\"\n";
+        let root = write("missing", &[("a.txt", contents)]);
+        let report = Loader::new(&root).load().unwrap_err();
+        let [error] = &report.errors[..] else {
+            panic!("expected one error, found {:?}", report.errors);
+        };
+        let missing = error.downcast_ref::<MissingIncludeError>().unwrap();
+        assert_eq!(missing.path, root.join("gone.txt"));
+        assert_eq!(Some(missing.bad_bit.offset()), contents.find("\"gone"));
+        assert_eq!(report.content.data.goods_data.len(), 1);
+    }
+
+    #[test]
+    fn includes_resolve_from_including_file() {
+        let root = write(
+            "relative",
+            &[
+                ("a.txt", "#include \"shared/b.txt\"\n"),
+                ("shared/b.txt", "#include \"c.txt\"\n"),
+                ("shared/c.txt", "#goods\n\"ore\"\n    icon = = \"ore\"\n"),
+            ],
+        );
+        let report = Loader::new(&root).load().unwrap_err();
+        let name = |file: &str| root.join(file).display().to_string();
+        let graph = report.content.include_graph();
+        assert_eq!(
+            graph[name("a.txt").as_str()],
+            [name("shared/b.txt").as_str()].into()
+        );
+        assert_eq!(
+            graph[name("shared/b.txt").as_str()],
+            [name("shared/c.txt").as_str()].into()
+        );
+
+        // Problems in an included file are reported against that file
+        let [error] = &report.errors[..] else {
+            panic!("expected one error, found {:?}", report.errors);
+        };
+        let parse = error.downcast_ref::<ParseReport>().unwrap();
+        assert_eq!(parse.file_name, name("shared/c.txt"));
+    }
+}
@@ -0,0 +1,673 @@
+//! A language server for script files.
+//!
+//! The `pronytic-lsp` binary speaks it over stdio, [`run`] takes any
+//! [`Connection`] so a client can also drive it in memory through [`Connection::memory`].
+//! Diagnostics are published when a file is opened or saved,
+//! those not tied to any file are sent to the client's log instead.
+//! Keywords of the section the cursor is in are completed
+//! and good, tech and planet type ids go to where they are defined
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    error::Error,
+    fs,
+    path::PathBuf,
+};
+
+use lsp_server::{Connection, ErrorCode, Message, Notification, Request, RequestId, Response};
+use lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionOptions, CompletionParams, Diagnostic,
+    DiagnosticSeverity, DidChangeTextDocumentParams, DidCloseTextDocumentParams,
+    DidOpenTextDocumentParams, DidSaveTextDocumentParams, GotoDefinitionParams, InitializeParams,
+    Location as LspLocation, LogMessageParams, MessageType, NumberOrString, OneOf, Position,
+    PublishDiagnosticsParams, Range, SaveOptions, ServerCapabilities, TextDocumentSyncCapability,
+    TextDocumentSyncKind, TextDocumentSyncOptions, Url,
+    notification::{
+        DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, DidSaveTextDocument,
+        LogMessage, Notification as _, PublishDiagnostics,
+    },
+    request::{Completion, GotoDefinition, Request as _},
+};
+use miette::Severity;
+
+use crate::{
+    ParseData,
+    common::Location,
+    expression::Constants,
+    loader::{LoadedFile, Loader},
+    parse_with_constants,
+};
+
+/// Runs the server until the client asks it to shut down
+pub fn run(connection: &Connection) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let (id, params) = connection.initialize_start()?;
+    let params: InitializeParams = serde_json::from_value(params)?;
+    connection.initialize_finish(
+        id,
+        serde_json::json!({
+            "capabilities": capabilities(),
+            "serverInfo": { "name": "pronytic-lsp", "version": env!("CARGO_PKG_VERSION") },
+        }),
+    )?;
+
+    let mut server = Server::new(workspace_root(&params));
+    server.index();
+    server.publish(connection)?;
+
+    for message in &connection.receiver {
+        match message {
+            Message::Request(request) => {
+                if connection.handle_shutdown(&request)? {
+                    return Ok(());
+                }
+                connection.sender.send(server.respond(request).into())?;
+            }
+            Message::Notification(notification) => {
+                if server.notified(notification) {
+                    server.publish(connection)?;
+                }
+            }
+            Message::Response(_) => {}
+        }
+    }
+    Ok(())
+}
+
+fn capabilities() -> ServerCapabilities {
+    ServerCapabilities {
+        text_document_sync: Some(TextDocumentSyncCapability::Options(
+            TextDocumentSyncOptions {
+                open_close: Some(true),
+                change: Some(TextDocumentSyncKind::FULL),
+                save: Some(
+                    SaveOptions {
+                        include_text: Some(true),
+                    }
+                    .into(),
+                ),
+                ..Default::default()
+            },
+        )),
+        completion_provider: Some(CompletionOptions::default()),
+        definition_provider: Some(OneOf::Left(true)),
+        ..Default::default()
+    }
+}
+
+#[allow(deprecated)]
+fn workspace_root(params: &InitializeParams) -> Option<PathBuf> {
+    params
+        .workspace_folders
+        .iter()
+        .flatten()
+        .map(|f| &f.uri)
+        .chain(&params.root_uri)
+        .find_map(|uri| uri.to_file_path().ok())
+}
+
+/// A script file the server knows about
+struct Document {
+    uri: Url,
+    /// The text that was last parsed, what every span points into
+    text: String,
+    /// Edits made since the file was last saved
+    unsaved: Option<String>,
+    data: ParseData,
+    /// Problems found parsing the text
+    problems: Vec<Problem>,
+}
+
+impl Document {
+    /// The text as the client currently has it
+    fn current_text(&self) -> &str {
+        self.unsaved.as_deref().unwrap_or(&self.text)
+    }
+}
+
+/// A diagnostic along with the file it was found in
+#[derive(Clone)]
+struct Problem {
+    /// `None` when nothing ties the diagnostic to a file
+    file: Option<String>,
+    span: Option<(usize, usize)>,
+    message: String,
+    severity: DiagnosticSeverity,
+    code: Option<String>,
+}
+
+struct Server {
+    root: Option<PathBuf>,
+    /// Every document by the name it was parsed with
+    documents: BTreeMap<String, Document>,
+    /// The files of the workspace in the order the loader combines them, along with where each came from
+    loaded: Vec<LoadedFile>,
+    /// Files diagnostics were last published for, so fixed files can be cleared
+    published: HashSet<String>,
+}
+
+impl Server {
+    fn new(root: Option<PathBuf>) -> Self {
+        Server {
+            root,
+            documents: BTreeMap::new(),
+            loaded: Vec::new(),
+            published: HashSet::new(),
+        }
+    }
+
+    /// Parses every script file in the workspace
+    fn index(&mut self) {
+        let Some(root) = &self.root else { return };
+        // Loading rather than discovering gives included files before the files including them
+        let files = match Loader::new(root).load() {
+            Ok(content) => content.files,
+            Err(report) => report.content.files,
+        };
+        for file in &files {
+            let (Ok(text), Ok(uri)) = (
+                fs::read_to_string(&file.path),
+                Url::from_file_path(&file.path),
+            ) else {
+                continue;
+            };
+            self.update(file.name.clone(), uri, text);
+        }
+        self.loaded = files;
+    }
+
+    /// Every document in the order the loader combines them,
+    /// followed by those it doesn't know about by name
+    fn in_load_order(&self) -> impl Iterator<Item = (&str, &Document)> {
+        let loaded = self.loaded.iter().filter_map(|file| {
+            let document = self.documents.get(&file.name)?;
+            Some((file.name.as_str(), document))
+        });
+        let others = self
+            .documents
+            .iter()
+            .filter(|(name, _)| !self.loaded.iter().any(|file| &file.name == *name))
+            .map(|(name, document)| (name.as_str(), document));
+        loaded.chain(others)
+    }
+
+    /// The data of every document combined the way the loader would,
+    /// along with the problems found combining it
+    fn combined(&self, problems: &mut Vec<Problem>) -> ParseData {
+        // Problems combining a document come from the changes it makes
+        let mut data = ParseData::default();
+        for (name, document) in self.in_load_order() {
+            for error in data.combine(document.data.clone()) {
+                collect(error.as_ref(), Some(name), problems);
+            }
+        }
+        data
+    }
+
+    /// Parses a document's text, replacing what was known about it
+    fn update(&mut self, name: String, uri: Url, text: String) {
+        // Documents are parsed one at a time as they change,
+        // so the constants of every other document can be used rather than only those loaded before it
+        let constants: Constants = self
+            .documents
+            .iter()
+            .filter(|(other, _)| **other != name)
+            .flat_map(|(_, d)| d.data.constant_values())
+            .collect();
+        let (data, errors) = match parse_with_constants(&name, &text, &constants) {
+            Ok(data) => (data, Vec::new()),
+            Err(report) => (*report.data, report.errors),
+        };
+        let mut problems = Vec::new();
+        for error in &errors {
+            collect(error.as_ref(), Some(&name), &mut problems);
+        }
+        for warning in &data.warnings {
+            collect(warning, Some(&name), &mut problems);
+        }
+        self.documents.insert(
+            name,
+            Document {
+                uri,
+                text,
+                unsaved: None,
+                data,
+                problems,
+            },
+        );
+    }
+
+    /// Handles a notification, returning whether diagnostics should be published
+    fn notified(&mut self, notification: Notification) -> bool {
+        match notification.method.as_str() {
+            DidOpenTextDocument::METHOD => {
+                let Ok(params) =
+                    notification.extract::<DidOpenTextDocumentParams>(DidOpenTextDocument::METHOD)
+                else {
+                    return false;
+                };
+                let document = params.text_document;
+                self.update(name_of(&document.uri), document.uri, document.text);
+                true
+            }
+            DidChangeTextDocument::METHOD => {
+                let Ok(mut params) = notification
+                    .extract::<DidChangeTextDocumentParams>(DidChangeTextDocument::METHOD)
+                else {
+                    return false;
+                };
+                let name = name_of(&params.text_document.uri);
+                if let (Some(document), Some(change)) =
+                    (self.documents.get_mut(&name), params.content_changes.pop())
+                {
+                    document.unsaved = Some(change.text);
+                }
+                false
+            }
+            DidSaveTextDocument::METHOD => {
+                let Ok(params) =
+                    notification.extract::<DidSaveTextDocumentParams>(DidSaveTextDocument::METHOD)
+                else {
+                    return false;
+                };
+                let uri = params.text_document.uri;
+                let name = name_of(&uri);
+                let text = params
+                    .text
+                    .or_else(|| self.documents.get_mut(&name)?.unsaved.take())
+                    .or_else(|| fs::read_to_string(uri.to_file_path().ok()?).ok());
+                match text {
+                    Some(text) => {
+                        self.update(name, uri, text);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            DidCloseTextDocument::METHOD => {
+                if let Ok(params) =
+                    notification.extract::<DidCloseTextDocumentParams>(DidCloseTextDocument::METHOD)
+                    && let Some(document) =
+                        self.documents.get_mut(&name_of(&params.text_document.uri))
+                {
+                    document.unsaved = None;
+                }
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn respond(&self, request: Request) -> Response {
+        let id = request.id.clone();
+        match request.method.as_str() {
+            Completion::METHOD => match request.extract::<CompletionParams>(Completion::METHOD) {
+                Ok((id, params)) => Response::new_ok(id, self.complete(&params)),
+                Err(e) => invalid_params(id, e),
+            },
+            GotoDefinition::METHOD => {
+                match request.extract::<GotoDefinitionParams>(GotoDefinition::METHOD) {
+                    Ok((id, params)) => Response::new_ok(id, self.definitions(&params)),
+                    Err(e) => invalid_params(id, e),
+                }
+            }
+            method => Response::new_err(
+                id,
+                ErrorCode::MethodNotFound as i32,
+                format!("{method} is not supported"),
+            ),
+        }
+    }
+
+    /// The keywords of the section the cursor is in, nothing is offered inside a string
+    fn complete(&self, params: &CompletionParams) -> Vec<CompletionItem> {
+        let at = &params.text_document_position;
+        let Some(document) = self.documents.get(&name_of(&at.text_document.uri)) else {
+            return Vec::new();
+        };
+        let text = document.current_text();
+        let before = &text[..offset(text, at.position)];
+        let line = before.rsplit('\n').next().unwrap_or_default();
+        if quotes(line).count() % 2 == 1 {
+            return Vec::new();
+        }
+
+        let header = before
+            .lines()
+            .rev()
+            .map(str::trim_start)
+            .find(|l| l.starts_with('#'))
+            .and_then(|l| l.split_whitespace().next());
+        header
+            .and_then(ParseData::section_keywords)
+            .unwrap_or_default()
+            .iter()
+            .map(|keyword| CompletionItem {
+                label: keyword.to_string(),
+                kind: Some(CompletionItemKind::KEYWORD),
+                ..Default::default()
+            })
+            .collect()
+    }
+
+    /// Where the good, tech or planet type named by the string under the cursor is defined
+    fn definitions(&self, params: &GotoDefinitionParams) -> Vec<LspLocation> {
+        let at = &params.text_document_position_params;
+        let Some(document) = self.documents.get(&name_of(&at.text_document.uri)) else {
+            return Vec::new();
+        };
+        let text = document.current_text();
+        let Some(id) = string_at(text, offset(text, at.position)) else {
+            return Vec::new();
+        };
+
+        self.documents
+            .values()
+            .flat_map(|d| {
+                let locations = &d.data.locations;
+                locations
+                    .goods_data
+                    .iter()
+                    .chain(&locations.tech_data)
+                    .chain(&locations.planet_type_data)
+            })
+            .filter(|location| id_text(location) == id)
+            .filter_map(|location| {
+                let uri = self.documents.get(&location.file.name)?.uri.clone();
+                let contents = &location.file.contents;
+                let id = location.span.id;
+                Some(LspLocation {
+                    uri,
+                    range: range(contents, id.offset(), id.offset() + id.len()),
+                })
+            })
+            .collect()
+    }
+
+    /// Publishes the problems of every document, along with those found validating
+    /// the whole workspace, and clears documents that no longer have any
+    fn publish(&mut self, connection: &Connection) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let mut problems: Vec<Problem> = self
+            .documents
+            .values()
+            .flat_map(|d| d.problems.iter().cloned())
+            .collect();
+
+        let data = self.combined(&mut problems);
+        if let Err(report) = data.validate() {
+            for error in &report.errors {
+                collect(error.as_ref(), None, &mut problems);
+            }
+        }
+
+        let mut by_file: BTreeMap<&str, Vec<Diagnostic>> = BTreeMap::new();
+        for name in &self.published {
+            by_file.entry(name).or_default();
+        }
+        for problem in &problems {
+            let Some(file) = &problem.file else {
+                log(connection, problem)?;
+                continue;
+            };
+            let Some(document) = self.documents.get(file) else {
+                continue;
+            };
+            let (start, end) = problem.span.unwrap_or((0, 0));
+            by_file.entry(file).or_default().push(Diagnostic {
+                range: range(&document.text, start, end),
+                severity: Some(problem.severity),
+                code: problem.code.clone().map(NumberOrString::String),
+                source: Some("pronytic".to_string()),
+                message: problem.message.clone(),
+                ..Default::default()
+            });
+        }
+
+        let mut published = HashSet::new();
+        for (name, diagnostics) in by_file {
+            let Some(document) = self.documents.get(name) else {
+                continue;
+            };
+            if !diagnostics.is_empty() {
+                published.insert(name.to_string());
+            }
+            let params = PublishDiagnosticsParams::new(document.uri.clone(), diagnostics, None);
+            connection
+                .sender
+                .send(Notification::new(PublishDiagnostics::METHOD.to_string(), params).into())?;
+        }
+        self.published = published;
+        Ok(())
+    }
+}
+
+fn invalid_params(id: RequestId, error: impl Error) -> Response {
+    Response::new_err(id, ErrorCode::InvalidParams as i32, error.to_string())
+}
+
+/// The name a file is parsed with, the same name the loader gives it
+fn name_of(uri: &Url) -> String {
+    uri.to_file_path()
+        .map(|p| p.display().to_string())
+        .unwrap_or_else(|_| uri.to_string())
+}
+
+/// The id of an entry as written, without its quotes
+fn id_text(location: &Location) -> &str {
+    let id = location.span.id;
+    location.file.contents[id.offset()..id.offset() + id.len()].trim_matches('"')
+}
+
+/// Sends a problem that isn't tied to any file to the client's log
+fn log(connection: &Connection, problem: &Problem) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let typ = match problem.severity {
+        DiagnosticSeverity::WARNING => MessageType::WARNING,
+        DiagnosticSeverity::HINT => MessageType::INFO,
+        _ => MessageType::ERROR,
+    };
+    let params = LogMessageParams {
+        typ,
+        message: problem.message.clone(),
+    };
+    connection
+        .sender
+        .send(Notification::new(LogMessage::METHOD.to_string(), params).into())?;
+    Ok(())
+}
+
+/// Flattens a diagnostic and everything related to it into problems,
+/// diagnostics without a source of their own are put in `file`
+fn collect(diagnostic: &dyn miette::Diagnostic, file: Option<&str>, problems: &mut Vec<Problem>) {
+    let label = diagnostic.labels().and_then(|mut l| l.next());
+    let file = label
+        .as_ref()
+        .and_then(|l| {
+            let contents = diagnostic.source_code()?.read_span(l.inner(), 0, 0).ok()?;
+            contents.name().map(str::to_string)
+        })
+        .or_else(|| file.map(str::to_string));
+
+    let related: Vec<_> = diagnostic.related().into_iter().flatten().collect();
+    if label.is_some() || related.is_empty() {
+        let mut message = diagnostic.to_string();
+        if let Some(text) = label.as_ref().and_then(|l| l.label()) {
+            message = format!("{message}\n{text}");
+        }
+        if let Some(help) = diagnostic.help() {
+            message = format!("{message}\nhelp: {help}");
+        }
+        problems.push(Problem {
+            file: file.clone(),
+            span: label.map(|l| (l.offset(), l.offset() + l.len())),
+            message,
+            severity: match diagnostic.severity() {
+                Some(Severity::Warning) => DiagnosticSeverity::WARNING,
+                Some(Severity::Advice) => DiagnosticSeverity::HINT,
+                _ => DiagnosticSeverity::ERROR,
+            },
+            code: diagnostic.code().map(|c| c.to_string()),
+        });
+    }
+    for related in related {
+        collect(related, file.as_deref(), problems);
+    }
+}
+
+/// The string literal the offset is inside of, without its quotes
+fn string_at(text: &str, offset: usize) -> Option<&str> {
+    let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[offset..].find('\n').map_or(text.len(), |i| offset + i);
+    let quotes: Vec<usize> = quotes(&text[line_start..line_end])
+        .map(|i| line_start + i)
+        .collect();
+    quotes
+        .chunks_exact(2)
+        .find(|pair| pair[0] <= offset && offset <= pair[1])
+        .map(|pair| &text[pair[0] + 1..pair[1]])
+}
+
+/// Positions of the quotes in a line that aren't escaped with a backslash
+fn quotes(line: &str) -> impl Iterator<Item = usize> {
+    line.match_indices('"').map(|(i, _)| i).filter(|&i| {
+        let backslashes = line[..i].len() - line[..i].trim_end_matches('\\').len();
+        backslashes.is_multiple_of(2)
+    })
+}
+
+/// Converts a client position, counted in UTF-16 code units, to a byte offset
+fn offset(text: &str, position: Position) -> usize {
+    let line_start: usize = text
+        .split_inclusive('\n')
+        .take(position.line as usize)
+        .map(str::len)
+        .sum();
+    let mut units = 0;
+    for (i, c) in text[line_start..].char_indices() {
+        if units >= position.character as usize || c == '\n' {
+            return line_start + i;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+fn position(text: &str, offset: usize) -> Position {
+    let offset = offset.min(text.len());
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Position::new(
+        before.matches('\n').count() as u32,
+        before[line_start..].encode_utf16().count() as u32,
+    )
+}
+
+fn range(text: &str, start: usize, end: usize) -> Range {
+    Range::new(position(text, start), position(text, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use lsp_types::{
+        InitializedParams, TextDocumentItem,
+        notification::{Exit, Initialized},
+        request::{Initialize, Shutdown},
+    };
+
+    use super::*;
+
+    fn request<R: lsp_types::request::Request>(id: i32, params: R::Params) -> Message {
+        Request::new(id.into(), R::METHOD.to_string(), params).into()
+    }
+
+    fn notification<N: lsp_types::notification::Notification>(params: N::Params) -> Message {
+        Notification::new(N::METHOD.to_string(), params).into()
+    }
+
+    /// The next message that isn't a notification logged to the client
+    fn receive(client: &Connection) -> Message {
+        loop {
+            let message = client
+                .receiver
+                .recv_timeout(Duration::from_secs(10))
+                .unwrap();
+            match message {
+                Message::Notification(n) if n.method == LogMessage::METHOD => {}
+                message => return message,
+            }
+        }
+    }
+
+    #[test]
+    fn opened_document_gets_diagnostics() {
+        let (server, client) = Connection::memory();
+        let server = thread::spawn(move || run(&server).unwrap());
+
+        client
+            .sender
+            .send(request::<Initialize>(1, InitializeParams::default()))
+            .unwrap();
+        let Message::Response(response) = receive(&client) else {
+            panic!("expected the initialize response");
+        };
+        assert!(response.error.is_none());
+        client
+            .sender
+            .send(notification::<Initialized>(InitializedParams {}))
+            .unwrap();
+
+        let uri = Url::parse("file:///scripts/goods.txt").unwrap();
+        let text = "#goods\n\"ore\"\n    name = \"Ore\"\n";
+        let document = TextDocumentItem::new(uri.clone(), "pronytic".to_string(), 1, text.into());
+        client
+            .sender
+            .send(notification::<DidOpenTextDocument>(
+                DidOpenTextDocumentParams {
+                    text_document: document,
+                },
+            ))
+            .unwrap();
+        let Message::Notification(published) = receive(&client) else {
+            panic!("expected diagnostics to be published");
+        };
+        assert_eq!(published.method, PublishDiagnostics::METHOD);
+        let params: PublishDiagnosticsParams = serde_json::from_value(published.params).unwrap();
+        assert_eq!(params.uri, uri);
+        let missing = params
+            .diagnostics
+            .iter()
+            .find(|d| d.message.contains("`icon`"))
+            .unwrap();
+        assert_eq!(missing.range.start, Position::new(1, 0));
+
+        client.sender.send(request::<Shutdown>(2, ())).unwrap();
+        let Message::Response(response) = receive(&client) else {
+            panic!("expected the shutdown response");
+        };
+        assert_eq!(response.id, 2.into());
+        client.sender.send(notification::<Exit>(())).unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn documents_are_combined_in_load_order() {
+        let root = std::env::temp_dir().join(format!("pronytic-lsp-{}", std::process::id()));
+        fs::create_dir_all(&root).unwrap();
+        // Deleting before the good is defined would leave it in place
+        fs::write(
+            root.join("a.txt"),
+            "#include \"z.txt\"\n#goods\ndelete \"ore\"\n",
+        )
+        .unwrap();
+        fs::write(root.join("z.txt"), "#goods\n\"ore\"\n    name = \"Ore\"\n").unwrap();
+
+        let mut server = Server::new(Some(root.clone()));
+        server.index();
+        let data = server.combined(&mut Vec::new());
+        fs::remove_dir_all(&root).unwrap();
+
+        let names: Vec<_> = server.in_load_order().map(|(name, _)| name).collect();
+        assert!(names[0].ends_with("z.txt") && names[1].ends_with("a.txt"));
+        assert!(data.goods_data.is_empty());
+    }
+}
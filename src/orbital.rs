@@ -1,26 +1,48 @@
-use std::{fmt, str::FromStr};
+use std::fmt;
 
 use lalrpop_util::lalrpop_mod;
-use logos::{self, Logos};
+use logos::{self, Lexer, Logos};
 use rust_decimal::prelude::*;
 
 use crate::{
     LexicalError,
-    common::{DataParser, GoodConsumes, Temperature},
+    common::{
+        self, DataParser, GoodConsumes, LexExtras, NumberToken, ParsedEntries, Patchable,
+        Temperature,
+    },
+    printer::{PrintScript, ScriptWriter, decimal, good_amount, quoted, temperature},
+    schema::FieldSchema,
 };
 
 #[derive(Logos, Clone, Debug, PartialEq)]
-#[logos(skip r"[\s\t\f]+", error = LexicalError)]
+#[logos(skip r"[\s\t\f]+", error = LexicalError, extras = LexExtras)]
 #[logos(skip r"//[^\n\r]*")]
+#[logos(skip(r"///([^/\n\r][^\n\r]*)?", common::doc_comment))]
+#[logos(skip(r"/\*", common::block_comment))]
 pub enum OrbitalToken {
-    #[regex(r#""[^"]*""#, |lex| lex.slice().trim_matches('"').to_string())]
+    #[token("replace")]
+    Replace,
+    #[token("patch")]
+    Patch,
+    #[token("delete")]
+    Delete,
+
+    #[regex(r#""([^"\\]|\\.)*""#, common::string)]
+    #[regex(r#""""([^"]|"[^"]|""[^"])*""""#, common::string)]
     String(String),
 
-    #[regex(r"(\d+)", |lex|lex.slice().parse::<u8>().expect("parsing u8"), priority = 5)]
+    #[regex(r"[0-9][0-9_]*([eE]\+?[0-9]+)?", common::integer::<u8>, priority = 5)]
     Number(u8),
 
-    #[regex(r"(\d+\.?\d*)", |lex| Decimal::from_str(lex.slice()).expect("parsed_decimal"), priority = 4)]
+    #[regex(
+        r"-?[0-9][0-9_]*(\.[0-9_]*)?([eE][+-]?[0-9]+)?",
+        common::decimal,
+        priority = 4
+    )]
     DecimalNumber(Decimal),
+    #[regex(r"\$[A-Za-z_][A-Za-z0-9_]*", common::expression)]
+    #[regex(r"\$\(", common::expression)]
+    Expression,
 
     #[token("=")]
     Equal,
@@ -75,8 +97,19 @@ impl fmt::Display for OrbitalToken {
     }
 }
 
-lalrpop_mod!(pub orbital);
-#[derive(Clone, Default, Debug)]
+impl<'s> NumberToken<'s> for OrbitalToken {
+    fn number(lex: &mut Lexer<'s, Self>, value: Decimal) -> Self {
+        if value.scale() == 0 {
+            OrbitalToken::Number(common::fit(lex, value))
+        } else {
+            OrbitalToken::DecimalNumber(value)
+        }
+    }
+}
+
+lalrpop_mod!(#[allow(clippy::ptr_arg)] pub orbital);
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrbitalData {
     pub level: u8,
     pub name: String,
@@ -93,10 +126,13 @@ pub struct OrbitalData {
     pub temperature: Temperature,
     pub water: Decimal,
     pub breathability: Decimal,
+    /// Doc comments written above where the entry is defined or replaced
+    pub doc: Option<String>,
 }
 
 /// Differentiates between each field when parsing
 /// This allows fields to be done in arbitrary order in lalrpop files
+#[derive(Clone, Debug)]
 pub enum Field {
     Name(String),
     AssetLocation(String),
@@ -110,11 +146,97 @@ pub enum Field {
     Breathability(Decimal),
 }
 
+impl Patchable for OrbitalData {
+    type Key = u8;
+    type Field = Field;
+    fn key(&self) -> &Self::Key {
+        &self.level
+    }
+    fn set_key(&mut self, key: Self::Key) {
+        self.level = key;
+    }
+    fn apply(&mut self, field: Self::Field) {
+        match field {
+            Field::Name(n) => self.name = n,
+            Field::AssetLocation(a) => self.asset_location = a,
+            Field::Consumes(c) => self.costs = c,
+            Field::Time(t) => self.time = t,
+            Field::BuildingLimit(l) => self.building_limit = l,
+            Field::Magnetosphere(m) => self.magnetosphere = m,
+            Field::Atmosphere(a) => self.atmosphere = a,
+            Field::Water(w) => self.water = w,
+            Field::Temperature(t) => self.temperature = t,
+            Field::Breathability(b) => self.breathability = b,
+        }
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+    fn set_doc(&mut self, doc: Option<String>) {
+        self.doc = doc;
+    }
+}
+
 impl<'s> DataParser<'s> for OrbitalData {
     type Token = OrbitalToken;
+    const KEYWORDS: &'static [&'static str] = &[
+        "replace",
+        "patch",
+        "delete",
+        "name",
+        "asset_location",
+        "consumes",
+        "good_id",
+        "amount",
+        "time",
+        "building_limit",
+        "magnetosphere",
+        "atmosphere",
+        "temperature_kelvin",
+        "temperature_celsius",
+        "water",
+        "breathability",
+    ];
+    const SCHEMA: &'static [FieldSchema<Self>] = &[
+        FieldSchema::required("name"),
+        FieldSchema::required("asset_location"),
+        FieldSchema::recommended("time"),
+    ];
     fn parse_tokens(
         tokens: Vec<(usize, Self::Token, usize)>,
-    ) -> Result<Vec<OrbitalData>, lalrpop_util::ParseError<usize, Self::Token, String>> {
-        orbital::OrbitalDataParser::new().parse(tokens)
+        errors: &mut Vec<lalrpop_util::ErrorRecovery<usize, Self::Token, String>>,
+    ) -> ParsedEntries<Self, Self::Token> {
+        orbital::OrbitalDataParser::new().parse(errors, tokens)
+    }
+}
+
+impl PrintScript for OrbitalData {
+    const SECTION: &'static str = "#orbital";
+    fn print(&self, w: &mut ScriptWriter) {
+        w.block(format_args!("{} =", self.level), |w| {
+            w.field_unless_default("name", quoted(&self.name), self.name.is_empty());
+            w.field_unless_default(
+                "asset_location",
+                quoted(&self.asset_location),
+                self.asset_location.is_empty(),
+            );
+            w.list("consumes", &self.costs, good_amount);
+            w.field_unless_default("time", self.time, self.time == 0);
+            w.field_unless_default(
+                "building_limit",
+                self.building_limit,
+                self.building_limit == 0,
+            );
+            let values = [
+                ("magnetosphere", self.magnetosphere),
+                ("atmosphere", self.atmosphere),
+                ("water", self.water),
+                ("breathability", self.breathability),
+            ];
+            for (name, value) in values {
+                w.field_unless_default(name, decimal(value), value.is_zero());
+            }
+            temperature(w, &self.temperature);
+        });
     }
 }
@@ -1,19 +1,30 @@
-use std::{fmt, str::FromStr};
+use std::fmt;
 
 use lalrpop_util::lalrpop_mod;
 use rust_decimal::Decimal;
 
-use logos::Logos;
+use logos::{Lexer, Logos};
 
 use crate::{
     LexicalError,
-    common::{DataParser, GoodAbundance},
+    common::{self, DataParser, GoodAbundance, LexExtras, NumberToken, ParsedEntries, Patchable},
+    printer::{PrintScript, ScriptWriter, decimal, quoted},
+    schema::FieldSchema,
 };
 
 #[derive(Logos, Clone, Debug, PartialEq)]
-#[logos(skip r"[\s\t\f]+", error = LexicalError)]
+#[logos(skip r"[\s\t\f]+", error = LexicalError, extras = LexExtras)]
 #[logos(skip r"//[^\n\r]*")]
+#[logos(skip(r"///([^/\n\r][^\n\r]*)?", common::doc_comment))]
+#[logos(skip(r"/\*", common::block_comment))]
 pub enum PlanetTypeToken {
+    #[token("replace")]
+    Replace,
+    #[token("patch")]
+    Patch,
+    #[token("delete")]
+    Delete,
+
     #[token("true")]
     True,
     #[token("false")]
@@ -22,12 +33,18 @@ pub enum PlanetTypeToken {
     #[token("=")]
     Equal,
 
-    #[regex(r"(-?\d+\.?\d*)", |lex| Decimal::from_str(lex.slice()).expect("parsed_decimal"), priority = 4)]
+    #[regex(
+        r"-?[0-9][0-9_]*(\.[0-9_]*)?([eE][+-]?[0-9]+)?",
+        common::decimal,
+        priority = 4
+    )]
     DecimalNumber(Decimal),
+    #[regex(r"\$[A-Za-z_][A-Za-z0-9_]*", common::expression)]
+    #[regex(r"\$\(", common::expression)]
+    Expression,
 
-    #[regex(r"[1-9][0-9]*", |lex| Decimal::from_str(lex.slice()).expect("parsed_decimal"))]
-    Number(Decimal),
-    #[regex(r#""[^"]*""#, |lex| lex.slice().trim_matches('"').to_string())]
+    #[regex(r#""([^"\\]|\\.)*""#, common::string)]
+    #[regex(r#""""([^"]|"[^"]|""[^"])*""""#, common::string)]
     Name(String),
 
     #[token("class")]
@@ -95,6 +112,10 @@ pub enum PlanetTypeToken {
 
     #[token("&")]
     Ampersand,
+    #[token("|")]
+    Pipe,
+    #[token("!")]
+    Exclamation,
 
     #[token("star_type")]
     StarType,
@@ -134,7 +155,13 @@ impl fmt::Display for PlanetTypeToken {
         write!(f, "{self:?}")
     }
 }
-lalrpop_mod!(pub planet_types);
+
+impl<'s> NumberToken<'s> for PlanetTypeToken {
+    fn number(_lex: &mut Lexer<'s, Self>, value: Decimal) -> Self {
+        PlanetTypeToken::DecimalNumber(value)
+    }
+}
+lalrpop_mod!(#[allow(clippy::ptr_arg)] pub planet_types);
 
 #[derive(Debug, Clone)]
 pub enum Field {
@@ -145,7 +172,8 @@ pub enum Field {
     Terraform(Vec<Branch>),
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PlanetTypeData {
     pub name: String,
     pub planet_class: PlanetClass,
@@ -153,19 +181,92 @@ pub struct PlanetTypeData {
     pub asset_location: String,
     pub setup_conditions: Vec<Branch>,
     pub terraform_conditions: Vec<Branch>,
+    /// Doc comments written above where the entry is defined or replaced
+    pub doc: Option<String>,
+}
+
+impl Patchable for PlanetTypeData {
+    type Key = String;
+    type Field = Field;
+    fn key(&self) -> &Self::Key {
+        &self.name
+    }
+    fn set_key(&mut self, key: Self::Key) {
+        self.name = key;
+    }
+    fn apply(&mut self, field: Self::Field) {
+        match field {
+            Field::AssetLocation(a) => self.asset_location = a,
+            Field::Setup(s) => self.setup_conditions.push(s),
+            Field::PlanetClass(p) => self.planet_class = p,
+            Field::GoodsAbundance(g) => self.abundances = g,
+            Field::Terraform(t) => self.terraform_conditions.extend(t),
+        }
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+    fn set_doc(&mut self, doc: Option<String>) {
+        self.doc = doc;
+    }
 }
 
 impl<'s> DataParser<'s> for PlanetTypeData {
     type Token = PlanetTypeToken;
+    const KEYWORDS: &'static [&'static str] = &[
+        "replace",
+        "patch",
+        "delete",
+        "true",
+        "false",
+        "class",
+        "set_asset",
+        "set_planet_type",
+        "goods_abundance",
+        "mean",
+        "std_dev",
+        "setup",
+        "on_terraform",
+        "if",
+        "else",
+        "EQ",
+        "NE",
+        "GT",
+        "GE",
+        "LT",
+        "LE",
+        "IN",
+        "star_type",
+        "oxygen_level",
+        "temperature_celsius",
+        "temperature_kelvin",
+        "water_level",
+        "magnetosphere",
+        "atmosphere",
+        "goods_base",
+        "rocky",
+        "atmospheric",
+        "gas",
+        "stored",
+        "stored_number",
+        "rand_of_string",
+    ];
+    const REPEATABLE_FIELDS: &'static [&'static str] = &["setup", "on_terraform"];
+    const SCHEMA: &'static [FieldSchema<Self>] = &[
+        FieldSchema::required("class"),
+        FieldSchema::recommended("set_asset"),
+    ];
     fn parse_tokens(
         tokens: Vec<(usize, Self::Token, usize)>,
-    ) -> Result<Vec<PlanetTypeData>, lalrpop_util::ParseError<usize, Self::Token, String>> {
-        planet_types::PlanetTypeListParser::new().parse(tokens)
+        errors: &mut Vec<lalrpop_util::ErrorRecovery<usize, Self::Token, String>>,
+    ) -> ParsedEntries<Self, Self::Token> {
+        planet_types::PlanetTypeListParser::new().parse(errors, tokens)
     }
 }
 
 /// The group the planet type falls under
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PlanetClass {
     #[default]
     Rocky,
@@ -173,18 +274,21 @@ pub enum PlanetClass {
     Gas,
 }
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Branch {
     pub if_conditions: Vec<IfCondition>,
     pub else_actions: Vec<Action>,
 }
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct IfCondition {
-    pub conditions: Vec<Condition>,
+    pub condition: Condition,
     pub actions: Vec<Action>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Condition {
     Eq(Value, Value),
     Gt(Value, Value),
@@ -192,9 +296,34 @@ pub enum Condition {
     Lt(Value, Value),
     Le(Value, Value),
     Ne(Value, Value),
+    /// The value is equal to one of the listed values
+    In(Value, Vec<Value>),
+    Not(Box<Condition>),
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+}
+
+impl Condition {
+    /// Every value compared anywhere in the condition, in the order written
+    pub fn values(&self) -> Vec<&Value> {
+        match self {
+            Condition::Eq(a, b)
+            | Condition::Ne(a, b)
+            | Condition::Gt(a, b)
+            | Condition::Ge(a, b)
+            | Condition::Lt(a, b)
+            | Condition::Le(a, b) => vec![a, b],
+            Condition::In(value, values) => [value].into_iter().chain(values).collect(),
+            Condition::Not(c) => c.values(),
+            Condition::And(conditions) | Condition::Or(conditions) => {
+                conditions.iter().flat_map(Condition::values).collect()
+            }
+        }
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Action {
     None,
     SetAsset(String),
@@ -204,7 +333,8 @@ pub enum Action {
     SetStoredRandom(String, Vec<String>),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Value {
     Decimal(Decimal),
     OxygenLevel,
@@ -220,3 +350,131 @@ pub enum Value {
     String(String),
     StringLookup(String),
 }
+
+impl PrintScript for PlanetTypeData {
+    const SECTION: &'static str = "#planet_types";
+    fn print(&self, w: &mut ScriptWriter) {
+        w.line(quoted(&self.name));
+        w.indented(|w| {
+            let class = match self.planet_class {
+                PlanetClass::Rocky => "rocky",
+                PlanetClass::Atmospheric => "atmospheric",
+                PlanetClass::Gas => "gas",
+            };
+            w.field_unless_default("class", class, self.planet_class == PlanetClass::default());
+            w.field_unless_default(
+                "set_asset",
+                quoted(&self.asset_location),
+                self.asset_location.is_empty(),
+            );
+            w.list("goods_abundance", &self.abundances, |g| {
+                format!(
+                    "{{ {} ( mean : {} std_dev : {} ) }}",
+                    quoted(&g.id),
+                    decimal(g.mean),
+                    decimal(g.std_dev)
+                )
+            });
+            for branch in &self.setup_conditions {
+                w.block("setup", |w| print_branch(w, branch));
+            }
+            if !self.terraform_conditions.is_empty() {
+                w.block("on_terraform", |w| {
+                    for branch in &self.terraform_conditions {
+                        print_branch(w, branch);
+                    }
+                });
+            }
+        });
+    }
+}
+
+/// Writes an if, its else ifs and else as one chain
+fn print_branch(w: &mut ScriptWriter, branch: &Branch) {
+    for (i, arm) in branch.if_conditions.iter().enumerate() {
+        let conditions = condition_text(&arm.condition);
+        match i {
+            0 => w.line(format_args!("if {conditions} {{")),
+            _ => w.line(format_args!("}} else if {conditions} {{")),
+        }
+        w.indented(|w| arm.actions.iter().for_each(|a| print_action(w, a)));
+    }
+    if !branch.else_actions.is_empty() {
+        w.line("} else {");
+        w.indented(|w| branch.else_actions.iter().for_each(|a| print_action(w, a)));
+    }
+    w.line("}");
+}
+
+fn print_action(w: &mut ScriptWriter, action: &Action) {
+    match action {
+        // Has no syntax of its own
+        Action::None => {}
+        Action::SetAsset(a) => w.field("set_asset", quoted(a)),
+        Action::SetPlanetType(p) => w.field("set_planet_type", quoted(p)),
+        Action::Branch(b) => print_branch(w, b),
+        Action::SetStored(key, value) => w.line(format_args!(
+            "stored [ {} ] = {}",
+            quoted(key),
+            quoted(value)
+        )),
+        Action::SetStoredRandom(key, values) => {
+            let values = values
+                .iter()
+                .map(|v| quoted(v).to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            w.line(format_args!("stored [ {} ] = {{ {values} }}", quoted(key)))
+        }
+    }
+}
+
+pub(crate) fn condition_text(condition: &Condition) -> String {
+    let joined = |conditions: &[Condition], separator| {
+        conditions
+            .iter()
+            .map(grouped_text)
+            .collect::<Vec<_>>()
+            .join(separator)
+    };
+    let (compare, first, second) = match condition {
+        Condition::Eq(a, b) => ("EQ", a, b),
+        Condition::Ne(a, b) => ("NE", a, b),
+        Condition::Gt(a, b) => ("GT", a, b),
+        Condition::Ge(a, b) => ("GE", a, b),
+        Condition::Lt(a, b) => ("LT", a, b),
+        Condition::Le(a, b) => ("LE", a, b),
+        Condition::In(value, values) => {
+            let values = values.iter().map(value_text).collect::<Vec<_>>().join(" ");
+            return format!("{} IN [ {values} ]", value_text(value));
+        }
+        Condition::Not(c) => return format!("!{}", grouped_text(c)),
+        Condition::And(conditions) => return joined(conditions, " & "),
+        Condition::Or(conditions) => return joined(conditions, " | "),
+    };
+    format!("{} {compare} {}", value_text(first), value_text(second))
+}
+
+/// Wraps `and` and `or` in brackets so they keep their grouping when nested
+fn grouped_text(condition: &Condition) -> String {
+    match condition {
+        Condition::And(_) | Condition::Or(_) => format!("( {} )", condition_text(condition)),
+        _ => condition_text(condition),
+    }
+}
+
+fn value_text(value: &Value) -> String {
+    match value {
+        Value::Decimal(d) => decimal(*d).to_string(),
+        Value::OxygenLevel => "oxygen_level".to_string(),
+        Value::TemperatureCelsius => "temperature_celsius".to_string(),
+        Value::TemperatureKelvin => "temperature_kelvin".to_string(),
+        Value::WaterLevel => "water_level".to_string(),
+        Value::Magnetosphere => "magnetosphere".to_string(),
+        Value::Atmosphere => "atmosphere".to_string(),
+        Value::GoodsAbundance(id) => format!("goods_base [ {} ]", quoted(id)),
+        Value::StarType => "star_type".to_string(),
+        Value::String(s) => quoted(s).to_string(),
+        Value::StringLookup(key) => format!("stored [ {} ]", quoted(key)),
+    }
+}
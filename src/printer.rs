@@ -0,0 +1,718 @@
+use std::fmt::{self, Display};
+
+use rust_decimal::Decimal;
+
+use crate::{
+    ParseData,
+    building::CustomGood,
+    common::{DataParser, GoodConsumes, PlanetFilter, TRIPLE_QUOTE, Temperature, dedent},
+    schema::Presence,
+};
+
+const INDENT: &str = "    ";
+
+/// Data that can be written back out as script text
+pub trait PrintScript {
+    /// The header of the section entries are written under
+    const SECTION: &'static str;
+
+    /// Writes the entry including its id or level,
+    /// parsing the text gives back an equal entry
+    fn print(&self, w: &mut ScriptWriter);
+}
+
+/// Builds up canonical script text one line at a time,
+/// keeping track of how deep the current block is
+#[derive(Default)]
+pub struct ScriptWriter {
+    out: String,
+    depth: usize,
+    /// Fields the current entry's schema expects to see even at their default
+    always: Vec<&'static str>,
+}
+
+impl ScriptWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn line(&mut self, text: impl Display) {
+        for _ in 0..self.depth {
+            self.out.push_str(INDENT);
+        }
+        self.out.push_str(&text.to_string());
+        self.out.push('\n');
+    }
+
+    pub fn field(&mut self, name: &str, value: impl Display) {
+        self.line(format_args!("{name} = {value}"));
+    }
+
+    /// Writes a field unless it is left at its default value,
+    /// fields the schema requires or recommends are always written
+    pub fn field_unless_default(&mut self, name: &str, value: impl Display, is_default: bool) {
+        if !is_default || self.always.contains(&name) {
+            self.field(name, value);
+        }
+    }
+
+    /// Writes `open {`, the indented body and a closing `}`
+    pub fn block(&mut self, open: impl Display, body: impl FnOnce(&mut Self)) {
+        self.line(format_args!("{open} {{"));
+        self.indented(body);
+        self.line("}");
+    }
+
+    /// Writes `name = [` with one item per line, nothing is written for an empty list
+    pub fn list<T>(&mut self, name: &str, items: &[T], item: impl Fn(&T) -> String) {
+        if items.is_empty() {
+            return;
+        }
+        self.line(format_args!("{name} = ["));
+        self.indented(|w| items.iter().for_each(|i| w.line(item(i))));
+        self.line("]");
+    }
+
+    pub fn indented(&mut self, body: impl FnOnce(&mut Self)) {
+        self.depth += 1;
+        body(self);
+        self.depth -= 1;
+    }
+
+    /// Writes an entry, remembering which of its fields the schema expects
+    pub fn entry<T>(&mut self, data: &T)
+    where
+        T: DataParser<'static> + PrintScript,
+    {
+        self.always = T::SCHEMA
+            .iter()
+            .filter(|f| f.presence != Presence::Optional)
+            .map(|f| f.name)
+            .collect();
+        for line in data.doc().iter().flat_map(|d| d.lines()) {
+            match line {
+                "" => self.line("///"),
+                line => self.line(format!("/// {line}")),
+            }
+        }
+        data.print(self);
+        self.always.clear();
+    }
+
+    /// Writes a section header followed by its entries, nothing is written without entries
+    pub fn section<T>(&mut self, entries: &[T])
+    where
+        T: DataParser<'static> + PrintScript,
+    {
+        if entries.is_empty() {
+            return;
+        }
+        self.gap();
+        self.line(T::SECTION);
+        for (i, e) in entries.iter().enumerate() {
+            if i > 0 {
+                self.gap();
+            }
+            self.entry(e);
+        }
+    }
+
+    /// Separates what comes next from what was written before with a blank line
+    fn gap(&mut self) {
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+    }
+
+    pub fn finish(self) -> String {
+        self.out
+    }
+}
+
+/// A string written as a quoted literal, text over several lines is written
+/// between triple quotes when it would read back the same
+pub struct Quoted<'a>(pub &'a str);
+
+impl Display for Quoted<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = self.0;
+        let multi_line = text.contains('\n') && !text.contains(TRIPLE_QUOTE);
+        if multi_line && dedent(&format!("\n{text}\n")) == text {
+            return write!(f, "{TRIPLE_QUOTE}\n{text}\n{TRIPLE_QUOTE}");
+        }
+        f.write_str("\"")?;
+        for c in text.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\r' => f.write_str("\\r")?,
+                '\t' => f.write_str("\\t")?,
+                '\0' => f.write_str("\\0")?,
+                c if c.is_control() => write!(f, "\\u{{{:x}}}", u32::from(c))?,
+                c => write!(f, "{c}")?,
+            }
+        }
+        f.write_str("\"")
+    }
+}
+
+pub fn quoted(s: &str) -> Quoted<'_> {
+    Quoted(s)
+}
+
+/// A number written with a decimal point,
+/// several sections only read whole numbers as integers
+pub struct DecimalLiteral(pub Decimal);
+
+impl Display for DecimalLiteral {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.0.scale() == 0 {
+            write!(f, "{}.0", self.0)
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+pub fn decimal(d: Decimal) -> DecimalLiteral {
+    DecimalLiteral(d)
+}
+
+/// Floats are written the shortest way that reads back as the same value
+pub fn float(f: f32) -> String {
+    let text = f.to_string();
+    if text.contains('.') {
+        text
+    } else {
+        format!("{text}.0")
+    }
+}
+
+pub fn boolean(b: bool) -> &'static str {
+    if b { "true" } else { "false" }
+}
+
+/// A good and an amount in the `{ good_id : "id" amount : 1.0 }` form
+pub fn good_amount(good: &GoodConsumes) -> String {
+    format!(
+        "{{ good_id : {} amount : {} }}",
+        quoted(&good.id),
+        decimal(good.amount)
+    )
+}
+
+/// A good and an amount in the shorter `{ "id" 1.0 }` form
+pub fn custom_good(good: &CustomGood) -> String {
+    format!("{{ {} {} }}", quoted(&good.id), decimal(good.amount))
+}
+
+pub fn planet_filter(filter: &PlanetFilter) -> String {
+    match filter {
+        PlanetFilter::PlanetSide(p) => quoted(p).to_string(),
+        PlanetFilter::Orbital(p) => format!("orbital ( {} )", quoted(p)),
+        PlanetFilter::AllOrbitals => "all_orbitals".to_string(),
+        PlanetFilter::AllPlanets => "all_planets".to_string(),
+    }
+}
+
+/// Temperatures are always written in kelvin, the unit they are stored in
+pub fn temperature(w: &mut ScriptWriter, temperature: &Temperature) {
+    w.field_unless_default(
+        "temperature_kelvin",
+        decimal(temperature.exact_kelvin()),
+        *temperature == Temperature::default(),
+    );
+}
+
+impl ParseData {
+    /// Writes every entry as canonical script text,
+    /// one section per type in a fixed order
+    pub fn to_script(&self) -> String {
+        let mut w = ScriptWriter::new();
+        w.section(&self.asteroid_mining);
+        w.section(&self.augmentations);
+        w.section(&self.building_data);
+        w.section(&self.constants);
+        w.section(&self.designation_data);
+        w.section(&self.goods_data);
+        w.section(&self.orbital_data);
+        w.section(&self.planet_type_data);
+        w.section(&self.rank_data);
+        w.section(&self.species_trait);
+        w.section(&self.ships);
+        w.section(&self.shipyard);
+        w.section(&self.shipyard_buildings);
+        w.section(&self.stapledon);
+        w.section(&self.stellar_system);
+        w.section(&self.tech_data);
+        // Every tooltips section is kept whole so each is parsed back on its own
+        for tooltips in &self.tooltips {
+            w.section(std::slice::from_ref(tooltips));
+        }
+        w.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ParseData, parse};
+
+    /// Parses `contents`, prints it and parses the printed text,
+    /// which should give back the same data
+    fn round_trip(contents: &str) -> ParseData {
+        let first = parse("test.txt", contents).unwrap();
+        let printed = first.to_script();
+        let second = parse("printed.txt", &printed).unwrap();
+        macro_rules! assert_same {
+            ($($field:ident),*) => {
+                $( assert_eq!(first.$field, second.$field, "{printed}"); )*
+            };
+        }
+        assert_same!(
+            asteroid_mining,
+            augmentations,
+            building_data,
+            constants,
+            designation_data,
+            goods_data,
+            orbital_data,
+            planet_type_data,
+            rank_data,
+            species_trait,
+            ships,
+            shipyard,
+            shipyard_buildings,
+            stapledon,
+            stellar_system,
+            tech_data,
+            tooltips
+        );
+        assert_eq!(printed, second.to_script());
+        second
+    }
+
+    #[test]
+    fn asteroid_mining() {
+        round_trip(
+            r#"#asteroid_mining
+1 = {
+    name = "Drill"
+    depot_asset = "depot"
+    ship_asset = "ship"
+    time = 4
+    power = 1.5
+    consumes = [ { good_id: "fuel" amount: 2.0 } ]
+    produces = [ { good_id: "ore" amount: 0.25 } ]
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn augmentations() {
+        round_trip(
+            r#"#augmentations
+"gills"
+    name = "Gills"
+    icon = "gills.png"
+    consumes = [ { good_id: "water" amount: 2 } ]
+    effects = { star_adapt add_trait = "aquatic" remove_trait = "dry" }
+"#,
+        );
+    }
+
+    #[test]
+    fn buildings() {
+        round_trip(
+            r#"#buildings
+"mine"
+    name = "Mine"
+    build_planets = [ "rocky" orbital("moon") all_orbitals ]
+    initial = true
+    unique = false
+    energy = -2.5
+    private_cost = 10.0
+    costs = [ { "steel" 5.0 } ]
+    consumes = [ { "power" 1.5 } ]
+    upkeep = [ { "credits" 0.5 } ]
+    produces = [ { "ore" 3.0 } ]
+    housing = 0
+    workers = 20
+    private_sector = true
+    magnetosphere_equilibrium = { added = 0.1 rate = 0.01 }
+    atmosphere_equilibrium = { added = -0.2 rate = 0.02 }
+    temperature_change = 1.5
+    water_change = -0.5
+    breathable_change = 0.25
+    tech_needed = "mining"
+    prosperity_per_job = 0.1
+    stations = [ { right = 1.5 up = 0.0 back = -2.0 } ]
+    category = misc
+"deep_mine"
+    name = "Deep Mine"
+    upgrades_from = "mine"
+"#,
+        );
+    }
+
+    #[test]
+    fn constants() {
+        let data = round_trip(
+            r#"#constants
+"base" = 4
+"double" = $(base * 2)
+"same" = $base
+
+#tech
+"mining"
+    name = "Mining"
+    time = $(double + 1)
+"#,
+        );
+        assert_eq!(data.tech_data[0].time, 9);
+    }
+
+    #[test]
+    fn designations() {
+        round_trip(
+            r#"#designations
+"farm"
+    name = "Farm"
+    description = "Grows food"
+    housing = unmanaged
+    building_limit = limited(3)
+    population_impact { growth = 0.5 min_population = 10 }
+    private_buildings = min_population(100)
+    tech_required = "farming"
+"city"
+    name = "City"
+    housing = managend
+    building_limit = Unlimited
+    private_buildings = always
+"#,
+        );
+    }
+
+    #[test]
+    fn goods() {
+        round_trip(
+            r#"#goods
+"air"
+    name = "Air"
+    icon = "air.png"
+    buy_value = 2
+    sell_value = 1.5
+    good_type = public
+    hardcoded_id = 7
+    consumption_type = survival { atmosphere = 0.5 breathability = 0.8 served_step = 2 }
+    prosperity_bonus = 0.1
+    prosperity_cost = 0.2
+    vendible = false
+"gold"
+    name = "Gold"
+    icon = "gold.png"
+    good_type = tender
+    consumption_type = amenity
+"#,
+        );
+    }
+
+    #[test]
+    fn orbitals() {
+        round_trip(
+            r#"#orbital
+2 = {
+    name = "Ring"
+    asset_location = "ring"
+    time = 8
+    building_limit = 4
+    consumes = [ { good_id: "steel" amount: 20.0 } ]
+    magnetosphere = 0.5
+    atmosphere = 0.25
+    water = 0.1
+    temperature_kelvin = 250.5
+    breathability = 0.75
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn planet_types() {
+        round_trip(
+            r#"#planet_types
+"ocean"
+    class = atmospheric
+    set_asset = "ocean"
+    goods_abundance = [ { "water" (mean: 0.8 std_dev: 0.1) } ]
+    setup {
+        if water_level GE 0.9 & !(star_type IN [ "M" "K" ]) {
+            stored["depth"] = "deep"
+        } else if temperature_celsius LT -10 | goods_base["ice"] GT 0.5 {
+            stored["depth"] = { "frozen" "icy" }
+            set_asset = "ice"
+        } else {
+            stored["depth"] = "shallow"
+        }
+    }
+    on_terraform {
+        if stored["depth"] EQ "deep" {
+            if oxygen_level GT 0.2 {
+                set_planet_type = "garden"
+            }
+        }
+        if magnetosphere LE 0 {
+            set_planet_type = "barren"
+        }
+    }
+"#,
+        );
+    }
+
+    #[test]
+    fn ranks() {
+        round_trip(
+            r#"#ranks
+1 = {
+    name = "Outpost"
+    number_of_stars = 3
+    stockpile_max = 500
+    huck_max = 2.5
+    description = "Just starting"
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn species_traits() {
+        round_trip(
+            r#"#specie_traits
+"hardy"
+    name = "Hardy"
+    icon = "hardy.png"
+    consumes = [ { good_id: "food" amount: 2 } ]
+    effects { growth_rate = 1.5 }
+"#,
+        );
+    }
+
+    #[test]
+    fn ships() {
+        round_trip(
+            r#"#ships
+"scout"
+    name = "Scout"
+    asset_location = "scout"
+    ship_class = survey
+    scale = 1.25
+    starts_with = true
+"#,
+        );
+    }
+
+    #[test]
+    fn shipyards() {
+        round_trip(
+            r#"#shipyard
+1 = {
+    name = "Dock"
+    asset_location = "dock"
+    time = 6
+    consumes = [ { good_id: "steel" amount: 10.0 } ]
+    base_strength = 2.5
+    fleet_strength = 1.0
+    star_class = true
+    armaments = false
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn shipyard_buildings() {
+        round_trip(
+            r#"#shipyard_buildings
+"foundry"
+    name = "Foundry"
+    level_required = 2
+    costs = [ { "steel" 5.0 } ]
+    time = 3
+    upkeep = 0.5
+    power = 1.0
+    base_strength = 0.5
+    fleet_strength = 0.25
+"#,
+        );
+    }
+
+    #[test]
+    fn stapledon_swarms() {
+        round_trip(
+            r#"#stapledon_swarm
+1 = {
+    name = "Swarm"
+    swarm_asset = "swarm"
+    receiver_asset = "receiver"
+    consumes = [ { good_id: "steel" amount: 100.0 } ]
+    upkeep = [ { good_id: "power" amount: 1.0 } ]
+    power = 5.0
+    time = 20
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn stellar_systems() {
+        round_trip(
+            r#"#stellar_system
+1 = {
+    star_data = {
+        name = "Sol"
+        asset_location = "sun"
+        temperature_kelvin = 5778.0
+        star_type = "G"
+    }
+    planet_data = {
+        name = "Earth"
+        asset_location = "earth"
+        size = 12
+        planet_type = "ocean"
+        magnetosphere = 1.0
+        atmosphere = 1.0
+        temperature_celsius = 15.0
+        water = 0.7
+        breathability = 1.0
+        natural_resources = [ { good_id: "ore" amount: 0.5 } ]
+        ring
+        moon_data = {
+            name = "Moon"
+            size = 3
+            planet_type = "barren"
+            capital = false
+        }
+        capital = true
+    }
+    asteroid_belt = {
+        planet_data = {
+            name = "Ceres"
+            size = 1
+        }
+    }
+    surveyed = true
+}
+"#,
+        );
+    }
+
+    #[test]
+    fn tech() {
+        round_trip(
+            r#"#tech
+"mining"
+    name = "Mining"
+    time = 5
+    description = "Dig deeper"
+    prerequisites = [ "tools" "fire" ]
+"#,
+        );
+    }
+
+    #[test]
+    fn tooltips() {
+        round_trip(
+            r#"#tooltips
+"ore": "Dug up by a `mine` from ~rocky~ planets"
+"power": "Keeps the lights on"
+
+#tooltips
+"gold": "Shiny"
+"#,
+        );
+    }
+
+    #[test]
+    fn doc_comments() {
+        let data = round_trip(
+            r#"#goods
+/// Breathed by everyone
+///
+/// Runs out on barren planets
+"air"
+    name = "Air"
+    icon = "air.png"
+
+#tech
+/// Lets mines be built
+"mining"
+    name = "Mining"
+    time = 5
+"#,
+        );
+        assert_eq!(
+            data.goods_data[0].doc.as_deref(),
+            Some("Breathed by everyone\n\nRuns out on barren planets")
+        );
+    }
+
+    #[test]
+    fn templates_and_extends() {
+        let data = round_trip(
+            r#"#buildings
+template "factory"
+    name = "Factory"
+    workers = 50
+    costs = [ { "steel" 10.0 } ]
+"smelter" extends "factory"
+    name = "Smelter"
+    produces = [ { "iron" 1.0 } ]
+"big_smelter" extends "smelter"
+    workers = 100
+
+#designations
+template "settlement"
+    name = "Settlement"
+    housing = managend
+"town" extends "settlement"
+    description = "A small town"
+"#,
+        );
+        assert_eq!(data.building_data.len(), 2);
+        assert_eq!(data.building_data[1].workers, 100);
+        assert_eq!(data.building_data[1].name, "Smelter");
+    }
+
+    #[test]
+    fn patches() {
+        let data = round_trip(
+            r#"#goods
+"ore"
+    name = "Ore"
+    icon = "ore.png"
+    buy_value = 2
+"coal"
+    name = "Coal"
+    icon = "coal.png"
+patch "ore"
+    buy_value = 3
+    vendible = false
+delete "coal"
+
+#asteroid_mining
+1 = {
+    name = "Drill"
+    depot_asset = "depot"
+    ship_asset = "ship"
+    time = 4
+}
+patch 1 = {
+    time = 6
+}
+"#,
+        );
+        assert_eq!(data.goods_data.len(), 1);
+        assert_eq!(data.asteroid_mining[0].time, 6);
+    }
+}
@@ -1,22 +1,44 @@
 use std::fmt;
 
-use crate::{LexicalError, common::DataParser};
+use crate::{
+    LexicalError,
+    common::{self, DataParser, LexExtras, NumberToken, ParsedEntries, Patchable},
+    printer::{PrintScript, ScriptWriter, decimal, quoted},
+    schema::FieldSchema,
+};
 use lalrpop_util::lalrpop_mod;
-use logos::{self, Logos};
+use logos::{self, Lexer, Logos};
 use rust_decimal::prelude::*;
 
 #[derive(Logos, Clone, Debug, PartialEq)]
-#[logos(skip r"[\s\t\f]+", error = LexicalError)]
+#[logos(skip r"[\s\t\f]+", error = LexicalError, extras = LexExtras)]
 #[logos(skip r"//[^\n\r]*")]
+#[logos(skip(r"///([^/\n\r][^\n\r]*)?", common::doc_comment))]
+#[logos(skip(r"/\*", common::block_comment))]
 pub enum RankToken {
-    #[regex(r#""[^"]*""#, |lex| lex.slice().trim_matches('"').to_string())]
+    #[token("replace")]
+    Replace,
+    #[token("patch")]
+    Patch,
+    #[token("delete")]
+    Delete,
+
+    #[regex(r#""([^"\\]|\\.)*""#, common::string)]
+    #[regex(r#""""([^"]|"[^"]|""[^"])*""""#, common::string)]
     String(String),
 
-    #[regex(r"(\d+)", |lex|lex.slice().parse::<u16>().expect("parsing u8"), priority = 5)]
+    #[regex(r"[0-9][0-9_]*([eE]\+?[0-9]+)?", common::integer::<u16>, priority = 5)]
     Number(u16),
 
-    #[regex(r"(-?\d+\.?\d*)", |lex| Decimal::from_str(lex.slice()).expect("parsed_decimal"), priority = 4)]
+    #[regex(
+        r"-?[0-9][0-9_]*(\.[0-9_]*)?([eE][+-]?[0-9]+)?",
+        common::decimal,
+        priority = 4
+    )]
     DecimalNumber(Decimal),
+    #[regex(r"\$[A-Za-z_][A-Za-z0-9_]*", common::expression)]
+    #[regex(r"\$\(", common::expression)]
+    Expression,
 
     #[token("=")]
     Equal,
@@ -46,9 +68,20 @@ impl fmt::Display for RankToken {
     }
 }
 
-lalrpop_mod!(pub ranks);
+impl<'s> NumberToken<'s> for RankToken {
+    fn number(lex: &mut Lexer<'s, Self>, value: Decimal) -> Self {
+        if value.scale() == 0 {
+            RankToken::Number(common::fit(lex, value))
+        } else {
+            RankToken::DecimalNumber(value)
+        }
+    }
+}
+
+lalrpop_mod!(#[allow(clippy::ptr_arg)] pub ranks);
 
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RankData {
     pub level: u16,
     pub name: String,
@@ -56,17 +89,59 @@ pub struct RankData {
     pub stockpile_max: u16,
     pub huck_max: Decimal,
     pub description: Option<String>,
+    /// Doc comments written above where the entry is defined or replaced
+    pub doc: Option<String>,
+}
+impl Patchable for RankData {
+    type Key = u16;
+    type Field = Field;
+    fn key(&self) -> &Self::Key {
+        &self.level
+    }
+    fn set_key(&mut self, key: Self::Key) {
+        self.level = key;
+    }
+    fn apply(&mut self, field: Self::Field) {
+        match field {
+            Field::Name(n) => self.name = n,
+            Field::NumStars(n) => self.number_of_stars = n,
+            Field::StockpileMax(n) => self.stockpile_max = n,
+            Field::HuckMax(d) => self.huck_max = d,
+            Field::Description(d) => self.description = Some(d),
+        }
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+    fn set_doc(&mut self, doc: Option<String>) {
+        self.doc = doc;
+    }
 }
+
 impl<'s> DataParser<'s> for RankData {
     type Token = RankToken;
+    const KEYWORDS: &'static [&'static str] = &[
+        "replace",
+        "patch",
+        "delete",
+        "number_of_stars",
+        "stockpile_max",
+        "huck_max",
+        "level",
+        "name",
+        "description",
+    ];
+    const SCHEMA: &'static [FieldSchema<Self>] = &[FieldSchema::required("name")];
 
     fn parse_tokens(
         tokens: Vec<(usize, Self::Token, usize)>,
-    ) -> Result<Vec<Self>, lalrpop_util::ParseError<usize, Self::Token, String>> {
-        ranks::RankDataParser::new().parse(tokens)
+        errors: &mut Vec<lalrpop_util::ErrorRecovery<usize, Self::Token, String>>,
+    ) -> ParsedEntries<Self, Self::Token> {
+        ranks::RankDataParser::new().parse(errors, tokens)
     }
 }
 
+#[derive(Clone, Debug)]
 pub enum Field {
     Name(String),
     NumStars(u16),
@@ -74,3 +149,22 @@ pub enum Field {
     HuckMax(Decimal),
     Description(String),
 }
+
+impl PrintScript for RankData {
+    const SECTION: &'static str = "#ranks";
+    fn print(&self, w: &mut ScriptWriter) {
+        w.block(format_args!("{} =", self.level), |w| {
+            w.field_unless_default("name", quoted(&self.name), self.name.is_empty());
+            w.field_unless_default(
+                "number_of_stars",
+                self.number_of_stars,
+                self.number_of_stars == 0,
+            );
+            w.field_unless_default("stockpile_max", self.stockpile_max, self.stockpile_max == 0);
+            w.field_unless_default("huck_max", decimal(self.huck_max), self.huck_max.is_zero());
+            if let Some(d) = &self.description {
+                w.field("description", quoted(d));
+            }
+        });
+    }
+}
@@ -0,0 +1,268 @@
+use std::{cmp::Ordering, collections::BTreeSet};
+
+use rust_decimal::Decimal;
+
+use crate::planet_types::{Action, Branch, Condition, IfCondition, PlanetTypeData, Value};
+
+/// Every key the rules of a planet type store a string under
+pub fn stored_keys(planet_type: &PlanetTypeData) -> BTreeSet<&str> {
+    let mut written = BTreeSet::new();
+    let branches = planet_type
+        .setup_conditions
+        .iter()
+        .chain(&planet_type.terraform_conditions);
+    for branch in branches {
+        walk_branch(branch, &mut written, &mut Vec::new());
+    }
+    written
+}
+
+/// Keys read before anything could have stored them, in the order they are read.
+/// Setup starts with nothing stored, terraforming can happen after any setup
+/// or terraform so it starts with `stored_anywhere`
+pub fn unset_reads<'d>(
+    planet_type: &'d PlanetTypeData,
+    stored_anywhere: &BTreeSet<&'d str>,
+) -> Vec<&'d str> {
+    let mut reads = Vec::new();
+    let mut written = BTreeSet::new();
+    for branch in &planet_type.setup_conditions {
+        walk_branch(branch, &mut written, &mut reads);
+    }
+    let mut written = stored_anywhere.clone();
+    for branch in &planet_type.terraform_conditions {
+        walk_branch(branch, &mut written, &mut reads);
+    }
+    let mut seen = BTreeSet::new();
+    reads.retain(|key| seen.insert(*key));
+    reads
+}
+
+/// An arm that can never be taken because an arm before it
+/// in the same if holds whenever it does
+pub struct UnreachableArm<'d> {
+    pub arm: &'d IfCondition,
+    /// The field the arm is written in
+    pub field: &'static str,
+    /// How many `if`s come before the arm's own in its field
+    pub index: usize,
+}
+
+/// Arms that can never be taken, in the order they are written
+pub fn unreachable_arms(planet_type: &PlanetTypeData) -> Vec<UnreachableArm<'_>> {
+    let mut unreachable = Vec::new();
+    let fields = [
+        ("setup", planet_type.setup_conditions.as_slice()),
+        ("on_terraform", &planet_type.terraform_conditions),
+    ];
+    for (field, branches) in fields {
+        let mut count = 0;
+        for branch in branches {
+            subsumed_arms(branch, field, &mut count, &mut unreachable);
+        }
+    }
+    unreachable
+}
+
+/// Adds what `branch` may store to `written`, whichever arm is taken,
+/// recording reads of keys that are not stored yet
+fn walk_branch<'d>(branch: &'d Branch, written: &mut BTreeSet<&'d str>, reads: &mut Vec<&'d str>) {
+    let before = written.clone();
+    let arms = branch
+        .if_conditions
+        .iter()
+        .map(|arm| (Some(&arm.condition), &arm.actions))
+        .chain([(None, &branch.else_actions)]);
+    for (condition, actions) in arms {
+        if let Some(condition) = condition {
+            condition_reads(condition, &before, reads);
+        }
+        let mut arm_written = before.clone();
+        for action in actions {
+            match action {
+                Action::SetStored(key, _) | Action::SetStoredRandom(key, _) => {
+                    arm_written.insert(key);
+                }
+                Action::Branch(b) => walk_branch(b, &mut arm_written, reads),
+                Action::None | Action::SetAsset(_) | Action::SetPlanetType(_) => {}
+            }
+        }
+        written.extend(arm_written);
+    }
+}
+
+fn condition_reads<'d>(
+    condition: &'d Condition,
+    written: &BTreeSet<&'d str>,
+    reads: &mut Vec<&'d str>,
+) {
+    for value in condition.values() {
+        if let Value::StringLookup(key) = value
+            && !written.contains(key.as_str())
+        {
+            reads.push(key);
+        }
+    }
+}
+
+fn subsumed_arms<'d>(
+    branch: &'d Branch,
+    field: &'static str,
+    count: &mut usize,
+    unreachable: &mut Vec<UnreachableArm<'d>>,
+) {
+    for (i, arm) in branch.if_conditions.iter().enumerate() {
+        let earlier = &branch.if_conditions[..i];
+        if earlier
+            .iter()
+            .any(|e| implies(&arm.condition, &e.condition))
+        {
+            unreachable.push(UnreachableArm {
+                arm,
+                field,
+                index: *count,
+            });
+        }
+        *count += 1;
+        nested_arms(&arm.actions, field, count, unreachable);
+    }
+    nested_arms(&branch.else_actions, field, count, unreachable);
+}
+
+fn nested_arms<'d>(
+    actions: &'d [Action],
+    field: &'static str,
+    count: &mut usize,
+    unreachable: &mut Vec<UnreachableArm<'d>>,
+) {
+    for action in actions {
+        if let Action::Branch(b) = action {
+            subsumed_arms(b, field, count, unreachable);
+        }
+    }
+}
+
+/// Whether `a` holding means `b` holds too, only what can be seen
+/// from the conditions themselves is used so some implications are missed
+fn implies(a: &Condition, b: &Condition) -> bool {
+    if a == b {
+        return true;
+    }
+    match (a, b) {
+        (_, Condition::And(bs)) => bs.iter().all(|b| implies(a, b)),
+        (Condition::Or(r#as), _) => r#as.iter().all(|a| implies(a, b)),
+        (_, Condition::Or(bs)) if bs.iter().any(|b| implies(a, b)) => true,
+        (Condition::And(r#as), _) => r#as.iter().any(|a| implies(a, b)),
+        (Condition::Not(a), Condition::Not(b)) => implies(b, a),
+        _ => match (Test::new(a), Test::new(b)) {
+            (Some(a), Some(b)) => a.subject == b.subject && a.implies(&b),
+            _ => false,
+        },
+    }
+}
+
+/// A comparison of a looked up value against written out values
+struct Test<'c> {
+    subject: &'c Value,
+    kind: TestKind<'c>,
+}
+
+enum TestKind<'c> {
+    OneOf(Vec<&'c Value>),
+    NotEqual(&'c Value),
+    /// Holds when comparing the subject with the bound gives one of the orderings
+    Range {
+        allowed: &'static [Ordering],
+        bound: Decimal,
+    },
+}
+
+impl<'c> Test<'c> {
+    fn new(condition: &'c Condition) -> Option<Self> {
+        let literal = |v: &Value| matches!(v, Value::Decimal(_) | Value::String(_));
+        let (allowed, a, b): (&'static [Ordering], _, _) = match condition {
+            Condition::In(subject, values) if !literal(subject) && values.iter().all(literal) => {
+                return Some(Test {
+                    subject,
+                    kind: TestKind::OneOf(values.iter().collect()),
+                });
+            }
+            Condition::Eq(a, b) => (&[Ordering::Equal], a, b),
+            Condition::Ne(a, b) => (&[Ordering::Less, Ordering::Greater], a, b),
+            Condition::Gt(a, b) => (&[Ordering::Greater], a, b),
+            Condition::Ge(a, b) => (&[Ordering::Greater, Ordering::Equal], a, b),
+            Condition::Lt(a, b) => (&[Ordering::Less], a, b),
+            Condition::Le(a, b) => (&[Ordering::Less, Ordering::Equal], a, b),
+            _ => return None,
+        };
+        // Written so the subject is always on the left
+        let (subject, value, allowed) = match (literal(a), literal(b)) {
+            (false, true) => (a, b, allowed),
+            (true, false) => (b, a, flipped(allowed)),
+            _ => return None,
+        };
+        let kind = match (allowed, value) {
+            ([Ordering::Equal], _) => TestKind::OneOf(vec![value]),
+            ([Ordering::Less, Ordering::Greater], _) => TestKind::NotEqual(value),
+            (_, Value::Decimal(bound)) => TestKind::Range {
+                allowed,
+                bound: *bound,
+            },
+            _ => return None,
+        };
+        Some(Test { subject, kind })
+    }
+
+    fn holds_for(&self, value: &Value) -> bool {
+        match (&self.kind, value) {
+            (TestKind::OneOf(values), _) => values.contains(&value),
+            (TestKind::NotEqual(v), _) => *v != value,
+            (TestKind::Range { allowed, bound }, Value::Decimal(d)) => {
+                allowed.contains(&d.cmp(bound))
+            }
+            _ => false,
+        }
+    }
+
+    fn implies(&self, other: &Test) -> bool {
+        match (&self.kind, &other.kind) {
+            (TestKind::OneOf(values), _) => values.iter().all(|v| other.holds_for(v)),
+            (_, TestKind::NotEqual(v)) => !self.holds_for(v),
+            (
+                TestKind::Range {
+                    allowed: a,
+                    bound: a_bound,
+                },
+                TestKind::Range {
+                    allowed: b,
+                    bound: b_bound,
+                },
+            ) => {
+                let above = a.contains(&Ordering::Greater);
+                if above != b.contains(&Ordering::Greater) {
+                    return false;
+                }
+                let tighter = if above {
+                    a_bound > b_bound
+                } else {
+                    a_bound < b_bound
+                };
+                let a_inclusive = a.contains(&Ordering::Equal);
+                let b_inclusive = b.contains(&Ordering::Equal);
+                tighter || (a_bound == b_bound && (b_inclusive || !a_inclusive))
+            }
+            _ => false,
+        }
+    }
+}
+
+/// The orderings allowed once both sides of a comparison are swapped
+fn flipped(allowed: &'static [Ordering]) -> &'static [Ordering] {
+    match allowed {
+        [Ordering::Greater] => &[Ordering::Less],
+        [Ordering::Greater, Ordering::Equal] => &[Ordering::Less, Ordering::Equal],
+        [Ordering::Less] => &[Ordering::Greater],
+        [Ordering::Less, Ordering::Equal] => &[Ordering::Greater, Ordering::Equal],
+        other => other,
+    }
+}
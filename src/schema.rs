@@ -0,0 +1,253 @@
+use std::sync::Arc;
+
+use miette::{Diagnostic, NamedSource, SourceSpan};
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::common::{EntrySpan, Limit, field_name};
+
+/// Whether an entry has to set a field
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Presence {
+    /// Leaving the field out is an error
+    Required,
+    /// Leaving the field out is allowed but probably a mistake, so it is warned about
+    Recommended,
+    /// The default value is fine
+    Optional,
+}
+
+/// The rules a single field of an entry is checked against
+pub struct FieldSchema<T> {
+    pub name: &'static str,
+    pub presence: Presence,
+    /// Smallest value allowed when the field is set
+    pub min: Option<Decimal>,
+    /// Largest value allowed when the field is set
+    pub max: Option<Decimal>,
+    /// Reads the field's value for the range checks
+    pub value: Option<fn(&T) -> Decimal>,
+}
+
+impl<T> FieldSchema<T> {
+    pub const fn required(name: &'static str) -> Self {
+        Self::new(name, Presence::Required)
+    }
+    pub const fn recommended(name: &'static str) -> Self {
+        Self::new(name, Presence::Recommended)
+    }
+    pub const fn optional(name: &'static str) -> Self {
+        Self::new(name, Presence::Optional)
+    }
+
+    const fn new(name: &'static str, presence: Presence) -> Self {
+        FieldSchema {
+            name,
+            presence,
+            min: None,
+            max: None,
+            value: None,
+        }
+    }
+
+    /// Limits the values the field can be set to, inclusive of both ends
+    pub const fn range(
+        mut self,
+        min: Option<Decimal>,
+        max: Option<Decimal>,
+        value: fn(&T) -> Decimal,
+    ) -> Self {
+        self.min = min;
+        self.max = max;
+        self.value = Some(value);
+        self
+    }
+}
+
+pub use diagnostics::*;
+
+mod diagnostics {
+    // Kept apart for the same reason as the diagnostics in the crate root
+    #![allow(unused_assignments)]
+
+    use super::*;
+
+    /// An entry that leaves out a field it has to set
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("`{entry}` is missing the required field `{field}`")]
+    #[diagnostic(code(pronytic::schema::missing_field))]
+    pub struct MissingFieldError {
+        #[source_code]
+        pub src: NamedSource<Arc<str>>,
+        pub entry: String,
+        pub field: &'static str,
+        #[label("Declared here without `{field}`")]
+        pub bad_bit: SourceSpan,
+    }
+
+    /// An entry that leaves out a field that it should probably set,
+    /// the default value is used in its place
+    #[derive(Error, Debug, Diagnostic, Clone)]
+    #[error("`{entry}` does not set `{field}`")]
+    #[diagnostic(
+        code(pronytic::schema::missing_recommended_field),
+        severity(Warning),
+        help("The default value will be used")
+    )]
+    pub struct MissingFieldWarning {
+        #[source_code]
+        pub src: NamedSource<Arc<str>>,
+        pub entry: String,
+        pub field: &'static str,
+        #[label("Declared here without `{field}`")]
+        pub bad_bit: SourceSpan,
+    }
+
+    /// A field set to a value outside the range it allows
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("{message}")]
+    #[diagnostic(code(pronytic::schema::out_of_range))]
+    pub struct OutOfRangeError {
+        #[source_code]
+        pub src: NamedSource<Arc<str>>,
+        pub message: String,
+        #[label("Set here")]
+        pub bad_bit: SourceSpan,
+    }
+}
+
+/// Checks an entry against its schema, returning the errors and the warnings found.
+/// Fields named in `inherited` were set by what the entry extends so count as written.
+/// Fields holding a span in `reported` already had their value reported so aren't range checked
+pub fn check<T>(
+    schema: &[FieldSchema<T>],
+    data: &T,
+    span: &EntrySpan,
+    inherited: &[String],
+    reported: &[SourceSpan],
+    file_name: &str,
+    contents: &Arc<str>,
+) -> (Vec<miette::Report>, Vec<MissingFieldWarning>) {
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    let src = || NamedSource::new(file_name, contents.clone());
+    let entry = || {
+        let id = &contents[span.id.offset()..span.id.offset() + span.id.len()];
+        id.trim_matches('"').to_string()
+    };
+
+    for field in schema {
+        let written = span
+            .fields
+            .iter()
+            .find(|f| field_name(contents, **f) == field.name);
+        match written {
+            Some(field_span) => {
+                let end = field_span.offset() + field_span.len();
+                let already_reported = reported
+                    .iter()
+                    .any(|r| (field_span.offset()..end).contains(&r.offset()));
+                if let Some(value) = field.value.map(|v| v(data)).filter(|_| !already_reported) {
+                    let limit = match (field.min, field.max) {
+                        (Some(min), _) if value < min => Some(Limit::Min(min)),
+                        (_, Some(max)) if value > max => Some(Limit::Max(max)),
+                        _ => None,
+                    };
+                    if let Some(limit) = limit {
+                        errors.push(
+                            OutOfRangeError {
+                                src: src(),
+                                message: limit.message(value, Some(field.name)),
+                                bad_bit: *field_span,
+                            }
+                            .into(),
+                        );
+                    }
+                }
+            }
+            None if inherited.iter().any(|name| name == field.name) => {}
+            None => match field.presence {
+                Presence::Required => errors.push(
+                    MissingFieldError {
+                        src: src(),
+                        entry: entry(),
+                        field: field.name,
+                        bad_bit: span.id,
+                    }
+                    .into(),
+                ),
+                Presence::Recommended => warnings.push(MissingFieldWarning {
+                    src: src(),
+                    entry: entry(),
+                    field: field.name,
+                    bad_bit: span.id,
+                }),
+                Presence::Optional => {}
+            },
+        }
+    }
+    (errors, warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use miette::Diagnostic;
+
+    use crate::parse;
+
+    /// The message and first label of every error found parsing `contents`
+    fn errors(contents: &str) -> Vec<(String, Option<usize>)> {
+        let report = parse("test.txt", contents).unwrap_err();
+        report
+            .errors
+            .iter()
+            .map(|e| {
+                let label = e.labels().and_then(|mut l| l.next());
+                (e.to_string(), label.map(|l| l.offset()))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn value_outside_range_points_at_field() {
+        let contents = r#"#goods
+"ore"
+    name = "Ore"
+    icon = "ore"
+    buy_value = -1.0
+"#;
+        assert_eq!(
+            errors(contents),
+            [(
+                "value -1.0 is below minimum 0 for `buy_value`".to_string(),
+                contents.find("buy_value")
+            )]
+        );
+    }
+
+    #[test]
+    fn missing_required_field_points_at_id() {
+        let contents = "#tech\n\"t\"\n    time = 5\n";
+        assert_eq!(
+            errors(contents),
+            [(
+                "`t` is missing the required field `name`".to_string(),
+                contents.find("\"t\"")
+            )]
+        );
+    }
+
+    #[test]
+    fn missing_recommended_field_is_a_warning() {
+        let contents = "#planet_types\n\"hot\"\n    class = rocky\n";
+        let data = parse("test.txt", contents).unwrap();
+        let [warning] = &data.warnings[..] else {
+            panic!("expected one warning, found {:?}", data.warnings);
+        };
+        assert_eq!(warning.to_string(), "`hot` does not set `set_asset`");
+        assert_eq!(
+            warning.code().map(|c| c.to_string()).as_deref(),
+            Some("pronytic::schema::missing_recommended_field")
+        );
+    }
+}
@@ -1,27 +1,53 @@
 use std::fmt;
 
 use lalrpop_util::lalrpop_mod;
-use logos::Logos;
+use logos::{Lexer, Logos};
 use rust_decimal::prelude::*;
 
-use crate::{LexicalError, common::DataParser};
+use crate::{
+    LexicalError,
+    common::{self, DataParser, LexExtras, NumberToken, ParsedEntries, Patchable},
+    printer::{PrintScript, ScriptWriter, boolean, float, quoted},
+    schema::FieldSchema,
+};
 
 #[derive(Logos, Clone, Debug, PartialEq)]
-#[logos(skip r"[\s\t\f]+", error = LexicalError)]
+#[logos(skip r"[\s\t\f]+", error = LexicalError, extras = LexExtras)]
 #[logos(skip r"//[^\n\r]*")]
+#[logos(skip(r"///([^/\n\r][^\n\r]*)?", common::doc_comment))]
+#[logos(skip(r"/\*", common::block_comment))]
 pub enum ShipToken {
+    #[token("replace")]
+    Replace,
+    #[token("patch")]
+    Patch,
+    #[token("delete")]
+    Delete,
+    #[token("template")]
+    Template,
+    #[token("extends")]
+    Extends,
+
     #[token("true")]
     True,
     #[token("false")]
     False,
-    #[regex(r#""[^"]*""#, |lex| lex.slice().trim_matches('"').to_string())]
+    #[regex(r#""([^"\\]|\\.)*""#, common::string)]
+    #[regex(r#""""([^"]|"[^"]|""[^"])*""""#, common::string)]
     String(String),
 
-    #[regex(r"(\d+)", |lex|lex.slice().parse::<u8>().expect("parsing u8"), priority = 5)]
+    #[regex(r"[0-9][0-9_]*([eE]\+?[0-9]+)?", common::integer::<u8>, priority = 5)]
     Number(u8),
 
-    #[regex(r"(\d+\.\d*)", |lex| Decimal::from_str(lex.slice()).expect("parsed_decimal"), priority = 4)]
+    #[regex(
+        r"-?[0-9][0-9_]*(\.[0-9_]*)?([eE][+-]?[0-9]+)?",
+        common::decimal,
+        priority = 4
+    )]
     DecimalNumber(Decimal),
+    #[regex(r"\$[A-Za-z_][A-Za-z0-9_]*", common::expression)]
+    #[regex(r"\$\(", common::expression)]
+    Expression,
 
     #[token("=")]
     Equal,
@@ -52,15 +78,27 @@ impl fmt::Display for ShipToken {
     }
 }
 
-#[derive(Clone, Default, Debug)]
+impl<'s> NumberToken<'s> for ShipToken {
+    fn number(lex: &mut Lexer<'s, Self>, value: Decimal) -> Self {
+        if value.scale() == 0 {
+            ShipToken::Number(common::fit(lex, value))
+        } else {
+            ShipToken::DecimalNumber(value)
+        }
+    }
+}
+
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ShipClass {
     #[default]
     Survey,
     Military,
 }
 
-lalrpop_mod!(pub ship);
-#[derive(Clone, Default, Debug)]
+lalrpop_mod!(#[allow(clippy::ptr_arg)] pub ship);
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ShipData {
     pub id: String,
     pub name: String,
@@ -72,8 +110,11 @@ pub struct ShipData {
     pub scale: f32,
 
     pub starts_with: bool,
+    /// Doc comments written above where the entry is defined or replaced
+    pub doc: Option<String>,
 }
 
+#[derive(Clone, Debug)]
 pub enum Field {
     Name(String),
     AssetLocation(String),
@@ -82,12 +123,86 @@ pub enum Field {
     StartsWith(bool),
 }
 
+impl Patchable for ShipData {
+    type Key = String;
+    type Field = Field;
+    fn key(&self) -> &Self::Key {
+        &self.id
+    }
+    fn set_key(&mut self, key: Self::Key) {
+        self.id = key;
+    }
+    fn apply(&mut self, field: Self::Field) {
+        match field {
+            Field::Name(n) => self.name = n,
+            Field::AssetLocation(a) => self.asset_location = a,
+            Field::ShipClass(s) => self.ship_class = s,
+            Field::Scale(s) => self.scale = s,
+            Field::StartsWith(b) => self.starts_with = b,
+        }
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+    fn set_doc(&mut self, doc: Option<String>) {
+        self.doc = doc;
+    }
+}
+
 impl<'s> DataParser<'s> for ShipData {
     type Token = ShipToken;
+    const KEYWORDS: &'static [&'static str] = &[
+        "replace",
+        "patch",
+        "delete",
+        "template",
+        "extends",
+        "true",
+        "false",
+        "name",
+        "asset_location",
+        "ship_class",
+        "survey",
+        "military",
+        "scale",
+        "starts_with",
+    ];
+    const SCHEMA: &'static [FieldSchema<Self>] = &[
+        FieldSchema::required("name"),
+        FieldSchema::required("asset_location"),
+    ];
 
     fn parse_tokens(
         tokens: Vec<(usize, Self::Token, usize)>,
-    ) -> Result<Vec<Self>, lalrpop_util::ParseError<usize, Self::Token, String>> {
-        ship::ShipDataParser::new().parse(tokens)
+        errors: &mut Vec<lalrpop_util::ErrorRecovery<usize, Self::Token, String>>,
+    ) -> ParsedEntries<Self, Self::Token> {
+        ship::ShipDataParser::new().parse(errors, tokens)
+    }
+}
+
+impl PrintScript for ShipData {
+    const SECTION: &'static str = "#ships";
+    fn print(&self, w: &mut ScriptWriter) {
+        let d = ShipData::default();
+        w.line(quoted(&self.id));
+        w.indented(|w| {
+            w.field_unless_default("name", quoted(&self.name), self.name == d.name);
+            w.field_unless_default(
+                "asset_location",
+                quoted(&self.asset_location),
+                self.asset_location == d.asset_location,
+            );
+            let ship_class = match self.ship_class {
+                ShipClass::Survey => "survey",
+                ShipClass::Military => "military",
+            };
+            w.field_unless_default("ship_class", ship_class, self.ship_class == d.ship_class);
+            w.field_unless_default("scale", float(self.scale), self.scale == d.scale);
+            w.field_unless_default(
+                "starts_with",
+                boolean(self.starts_with),
+                self.starts_with == d.starts_with,
+            );
+        });
     }
 }
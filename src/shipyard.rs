@@ -1,32 +1,51 @@
-use std::{fmt, str::FromStr};
+use std::fmt;
 
 use lalrpop_util::lalrpop_mod;
-use logos::{self, Logos};
+use logos::{self, Lexer, Logos};
 use rust_decimal::prelude::*;
 
 use crate::{
     LexicalError,
-    common::{DataParser, GoodConsumes},
+    common::{self, DataParser, GoodConsumes, LexExtras, NumberToken, ParsedEntries, Patchable},
+    printer::{PrintScript, ScriptWriter, boolean, decimal, good_amount, quoted},
+    schema::FieldSchema,
 };
 
 //TODO! this number tokenising is inconsistent with other token types I should change the others to split decimal numbers as consistently
 #[derive(Logos, Clone, Debug, PartialEq)]
-#[logos(skip r"[\s\t\f]+", error = LexicalError)]
+#[logos(skip r"[\s\t\f]+", error = LexicalError, extras = LexExtras)]
 #[logos(skip r"//[^\n\r]*")]
+#[logos(skip(r"///([^/\n\r][^\n\r]*)?", common::doc_comment))]
+#[logos(skip(r"/\*", common::block_comment))]
 pub enum ShipyardToken {
+    #[token("replace")]
+    Replace,
+    #[token("patch")]
+    Patch,
+    #[token("delete")]
+    Delete,
+
     #[token("true")]
     True,
     #[token("false")]
     False,
 
-    #[regex(r#""[^"]*""#, |lex| lex.slice().trim_matches('"').to_string())]
+    #[regex(r#""([^"\\]|\\.)*""#, common::string)]
+    #[regex(r#""""([^"]|"[^"]|""[^"])*""""#, common::string)]
     String(String),
 
-    #[regex(r"(\d+)", |lex|lex.slice().parse::<u8>().expect("parsing u8"), priority = 5)]
+    #[regex(r"[0-9][0-9_]*([eE]\+?[0-9]+)?", common::integer::<u8>, priority = 5)]
     Number(u8),
 
-    #[regex(r"(\d+\.\d*)", |lex| Decimal::from_str(lex.slice()).expect("parsed_decimal"), priority = 4)]
+    #[regex(
+        r"-?[0-9][0-9_]*(\.[0-9_]*)?([eE][+-]?[0-9]+)?",
+        common::decimal,
+        priority = 4
+    )]
     DecimalNumber(Decimal),
+    #[regex(r"\$[A-Za-z_][A-Za-z0-9_]*", common::expression)]
+    #[regex(r"\$\(", common::expression)]
+    Expression,
 
     #[token("=")]
     Equal,
@@ -76,8 +95,19 @@ impl fmt::Display for ShipyardToken {
     }
 }
 
-lalrpop_mod!(pub shipyard);
-#[derive(Clone, Default, Debug)]
+impl<'s> NumberToken<'s> for ShipyardToken {
+    fn number(lex: &mut Lexer<'s, Self>, value: Decimal) -> Self {
+        if value.scale() == 0 {
+            ShipyardToken::Number(common::fit(lex, value))
+        } else {
+            ShipyardToken::DecimalNumber(value)
+        }
+    }
+}
+
+lalrpop_mod!(#[allow(clippy::ptr_arg)] pub shipyard);
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ShipyardData {
     pub level: u8,
     pub name: String,
@@ -94,8 +124,11 @@ pub struct ShipyardData {
 
     pub base_strength: Decimal,
     pub fleet_strength: Decimal,
+    /// Doc comments written above where the entry is defined or replaced
+    pub doc: Option<String>,
 }
 
+#[derive(Clone, Debug)]
 pub enum Field {
     Name(String),
     AssetLocation(String),
@@ -106,11 +139,95 @@ pub enum Field {
     StarClass(bool),
     Armaments(bool),
 }
+impl Patchable for ShipyardData {
+    type Key = u8;
+    type Field = Field;
+    fn key(&self) -> &Self::Key {
+        &self.level
+    }
+    fn set_key(&mut self, key: Self::Key) {
+        self.level = key;
+    }
+    fn apply(&mut self, field: Self::Field) {
+        match field {
+            Field::Name(n) => self.name = n,
+            Field::AssetLocation(a) => self.asset_location = a,
+            Field::Consumes(c) => self.costs = c,
+            Field::Time(t) => self.time = t,
+            Field::BaseStrength(b) => self.base_strength = b,
+            Field::FleetStrength(f) => self.fleet_strength = f,
+            Field::StarClass(s) => self.star_class = s,
+            Field::Armaments(a) => self.armaments = a,
+        }
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+    fn set_doc(&mut self, doc: Option<String>) {
+        self.doc = doc;
+    }
+}
+
 impl<'s> DataParser<'s> for ShipyardData {
     type Token = ShipyardToken;
+    const KEYWORDS: &'static [&'static str] = &[
+        "replace",
+        "patch",
+        "delete",
+        "true",
+        "false",
+        "name",
+        "asset_location",
+        "consumes",
+        "good_id",
+        "amount",
+        "time",
+        "star_class",
+        "armaments",
+        "base_strength",
+        "fleet_strength",
+    ];
+    const SCHEMA: &'static [FieldSchema<Self>] = &[
+        FieldSchema::required("name"),
+        FieldSchema::required("asset_location"),
+        FieldSchema::recommended("time"),
+        FieldSchema::optional("base_strength")
+            .range(Some(Decimal::ZERO), None, |s| s.base_strength),
+        FieldSchema::optional("fleet_strength")
+            .range(Some(Decimal::ZERO), None, |s| s.fleet_strength),
+    ];
     fn parse_tokens(
         tokens: Vec<(usize, Self::Token, usize)>,
-    ) -> Result<Vec<ShipyardData>, lalrpop_util::ParseError<usize, Self::Token, String>> {
-        shipyard::ShipyardDataParser::new().parse(tokens)
+        errors: &mut Vec<lalrpop_util::ErrorRecovery<usize, Self::Token, String>>,
+    ) -> ParsedEntries<Self, Self::Token> {
+        shipyard::ShipyardDataParser::new().parse(errors, tokens)
+    }
+}
+
+impl PrintScript for ShipyardData {
+    const SECTION: &'static str = "#shipyard";
+    fn print(&self, w: &mut ScriptWriter) {
+        w.block(format_args!("{} =", self.level), |w| {
+            w.field_unless_default("name", quoted(&self.name), self.name.is_empty());
+            w.field_unless_default(
+                "asset_location",
+                quoted(&self.asset_location),
+                self.asset_location.is_empty(),
+            );
+            w.list("consumes", &self.costs, good_amount);
+            w.field_unless_default("time", self.time, self.time == 0);
+            w.field_unless_default(
+                "base_strength",
+                decimal(self.base_strength),
+                self.base_strength.is_zero(),
+            );
+            w.field_unless_default(
+                "fleet_strength",
+                decimal(self.fleet_strength),
+                self.fleet_strength.is_zero(),
+            );
+            w.field_unless_default("star_class", boolean(self.star_class), !self.star_class);
+            w.field_unless_default("armaments", boolean(self.armaments), !self.armaments);
+        });
     }
 }
@@ -1,24 +1,47 @@
 use std::fmt;
 
-use logos::{self, Logos};
+use logos::{self, Lexer, Logos};
 
-use crate::{LexicalError, building::CustomGood, common::DataParser};
+use crate::{
+    LexicalError,
+    building::CustomGood,
+    common::{self, DataParser, LexExtras, NumberToken, ParsedEntries, Patchable},
+    printer::{PrintScript, ScriptWriter, custom_good, decimal, quoted},
+    schema::FieldSchema,
+};
 
 use lalrpop_util::lalrpop_mod;
 use rust_decimal::prelude::*;
 
 #[derive(Logos, Clone, Debug, PartialEq)]
-#[logos(skip r"[\s\t\f]+", error = LexicalError)]
+#[logos(skip r"[\s\t\f]+", error = LexicalError, extras = LexExtras)]
 #[logos(skip r"//[^\n\r]*")]
+#[logos(skip(r"///([^/\n\r][^\n\r]*)?", common::doc_comment))]
+#[logos(skip(r"/\*", common::block_comment))]
 pub enum ShipyardBuildingToken {
-    #[regex(r#""[^"]*""#, |lex| lex.slice().trim_matches('"').to_string())]
+    #[token("replace")]
+    Replace,
+    #[token("patch")]
+    Patch,
+    #[token("delete")]
+    Delete,
+
+    #[regex(r#""([^"\\]|\\.)*""#, common::string)]
+    #[regex(r#""""([^"]|"[^"]|""[^"])*""""#, common::string)]
     String(String),
 
-    #[regex(r"(\d+)", |lex|lex.slice().parse::<u8>().expect("parsing u8"), priority = 5)]
+    #[regex(r"[0-9][0-9_]*([eE]\+?[0-9]+)?", common::integer::<u8>, priority = 5)]
     Number(u8),
 
-    #[regex(r"(-?\d+\.\d*)", |lex| Decimal::from_str(lex.slice()).expect("parsed_decimal"), priority = 4)]
+    #[regex(
+        r"-?[0-9][0-9_]*(\.[0-9_]*)?([eE][+-]?[0-9]+)?",
+        common::decimal,
+        priority = 4
+    )]
     DecimalNumber(Decimal),
+    #[regex(r"\$[A-Za-z_][A-Za-z0-9_]*", common::expression)]
+    #[regex(r"\$\(", common::expression)]
+    Expression,
 
     #[token("=")]
     Equal,
@@ -71,9 +94,20 @@ impl fmt::Display for ShipyardBuildingToken {
     }
 }
 
-lalrpop_mod!(pub shipyard_buildings);
+impl<'s> NumberToken<'s> for ShipyardBuildingToken {
+    fn number(lex: &mut Lexer<'s, Self>, value: Decimal) -> Self {
+        if value.scale() == 0 {
+            ShipyardBuildingToken::Number(common::fit(lex, value))
+        } else {
+            ShipyardBuildingToken::DecimalNumber(value)
+        }
+    }
+}
 
-#[derive(Clone, Debug, Default)]
+lalrpop_mod!(#[allow(clippy::ptr_arg)] pub shipyard_buildings);
+
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ShipyardBuildingData {
     pub id: String,
     pub name: String,
@@ -94,8 +128,11 @@ pub struct ShipyardBuildingData {
     pub power: Decimal,
 
     pub time: u8,
+    /// Doc comments written above where the entry is defined or replaced
+    pub doc: Option<String>,
 }
 
+#[derive(Clone, Debug)]
 pub enum Field {
     Name(String),
     LevelRequired(u8),
@@ -107,12 +144,105 @@ pub enum Field {
     FleetStrength(Decimal),
 }
 
+impl Patchable for ShipyardBuildingData {
+    type Key = String;
+    type Field = Field;
+    fn key(&self) -> &Self::Key {
+        &self.id
+    }
+    fn set_key(&mut self, key: Self::Key) {
+        self.id = key;
+    }
+    fn apply(&mut self, field: Self::Field) {
+        match field {
+            Field::Name(n) => {
+                self.name = n;
+            }
+            Field::LevelRequired(l) => {
+                self.level_required = l;
+            }
+            Field::Costs(c) => {
+                self.costs = c;
+            }
+            Field::Time(t) => {
+                self.time = t;
+            }
+            Field::Upkeep(u) => {
+                self.upkeep = u;
+            }
+            Field::Power(p) => {
+                self.power = p;
+            }
+            Field::BaseStrength(b) => {
+                self.base_strength = b;
+            }
+            Field::FleetStrength(f) => {
+                self.fleet_strength = f;
+            }
+        }
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+    fn set_doc(&mut self, doc: Option<String>) {
+        self.doc = doc;
+    }
+}
+
 impl<'s> DataParser<'s> for ShipyardBuildingData {
     type Token = ShipyardBuildingToken;
+    const KEYWORDS: &'static [&'static str] = &[
+        "replace",
+        "patch",
+        "delete",
+        "name",
+        "costs",
+        "good_id",
+        "amount",
+        "level_required",
+        "base_strength",
+        "fleet_strength",
+        "upkeep",
+        "power",
+        "time",
+    ];
+    const SCHEMA: &'static [FieldSchema<Self>] = &[
+        FieldSchema::required("name"),
+        FieldSchema::recommended("time"),
+        FieldSchema::optional("power").range(Some(Decimal::ZERO), None, |s| s.power),
+        FieldSchema::optional("upkeep").range(Some(Decimal::ZERO), None, |s| s.upkeep),
+    ];
 
     fn parse_tokens(
         tokens: Vec<(usize, Self::Token, usize)>,
-    ) -> Result<Vec<Self>, lalrpop_util::ParseError<usize, Self::Token, String>> {
-        shipyard_buildings::ShipyardBuildingDataParser::new().parse(tokens)
+        errors: &mut Vec<lalrpop_util::ErrorRecovery<usize, Self::Token, String>>,
+    ) -> ParsedEntries<Self, Self::Token> {
+        shipyard_buildings::ShipyardBuildingDataParser::new().parse(errors, tokens)
+    }
+}
+
+impl PrintScript for ShipyardBuildingData {
+    const SECTION: &'static str = "#shipyard_buildings";
+    fn print(&self, w: &mut ScriptWriter) {
+        w.line(quoted(&self.id));
+        w.indented(|w| {
+            w.field_unless_default("name", quoted(&self.name), self.name.is_empty());
+            w.field_unless_default(
+                "level_required",
+                self.level_required,
+                self.level_required == 0,
+            );
+            w.list("costs", &self.costs, custom_good);
+            w.field_unless_default("time", self.time, self.time == 0);
+            let values = [
+                ("upkeep", self.upkeep),
+                ("power", self.power),
+                ("base_strength", self.base_strength),
+                ("fleet_strength", self.fleet_strength),
+            ];
+            for (name, value) in values {
+                w.field_unless_default(name, decimal(value), value.is_zero());
+            }
+        });
     }
 }
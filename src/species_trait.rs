@@ -1,24 +1,47 @@
-use std::{fmt, str::FromStr};
+use std::fmt;
 
 use lalrpop_util::lalrpop_mod;
 use rust_decimal::Decimal;
 
-use logos::Logos;
+use logos::{Lexer, Logos};
 
 use crate::{
     LexicalError,
-    common::{DataParser, GoodConsumes},
+    common::{self, DataParser, GoodConsumes, LexExtras, NumberToken, ParsedEntries, Patchable},
+    printer::{PrintScript, ScriptWriter, decimal, good_amount, quoted},
+    schema::FieldSchema,
 };
 
 #[derive(Logos, Clone, Debug, PartialEq)]
-#[logos(skip r"[\s\t\f]+", error = LexicalError)]
+#[logos(skip r"[\s\t\f]+", error = LexicalError, extras = LexExtras)]
 #[logos(skip r"//[^\n\r]*")]
+#[logos(skip(r"///([^/\n\r][^\n\r]*)?", common::doc_comment))]
+#[logos(skip(r"/\*", common::block_comment))]
 pub enum SpeciesToken {
-    #[regex(r#""[^"]*""#, |lex| lex.slice().trim_matches('"').to_string())]
+    #[token("replace")]
+    Replace,
+    #[token("patch")]
+    Patch,
+    #[token("delete")]
+    Delete,
+    #[token("template")]
+    Template,
+    #[token("extends")]
+    Extends,
+
+    #[regex(r#""([^"\\]|\\.)*""#, common::string)]
+    #[regex(r#""""([^"]|"[^"]|""[^"])*""""#, common::string)]
     String(String),
 
-    #[regex(r"(\d+\.?\d*)", |lex| Decimal::from_str(lex.slice()).expect("parsed_decimal"), priority = 4)]
+    #[regex(
+        r"-?[0-9][0-9_]*(\.[0-9_]*)?([eE][+-]?[0-9]+)?",
+        common::decimal,
+        priority = 4
+    )]
     DecimalNumber(Decimal),
+    #[regex(r"\$[A-Za-z_][A-Za-z0-9_]*", common::expression)]
+    #[regex(r"\$\(", common::expression)]
+    Expression,
 
     #[token("=")]
     Equal,
@@ -62,18 +85,28 @@ impl fmt::Display for SpeciesToken {
     }
 }
 
-lalrpop_mod!(pub species_trait);
+impl<'s> NumberToken<'s> for SpeciesToken {
+    fn number(_lex: &mut Lexer<'s, Self>, value: Decimal) -> Self {
+        SpeciesToken::DecimalNumber(value)
+    }
+}
 
-#[derive(Clone, Default, Debug)]
+lalrpop_mod!(#[allow(clippy::ptr_arg)] pub species_trait);
+
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct SpeciesTraitData {
     pub id: String,
     pub name: String,
     pub icon: String,
     pub consumes: Vec<GoodConsumes>,
     pub effects: Vec<Effect>,
+    /// Doc comments written above where the entry is defined or replaced
+    pub doc: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Effect {
     GrowthRate(Decimal),
 }
@@ -86,11 +119,74 @@ pub enum Field {
     Effects(Vec<Effect>),
 }
 
+impl Patchable for SpeciesTraitData {
+    type Key = String;
+    type Field = Field;
+    fn key(&self) -> &Self::Key {
+        &self.id
+    }
+    fn set_key(&mut self, key: Self::Key) {
+        self.id = key;
+    }
+    fn apply(&mut self, field: Self::Field) {
+        match field {
+            Field::Name(n) => self.name = n,
+            Field::Icon(i) => self.icon = i,
+            Field::Consumes(c) => self.consumes = c,
+            Field::Effects(e) => self.effects = e,
+        }
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+    fn set_doc(&mut self, doc: Option<String>) {
+        self.doc = doc;
+    }
+}
+
 impl<'s> DataParser<'s> for SpeciesTraitData {
     type Token = SpeciesToken;
+    const KEYWORDS: &'static [&'static str] = &[
+        "replace",
+        "patch",
+        "delete",
+        "template",
+        "extends",
+        "name",
+        "icon",
+        "good_id",
+        "amount",
+        "consumes",
+        "effects",
+        "growth_rate",
+    ];
+    const SCHEMA: &'static [FieldSchema<Self>] =
+        &[FieldSchema::required("name"), FieldSchema::required("icon")];
     fn parse_tokens(
         tokens: Vec<(usize, Self::Token, usize)>,
-    ) -> Result<Vec<SpeciesTraitData>, lalrpop_util::ParseError<usize, Self::Token, String>> {
-        species_trait::SpeciesTraitsParser::new().parse(tokens)
+        errors: &mut Vec<lalrpop_util::ErrorRecovery<usize, Self::Token, String>>,
+    ) -> ParsedEntries<Self, Self::Token> {
+        species_trait::SpeciesTraitsParser::new().parse(errors, tokens)
+    }
+}
+
+impl PrintScript for SpeciesTraitData {
+    const SECTION: &'static str = "#specie_traits";
+    fn print(&self, w: &mut ScriptWriter) {
+        w.line(quoted(&self.id));
+        w.indented(|w| {
+            w.field_unless_default("name", quoted(&self.name), self.name.is_empty());
+            w.field_unless_default("icon", quoted(&self.icon), self.icon.is_empty());
+            w.list("consumes", &self.consumes, good_amount);
+            if !self.effects.is_empty() {
+                w.block("effects", |w| {
+                    for effect in &self.effects {
+                        match effect {
+                            Effect::GrowthRate(g) => w.field("growth_rate", decimal(*g)),
+                        }
+                    }
+                });
+            }
+        });
     }
 }
@@ -1,26 +1,45 @@
 use std::fmt;
 
 use lalrpop_util::lalrpop_mod;
-use logos::Logos;
+use logos::{Lexer, Logos};
 use rust_decimal::prelude::*;
 
 use crate::{
     LexicalError,
-    common::{DataParser, GoodConsumes},
+    common::{self, DataParser, GoodConsumes, LexExtras, NumberToken, ParsedEntries, Patchable},
+    printer::{PrintScript, ScriptWriter, decimal, good_amount, quoted},
+    schema::FieldSchema,
 };
 
 #[derive(Logos, Clone, Debug, PartialEq)]
-#[logos(skip r"[\s\t\f]+", error = LexicalError)]
+#[logos(skip r"[\s\t\f]+", error = LexicalError, extras = LexExtras)]
 #[logos(skip r"//[^\n\r]*")]
+#[logos(skip(r"///([^/\n\r][^\n\r]*)?", common::doc_comment))]
+#[logos(skip(r"/\*", common::block_comment))]
 pub enum StapledonToken {
-    #[regex(r#""[^"]*""#, |lex| lex.slice().trim_matches('"').to_string())]
+    #[token("replace")]
+    Replace,
+    #[token("patch")]
+    Patch,
+    #[token("delete")]
+    Delete,
+
+    #[regex(r#""([^"\\]|\\.)*""#, common::string)]
+    #[regex(r#""""([^"]|"[^"]|""[^"])*""""#, common::string)]
     String(String),
 
-    #[regex(r"(\d+)", |lex| lex.slice().parse::<u8>().expect("parsing u8"), priority = 5)]
+    #[regex(r"[0-9][0-9_]*([eE]\+?[0-9]+)?", common::integer::<u8>, priority = 5)]
     Number(u8),
 
-    #[regex(r"(-?\d+\.\d*)", |lex| Decimal::from_str(lex.slice()).expect("parsed_decimal"), priority = 4)]
+    #[regex(
+        r"-?[0-9][0-9_]*(\.[0-9_]*)?([eE][+-]?[0-9]+)?",
+        common::decimal,
+        priority = 4
+    )]
     DecimalNumber(Decimal),
+    #[regex(r"\$[A-Za-z_][A-Za-z0-9_]*", common::expression)]
+    #[regex(r"\$\(", common::expression)]
+    Expression,
 
     #[token("=")]
     Equal,
@@ -67,9 +86,20 @@ impl fmt::Display for StapledonToken {
     }
 }
 
-lalrpop_mod!(pub stapledon_swarm);
+impl<'s> NumberToken<'s> for StapledonToken {
+    fn number(lex: &mut Lexer<'s, Self>, value: Decimal) -> Self {
+        if value.scale() == 0 {
+            StapledonToken::Number(common::fit(lex, value))
+        } else {
+            StapledonToken::DecimalNumber(value)
+        }
+    }
+}
 
-#[derive(Clone, Default, Debug)]
+lalrpop_mod!(#[allow(clippy::ptr_arg)] pub stapledon_swarm);
+
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StapledonSwarmData {
     pub level: u8,
     pub name: String,
@@ -82,8 +112,11 @@ pub struct StapledonSwarmData {
 
     pub costs: Vec<GoodConsumes>,
     pub upkeep: Vec<GoodConsumes>,
+    /// Doc comments written above where the entry is defined or replaced
+    pub doc: Option<String>,
 }
 
+#[derive(Clone, Debug)]
 pub enum Field {
     Name(String),
     SwarmAsset(String),
@@ -94,11 +127,83 @@ pub enum Field {
     Power(Decimal),
 }
 
+impl Patchable for StapledonSwarmData {
+    type Key = u8;
+    type Field = Field;
+    fn key(&self) -> &Self::Key {
+        &self.level
+    }
+    fn set_key(&mut self, key: Self::Key) {
+        self.level = key;
+    }
+    fn apply(&mut self, field: Self::Field) {
+        match field {
+            Field::Name(n) => self.name = n,
+            Field::SwarmAsset(a) => self.swarm_asset = a,
+            Field::ReceiverAsset(a) => self.receiver_asset = a,
+            Field::Cost(c) => self.costs = c,
+            Field::Upkeep(c) => self.upkeep = c,
+            Field::Time(t) => self.time = t,
+            Field::Power(p) => self.power = p,
+        }
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+    fn set_doc(&mut self, doc: Option<String>) {
+        self.doc = doc;
+    }
+}
+
 impl<'s> DataParser<'s> for StapledonSwarmData {
     type Token = StapledonToken;
+    const KEYWORDS: &'static [&'static str] = &[
+        "replace",
+        "patch",
+        "delete",
+        "name",
+        "swarm_asset",
+        "receiver_asset",
+        "consumes",
+        "upkeep",
+        "good_id",
+        "amount",
+        "power",
+        "time",
+    ];
+    const SCHEMA: &'static [FieldSchema<Self>] = &[
+        FieldSchema::required("name"),
+        FieldSchema::required("swarm_asset"),
+        FieldSchema::required("receiver_asset"),
+        FieldSchema::optional("power").range(Some(Decimal::ZERO), None, |s| s.power),
+    ];
     fn parse_tokens(
         tokens: Vec<(usize, Self::Token, usize)>,
-    ) -> Result<Vec<StapledonSwarmData>, lalrpop_util::ParseError<usize, Self::Token, String>> {
-        stapledon_swarm::StapledonDataParser::new().parse(tokens)
+        errors: &mut Vec<lalrpop_util::ErrorRecovery<usize, Self::Token, String>>,
+    ) -> ParsedEntries<Self, Self::Token> {
+        stapledon_swarm::StapledonDataParser::new().parse(errors, tokens)
+    }
+}
+
+impl PrintScript for StapledonSwarmData {
+    const SECTION: &'static str = "#stapledon_swarm";
+    fn print(&self, w: &mut ScriptWriter) {
+        w.block(format_args!("{} =", self.level), |w| {
+            w.field_unless_default("name", quoted(&self.name), self.name.is_empty());
+            w.field_unless_default(
+                "swarm_asset",
+                quoted(&self.swarm_asset),
+                self.swarm_asset.is_empty(),
+            );
+            w.field_unless_default(
+                "receiver_asset",
+                quoted(&self.receiver_asset),
+                self.receiver_asset.is_empty(),
+            );
+            w.list("consumes", &self.costs, good_amount);
+            w.list("upkeep", &self.upkeep, good_amount);
+            w.field_unless_default("power", decimal(self.power), self.power.is_zero());
+            w.field_unless_default("time", self.time, self.time == 0);
+        });
     }
 }
@@ -1,30 +1,49 @@
 use std::fmt;
 
 use lalrpop_util::lalrpop_mod;
-use logos::Logos;
+use logos::{Lexer, Logos};
 use rust_decimal::prelude::*;
 
 use crate::{
     LexicalError,
-    common::{DataParser, Temperature},
+    common::{self, DataParser, LexExtras, NumberToken, ParsedEntries, Patchable, Temperature},
+    printer::{PrintScript, ScriptWriter, boolean, decimal, quoted, temperature},
+    schema::FieldSchema,
 };
 
 #[derive(Logos, Clone, Debug, PartialEq)]
-#[logos(skip r"[\s\t\f]+", error = LexicalError)]
+#[logos(skip r"[\s\t\f]+", error = LexicalError, extras = LexExtras)]
 #[logos(skip r"//[^\n\r]*")]
+#[logos(skip(r"///([^/\n\r][^\n\r]*)?", common::doc_comment))]
+#[logos(skip(r"/\*", common::block_comment))]
 pub enum StellarToken {
+    #[token("replace")]
+    Replace,
+    #[token("patch")]
+    Patch,
+    #[token("delete")]
+    Delete,
+
     #[token("true")]
     True,
     #[token("false")]
     False,
-    #[regex(r#""[^"]*""#, |lex| lex.slice().trim_matches('"').to_string())]
+    #[regex(r#""([^"\\]|\\.)*""#, common::string)]
+    #[regex(r#""""([^"]|"[^"]|""[^"])*""""#, common::string)]
     String(String),
 
-    #[regex(r"(\d+)", |lex|lex.slice().parse::<u16>().expect("parsing u8"), priority = 5)]
+    #[regex(r"[0-9][0-9_]*([eE]\+?[0-9]+)?", common::integer::<u16>, priority = 5)]
     Number(u16),
 
-    #[regex(r"(-?\d+\.?\d*)", |lex| Decimal::from_str(lex.slice()).expect("parsed_decimal"), priority = 4)]
+    #[regex(
+        r"-?[0-9][0-9_]*(\.[0-9_]*)?([eE][+-]?[0-9]+)?",
+        common::decimal,
+        priority = 4
+    )]
     DecimalNumber(Decimal),
+    #[regex(r"\$[A-Za-z_][A-Za-z0-9_]*", common::expression)]
+    #[regex(r"\$\(", common::expression)]
+    Expression,
 
     #[token("=")]
     Equal,
@@ -106,17 +125,31 @@ impl fmt::Display for StellarToken {
     }
 }
 
-lalrpop_mod!(pub stellar_system);
+impl<'s> NumberToken<'s> for StellarToken {
+    fn number(lex: &mut Lexer<'s, Self>, value: Decimal) -> Self {
+        if value.scale() == 0 {
+            StellarToken::Number(common::fit(lex, value))
+        } else {
+            StellarToken::DecimalNumber(value)
+        }
+    }
+}
+
+lalrpop_mod!(#[allow(clippy::ptr_arg)] pub stellar_system);
 
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StellarData {
     pub id: u16,
     pub star_data: StarData,
     pub orbiting: Vec<StellarObject>,
     pub surveyed: bool,
+    /// Doc comments written above where the entry is defined or replaced
+    pub doc: Option<String>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 //Majority case is the large data structure variant and it will be cleaned up on program startup
 // #[allow(clippy::large_enum_variant)]
 pub enum StellarObject {
@@ -125,7 +158,8 @@ pub enum StellarObject {
     AsteroidBelt(Vec<PlanetData>),
 }
 
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct StarData {
     pub name: String,
     pub asset_location: String,
@@ -134,7 +168,8 @@ pub struct StarData {
     pub star_type: String,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PlanetData {
     pub name: String,
 
@@ -177,7 +212,8 @@ impl Default for PlanetData {
     }
 }
 
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MoonData {
     pub name: String,
     pub asset_location: String,
@@ -195,18 +231,21 @@ pub struct MoonData {
     pub capital: bool,
 }
 
-#[derive(Clone, Default, Debug)]
+#[derive(Clone, Default, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct NaturalResource {
     pub id: String,
     pub amount: Decimal,
 }
 
+#[derive(Clone, Debug)]
 pub enum StellarField {
     StarData(StarData),
     Orbiting(StellarObject),
     Surveyed(bool),
 }
 
+#[derive(Clone, Debug)]
 pub enum StarField {
     Name(String),
     AssetLocation(String),
@@ -214,6 +253,7 @@ pub enum StarField {
     StarType(String),
 }
 
+#[derive(Clone, Debug)]
 pub enum PlanetField {
     Name(String),
     AssetLocation(String),
@@ -230,6 +270,7 @@ pub enum PlanetField {
     Capital(bool),
 }
 
+#[derive(Clone, Debug)]
 pub enum MoonField {
     Name(String),
     AssetLocation(String),
@@ -244,11 +285,194 @@ pub enum MoonField {
     Capital(bool),
 }
 
+impl Patchable for StellarData {
+    type Key = u16;
+    type Field = StellarField;
+    fn key(&self) -> &Self::Key {
+        &self.id
+    }
+    fn set_key(&mut self, key: Self::Key) {
+        self.id = key;
+    }
+    fn apply(&mut self, field: Self::Field) {
+        match field {
+            StellarField::StarData(s) => self.star_data = s,
+            StellarField::Orbiting(o) => self.orbiting.push(o),
+            StellarField::Surveyed(b) => self.surveyed = b,
+        }
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+    fn set_doc(&mut self, doc: Option<String>) {
+        self.doc = doc;
+    }
+}
+
 impl<'s> DataParser<'s> for StellarData {
     type Token = StellarToken;
+    const KEYWORDS: &'static [&'static str] = &[
+        "replace",
+        "patch",
+        "delete",
+        "true",
+        "false",
+        "name",
+        "asset_location",
+        "size",
+        "surveyed",
+        "star_data",
+        "planet_data",
+        "moon_data",
+        "asteroid_belt",
+        "star_type",
+        "planet_type",
+        "magnetosphere",
+        "atmosphere",
+        "temperature_kelvin",
+        "temperature_celsius",
+        "water",
+        "breathability",
+        "natural_resources",
+        "ring",
+        "good_id",
+        "amount",
+        "capital",
+    ];
+    const REPEATABLE_FIELDS: &'static [&'static str] = &["planet_data", "asteroid_belt"];
+    const SCHEMA: &'static [FieldSchema<Self>] = &[FieldSchema::required("star_data")];
     fn parse_tokens(
         tokens: Vec<(usize, Self::Token, usize)>,
-    ) -> Result<Vec<StellarData>, lalrpop_util::ParseError<usize, Self::Token, String>> {
-        stellar_system::StellarDataParser::new().parse(tokens)
+        errors: &mut Vec<lalrpop_util::ErrorRecovery<usize, Self::Token, String>>,
+    ) -> ParsedEntries<Self, Self::Token> {
+        stellar_system::StellarDataParser::new().parse(errors, tokens)
+    }
+}
+
+impl PrintScript for StellarData {
+    const SECTION: &'static str = "#stellar_system";
+    fn print(&self, w: &mut ScriptWriter) {
+        w.block(format_args!("{} =", self.id), |w| {
+            let star = &self.star_data;
+            w.block("star_data =", |w| {
+                w.field_unless_default("name", quoted(&star.name), star.name.is_empty());
+                w.field_unless_default(
+                    "asset_location",
+                    quoted(&star.asset_location),
+                    star.asset_location.is_empty(),
+                );
+                temperature(w, &star.temperature);
+                w.field_unless_default(
+                    "star_type",
+                    quoted(&star.star_type),
+                    star.star_type.is_empty(),
+                );
+                // The size of a star can't be set from a script yet
+            });
+            for orbiting in &self.orbiting {
+                match orbiting {
+                    StellarObject::PlanetData(p) => print_planet(w, p),
+                    StellarObject::AsteroidBelt(belt) => w.block("asteroid_belt =", |w| {
+                        belt.iter().for_each(|p| print_planet(w, p));
+                    }),
+                }
+            }
+            w.field_unless_default("surveyed", boolean(self.surveyed), !self.surveyed);
+        });
+    }
+}
+
+fn print_planet(w: &mut ScriptWriter, planet: &PlanetData) {
+    w.block("planet_data =", |w| {
+        print_body(
+            w,
+            Body {
+                name: &planet.name,
+                asset_location: &planet.asset_location,
+                size: planet.size,
+                default_size: PlanetData::default().size,
+                planet_type: &planet.planet_type,
+                magnetosphere: planet.magnetosphere,
+                atmosphere: planet.atmosphere,
+                temperature: &planet.temperature,
+                water: planet.water,
+                breathability: planet.breathability,
+                natural_resources: &planet.natural_resources,
+                capital: planet.capital,
+            },
+        );
+        if planet.ring {
+            w.line("ring");
+        }
+        for moon in &planet.moons {
+            w.block("moon_data =", |w| {
+                print_body(
+                    w,
+                    Body {
+                        name: &moon.name,
+                        asset_location: &moon.asset_location,
+                        size: moon.size,
+                        default_size: MoonData::default().size,
+                        planet_type: &moon.planet_type,
+                        magnetosphere: moon.magnetosphere,
+                        atmosphere: moon.atmosphere,
+                        temperature: &moon.temperature,
+                        water: moon.water,
+                        breathability: moon.breathability,
+                        natural_resources: &moon.natural_resources,
+                        capital: moon.capital,
+                    },
+                )
+            });
+        }
+    });
+}
+
+/// The fields planets and moons share
+struct Body<'a> {
+    name: &'a str,
+    asset_location: &'a str,
+    size: u16,
+    default_size: u16,
+    planet_type: &'a str,
+    magnetosphere: Decimal,
+    atmosphere: Decimal,
+    temperature: &'a Temperature,
+    water: Decimal,
+    breathability: Decimal,
+    natural_resources: &'a [NaturalResource],
+    capital: bool,
+}
+
+fn print_body(w: &mut ScriptWriter, body: Body) {
+    w.field_unless_default("name", quoted(body.name), body.name.is_empty());
+    w.field_unless_default(
+        "asset_location",
+        quoted(body.asset_location),
+        body.asset_location.is_empty(),
+    );
+    w.field_unless_default("size", body.size, body.size == body.default_size);
+    w.field_unless_default(
+        "planet_type",
+        quoted(body.planet_type),
+        body.planet_type.is_empty(),
+    );
+    let values = [
+        ("magnetosphere", body.magnetosphere),
+        ("atmosphere", body.atmosphere),
+        ("water", body.water),
+        ("breathability", body.breathability),
+    ];
+    for (name, value) in values {
+        w.field_unless_default(name, decimal(value), value.is_zero());
     }
+    temperature(w, body.temperature);
+    w.list("natural_resources", body.natural_resources, |r| {
+        format!(
+            "{{ good_id : {} amount : {} }}",
+            quoted(&r.id),
+            decimal(r.amount)
+        )
+    });
+    w.field_unless_default("capital", boolean(body.capital), !body.capital);
 }
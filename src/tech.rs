@@ -1,35 +1,65 @@
 use std::fmt;
 
 use lalrpop_util::lalrpop_mod;
+use rust_decimal::Decimal;
 
-use logos::{self, Logos};
+use logos::{self, Lexer, Logos};
 
-use crate::{LexicalError, common::DataParser};
+use crate::{
+    LexicalError,
+    common::{self, DataParser, LexExtras, NumberToken, ParsedEntries, Patchable},
+    printer::{PrintScript, ScriptWriter, quoted},
+    schema::FieldSchema,
+};
 
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct TechData {
     pub id: String,
     pub name: String,
     pub time: u8,
     pub description: String,
+    /// Techs that have to be researched before this one
+    pub prerequisites: Vec<String>,
+    /// Doc comments written above where the entry is defined or replaced
+    pub doc: Option<String>,
 }
 
 #[derive(Logos, Clone, Debug, PartialEq)]
-#[logos(skip r"[\s\t\f]+", error = LexicalError)]
+#[logos(skip r"[\s\t\f]+", error = LexicalError, extras = LexExtras)]
 #[logos(skip r"//[^\n\r]*")]
+#[logos(skip(r"///([^/\n\r][^\n\r]*)?", common::doc_comment))]
+#[logos(skip(r"/\*", common::block_comment))]
 pub enum TechToken {
+    #[token("replace")]
+    Replace,
+    #[token("patch")]
+    Patch,
+    #[token("delete")]
+    Delete,
+
     #[token("=")]
     Equal,
-    #[regex(r#""[^"]*""#, |lex| lex.slice().trim_matches('"').to_string())]
+    #[regex(r#""([^"\\]|\\.)*""#, common::string)]
+    #[regex(r#""""([^"]|"[^"]|""[^"])*""""#, common::string)]
     String(String),
-    #[regex(r#"\d+"#, |lex| lex.slice().parse::<u8>().unwrap())]
+    #[regex(r"-?[0-9][0-9_]*([eE]\+?[0-9]+)?", common::integer::<u8>, priority = 5)]
     Number(u8),
+    #[regex(r"\$[A-Za-z_][A-Za-z0-9_]*", common::expression)]
+    #[regex(r"\$\(", common::expression)]
+    Expression,
     #[token("name")]
     Name,
     #[token("time")]
     Time,
     #[token("description")]
     Description,
+    #[token("prerequisites")]
+    Prerequisites,
+    #[token("[")]
+    LeftSquare,
+    #[token("]")]
+    RightSquare,
 }
 
 impl fmt::Display for TechToken {
@@ -37,19 +67,85 @@ impl fmt::Display for TechToken {
         write!(f, "{self:?}")
     }
 }
+
+impl<'s> NumberToken<'s> for TechToken {
+    fn number(lex: &mut Lexer<'s, Self>, value: Decimal) -> Self {
+        TechToken::Number(common::fit(lex, value))
+    }
+}
+#[derive(Clone, Debug)]
 pub enum Field {
     Time(u8),
     Name(String),
     Description(String),
+    Prerequisites(Vec<String>),
 }
 
-lalrpop_mod!(pub tech);
+lalrpop_mod!(#[allow(clippy::ptr_arg)] pub tech);
+
+impl Patchable for TechData {
+    type Key = String;
+    type Field = Field;
+    fn key(&self) -> &Self::Key {
+        &self.id
+    }
+    fn set_key(&mut self, key: Self::Key) {
+        self.id = key;
+    }
+    fn apply(&mut self, field: Self::Field) {
+        match field {
+            Field::Time(t) => self.time = t,
+            Field::Name(n) => self.name = n,
+            Field::Description(d) => self.description = d,
+            Field::Prerequisites(p) => self.prerequisites = p,
+        }
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+    fn set_doc(&mut self, doc: Option<String>) {
+        self.doc = doc;
+    }
+}
 
 impl<'s> DataParser<'s> for TechData {
     type Token = TechToken;
+    const KEYWORDS: &'static [&'static str] = &[
+        "replace",
+        "patch",
+        "delete",
+        "name",
+        "time",
+        "description",
+        "prerequisites",
+    ];
+    const SCHEMA: &'static [FieldSchema<Self>] = &[
+        FieldSchema::required("name"),
+        FieldSchema::required("time").range(Some(Decimal::ONE), None, |t| t.time.into()),
+    ];
     fn parse_tokens(
         tokens: Vec<(usize, Self::Token, usize)>,
-    ) -> Result<Vec<TechData>, lalrpop_util::ParseError<usize, Self::Token, String>> {
-        tech::TechsParser::new().parse(tokens)
+        errors: &mut Vec<lalrpop_util::ErrorRecovery<usize, Self::Token, String>>,
+    ) -> ParsedEntries<Self, Self::Token> {
+        tech::TechsParser::new().parse(errors, tokens)
+    }
+}
+
+impl PrintScript for TechData {
+    const SECTION: &'static str = "#tech";
+    fn print(&self, w: &mut ScriptWriter) {
+        w.line(quoted(&self.id));
+        w.indented(|w| {
+            w.field_unless_default("name", quoted(&self.name), self.name.is_empty());
+            w.field_unless_default("time", self.time, self.time == 0);
+            w.field_unless_default(
+                "description",
+                quoted(&self.description),
+                self.description.is_empty(),
+            );
+            w.list("prerequisites", &self.prerequisites, |p| {
+                quoted(p).to_string()
+            });
+        });
     }
 }
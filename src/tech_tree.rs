@@ -0,0 +1,305 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt::Write,
+};
+
+use crate::{
+    ParseData,
+    graph::{self, Edges, dot_quoted},
+};
+
+/// Something that can only be built or used once a tech is researched
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Unlock<'d> {
+    Building(&'d str),
+    Designation(&'d str),
+}
+
+impl Unlock<'_> {
+    pub fn id(&self) -> &str {
+        match self {
+            Unlock::Building(id) | Unlock::Designation(id) => id,
+        }
+    }
+}
+
+/// A tech along with what it needs and what it unlocks
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TechNode<'d> {
+    pub id: &'d str,
+    pub name: &'d str,
+    pub time: u8,
+    pub prerequisites: Vec<&'d str>,
+    pub unlocks: Vec<Unlock<'d>>,
+    /// The least total time to research this along with all of its prerequisites,
+    /// `None` when it is part of a cycle
+    pub research_time: Option<u32>,
+}
+
+/// Every tech, how they depend on each other and what they unlock
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct TechTree<'d> {
+    /// In the order they were loaded
+    pub techs: Vec<TechNode<'d>>,
+}
+
+impl ParseData {
+    /// The techs along with the buildings and designations each one unlocks
+    pub fn tech_tree(&self) -> TechTree<'_> {
+        TechTree::new(self)
+    }
+}
+
+impl<'d> TechTree<'d> {
+    pub fn new(data: &'d ParseData) -> Self {
+        let buildings = data
+            .building_data
+            .iter()
+            .filter_map(|b| Some((b.tech_needed.as_deref()?, Unlock::Building(&b.id))));
+        let designations = data
+            .designation_data
+            .iter()
+            .filter_map(|d| Some((d.tech_required.as_deref()?, Unlock::Designation(&d.id))));
+        let unlocks: Vec<_> = buildings.chain(designations).collect();
+
+        let techs = data
+            .tech_data
+            .iter()
+            .map(|t| TechNode {
+                id: &t.id,
+                name: &t.name,
+                time: t.time,
+                prerequisites: t.prerequisites.iter().map(String::as_str).collect(),
+                unlocks: unlocks
+                    .iter()
+                    .filter(|(tech, _)| *tech == t.id)
+                    .map(|(_, unlock)| *unlock)
+                    .collect(),
+                research_time: None,
+            })
+            .collect();
+        let mut tree = TechTree { techs };
+        let times = tree.research_times();
+        for (tech, time) in tree.techs.iter_mut().zip(times) {
+            tech.research_time = time;
+        }
+        tree
+    }
+
+    pub fn get(&self, id: &str) -> Option<&TechNode<'d>> {
+        self.techs.iter().find(|t| t.id == id)
+    }
+
+    /// The tech that unlocks a building or designation
+    pub fn unlocked_by(&self, unlock: Unlock) -> Option<&TechNode<'d>> {
+        self.techs.iter().find(|t| t.unlocks.contains(&unlock))
+    }
+
+    /// `id` and every tech that has to be researched before it
+    pub fn required_for(&self, id: &str) -> BTreeSet<&'d str> {
+        let edges = self.prerequisite_edges();
+        let start = self.get(id).map(|t| t.id);
+        graph::reachable(&edges, start)
+    }
+
+    /// Groups of techs that each need the others first, none of them can be researched
+    pub fn cycles(&self) -> Vec<Vec<&'d str>> {
+        graph::cycles(&self.prerequisite_edges())
+    }
+
+    /// The least total time to research `id` along with all of its prerequisites,
+    /// `None` when the tech is unknown or part of a cycle
+    pub fn research_time(&self, id: &str) -> Option<u32> {
+        self.get(id)?.research_time
+    }
+
+    /// The least total research time before a building or designation can be used,
+    /// `None` when no tech unlocks it or its tech can't be researched
+    pub fn unlock_time(&self, unlock: Unlock) -> Option<u32> {
+        self.research_time(self.unlocked_by(unlock)?.id)
+    }
+
+    /// The tree in Graphviz DOT, techs point at the techs that need them
+    /// and at what they unlock
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph tech {\n");
+        for tech in &self.techs {
+            let label = format!("{} ({})", tech.name, tech.time);
+            let _ = writeln!(
+                dot,
+                "    {} [label={}];",
+                dot_quoted(tech.id),
+                dot_quoted(&label)
+            );
+        }
+        for tech in &self.techs {
+            for prerequisite in &tech.prerequisites {
+                let _ = writeln!(
+                    dot,
+                    "    {} -> {};",
+                    dot_quoted(prerequisite),
+                    dot_quoted(tech.id)
+                );
+            }
+            for unlock in &tech.unlocks {
+                // Buildings and designations can share ids so their nodes are prefixed
+                let (kind, shape) = match unlock {
+                    Unlock::Building(_) => ("building", "box"),
+                    Unlock::Designation(_) => ("designation", "diamond"),
+                };
+                let node = dot_quoted(&format!("{kind} {}", unlock.id()));
+                let _ = writeln!(
+                    dot,
+                    "    {node} [label={}, shape={shape}];",
+                    dot_quoted(unlock.id())
+                );
+                let _ = writeln!(dot, "    {} -> {node};", dot_quoted(tech.id));
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// The research time of each tech, found in one pass that handles prerequisites
+    /// before the techs needing them so each tech's requirements are only gathered once
+    fn research_times(&self) -> Vec<Option<u32>> {
+        enum Visit {
+            Unvisited,
+            InProgress,
+            /// The techs needed including itself, `None` when they include a cycle
+            Done(Option<BTreeSet<usize>>),
+        }
+
+        let mut index = HashMap::new();
+        for (i, tech) in self.techs.iter().enumerate().rev() {
+            index.insert(tech.id, i);
+        }
+        // Prerequisites that are not known techs are left for validation
+        let prerequisites: Vec<Vec<usize>> = self
+            .techs
+            .iter()
+            .map(|t| {
+                t.prerequisites
+                    .iter()
+                    .filter_map(|p| index.get(p).copied())
+                    .collect()
+            })
+            .collect();
+        let mut visits: Vec<_> = self.techs.iter().map(|_| Visit::Unvisited).collect();
+        for start in 0..self.techs.len() {
+            if !matches!(visits[start], Visit::Unvisited) {
+                continue;
+            }
+            visits[start] = Visit::InProgress;
+            let mut stack = vec![(start, 0)];
+            while let Some((i, next)) = stack.last_mut() {
+                let i = *i;
+                if let Some(&p) = prerequisites[i].get(*next) {
+                    *next += 1;
+                    if matches!(visits[p], Visit::Unvisited) {
+                        visits[p] = Visit::InProgress;
+                        stack.push((p, 0));
+                    }
+                    continue;
+                }
+                stack.pop();
+                // A prerequisite still in progress leads back round to this tech
+                let mut required = Some(BTreeSet::from([i]));
+                for &p in &prerequisites[i] {
+                    match (&visits[p], &mut required) {
+                        (Visit::Done(Some(theirs)), Some(required)) => required.extend(theirs),
+                        _ => required = None,
+                    }
+                }
+                visits[i] = Visit::Done(required);
+            }
+        }
+        visits
+            .into_iter()
+            .map(|visit| match visit {
+                Visit::Done(Some(required)) => Some(
+                    required
+                        .into_iter()
+                        .map(|t| u32::from(self.techs[t].time))
+                        .sum(),
+                ),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// The prerequisites of each tech
+    fn prerequisite_edges(&self) -> Edges<'d> {
+        self.techs
+            .iter()
+            .map(|t| (t.id, t.prerequisites.iter().copied().collect()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parse;
+
+    #[test]
+    fn shared_prerequisites_count_once() {
+        let data = parse(
+            "test.txt",
+            r#"#tech
+"base"
+    name = "Base"
+    time = 1
+"left"
+    name = "Left"
+    time = 2
+    prerequisites = [ "base" ]
+"right"
+    name = "Right"
+    time = 4
+    prerequisites = [ "base" ]
+"top"
+    name = "Top"
+    time = 8
+    prerequisites = [ "left" "right" ]
+"#,
+        )
+        .unwrap();
+        let tree = data.tech_tree();
+        assert_eq!(tree.research_time("base"), Some(1));
+        assert_eq!(tree.research_time("left"), Some(3));
+        assert_eq!(tree.research_time("top"), Some(15));
+    }
+
+    #[test]
+    fn cycle_has_no_research_time() {
+        let data = parse(
+            "test.txt",
+            r#"#tech
+"a"
+    name = "A"
+    time = 1
+    prerequisites = [ "b" ]
+"b"
+    name = "B"
+    time = 1
+    prerequisites = [ "a" ]
+"after"
+    name = "After"
+    time = 1
+    prerequisites = [ "b" ]
+"apart"
+    name = "Apart"
+    time = 1
+"#,
+        )
+        .unwrap();
+        let tree = data.tech_tree();
+        assert_eq!(tree.research_time("a"), None);
+        assert_eq!(tree.research_time("b"), None);
+        assert_eq!(tree.research_time("after"), None);
+        assert_eq!(tree.research_time("apart"), Some(1));
+    }
+}
@@ -0,0 +1,274 @@
+use std::{collections::BTreeSet, fmt::Write};
+
+use crate::{
+    ParseData,
+    graph::{self, Edges, dot_quoted},
+    planet_types::{Action, Branch, Condition, IfCondition, PlanetTypeData, condition_text},
+};
+
+/// The rules a transition was found in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Setup,
+    Terraform,
+}
+
+/// A planet type becoming another planet type
+#[derive(Clone, Debug, PartialEq)]
+pub struct Transition<'d> {
+    pub from: &'d str,
+    pub to: &'d str,
+    pub stage: Stage,
+    /// What has to hold for the transition to happen, including earlier arms
+    /// of each if not holding, `None` when it always happens
+    pub guard: Option<Condition>,
+}
+
+/// Every planet type and every `set_planet_type` in their rules
+#[derive(Clone, Debug, Default)]
+pub struct TerraformGraph<'d> {
+    /// In the order they were loaded
+    pub planet_types: Vec<&'d str>,
+    pub transitions: Vec<Transition<'d>>,
+}
+
+impl ParseData {
+    /// The transitions between all planet types
+    pub fn terraform_graph(&self) -> TerraformGraph<'_> {
+        TerraformGraph::new(&self.planet_type_data)
+    }
+}
+
+impl<'d> TerraformGraph<'d> {
+    pub fn new(planet_types: &'d [PlanetTypeData]) -> Self {
+        let mut transitions = Vec::new();
+        for planet_type in planet_types {
+            let stages = [
+                (Stage::Setup, &planet_type.setup_conditions),
+                (Stage::Terraform, &planet_type.terraform_conditions),
+            ];
+            for (stage, branches) in stages {
+                for branch in branches {
+                    branch_transitions(&planet_type.name, stage, branch, &[], &mut transitions);
+                }
+            }
+        }
+        TerraformGraph {
+            planet_types: planet_types.iter().map(|p| p.name.as_str()).collect(),
+            transitions,
+        }
+    }
+
+    /// Transitions away from `planet_type`
+    pub fn from<'g>(&'g self, planet_type: &'g str) -> impl Iterator<Item = &'g Transition<'d>> {
+        self.transitions
+            .iter()
+            .filter(move |t| t.from == planet_type)
+    }
+
+    /// Transitions into `planet_type`
+    pub fn to<'g>(&'g self, planet_type: &'g str) -> impl Iterator<Item = &'g Transition<'d>> {
+        self.transitions.iter().filter(move |t| t.to == planet_type)
+    }
+
+    /// Every planet type that can be reached from `starts` through any number
+    /// of transitions, including the starts themselves
+    pub fn reachable_from<'s>(
+        &self,
+        starts: impl IntoIterator<Item = &'s str>,
+    ) -> BTreeSet<&'d str> {
+        let starts = starts.into_iter().filter_map(|s| self.node(s));
+        graph::reachable(&self.edges(), starts)
+    }
+
+    /// Planet types that can't be reached from `starts`,
+    /// such as the planet types stellar systems are made of
+    pub fn unreachable_from<'s>(&self, starts: impl IntoIterator<Item = &'s str>) -> Vec<&'d str> {
+        let reached = self.reachable_from(starts);
+        self.planet_types
+            .iter()
+            .copied()
+            .filter(|p| !reached.contains(p))
+            .collect()
+    }
+
+    /// Groups of planet types that can each become the others and back again,
+    /// a planet type only set to itself is not a cycle
+    pub fn cycles(&self) -> Vec<Vec<&'d str>> {
+        graph::cycles(&self.edges())
+    }
+
+    /// The graph in Graphviz DOT, setup transitions are dashed
+    /// and each transition is labelled with its guard
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph terraform {\n");
+        for planet_type in &self.planet_types {
+            let _ = writeln!(dot, "    {};", dot_quoted(planet_type));
+        }
+        for transition in &self.transitions {
+            let mut attributes = Vec::new();
+            if let Some(guard) = &transition.guard {
+                attributes.push(format!("label={}", dot_quoted(&condition_text(guard))));
+            }
+            if transition.stage == Stage::Setup {
+                attributes.push("style=dashed".to_string());
+            }
+            let _ = write!(
+                dot,
+                "    {} -> {}",
+                dot_quoted(transition.from),
+                dot_quoted(transition.to)
+            );
+            if !attributes.is_empty() {
+                let _ = write!(dot, " [{}]", attributes.join(", "));
+            }
+            dot.push_str(";\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// The planet types each planet type can become, leaving out itself
+    fn edges(&self) -> Edges<'d> {
+        let mut edges = Edges::new();
+        for transition in self.transitions.iter().filter(|t| t.from != t.to) {
+            edges
+                .entry(transition.from)
+                .or_default()
+                .insert(transition.to);
+        }
+        edges
+    }
+
+    /// The graph's own copy of a planet type's name,
+    /// unknown planet types that transitions lead to are included
+    fn node(&self, planet_type: &str) -> Option<&'d str> {
+        self.planet_types
+            .iter()
+            .copied()
+            .chain(self.transitions.iter().map(|t| t.to))
+            .find(|p| *p == planet_type)
+    }
+}
+
+/// Adds a transition for every `set_planet_type` in `branch`,
+/// `guards` are the conditions of the arms it is nested in
+fn branch_transitions<'d>(
+    from: &'d str,
+    stage: Stage,
+    branch: &'d Branch,
+    guards: &[Condition],
+    transitions: &mut Vec<Transition<'d>>,
+) {
+    let arms = branch.if_conditions.iter().enumerate().map(|(i, arm)| {
+        let mut arm_guards = not_taken(&branch.if_conditions[..i]);
+        match &arm.condition {
+            Condition::And(conditions) => arm_guards.extend(conditions.iter().cloned()),
+            condition => arm_guards.push(condition.clone()),
+        }
+        (arm_guards, &arm.actions)
+    });
+    let otherwise = (not_taken(&branch.if_conditions), &branch.else_actions);
+    for (arm_guards, actions) in arms.chain([otherwise]) {
+        let guards: Vec<_> = guards.iter().cloned().chain(arm_guards).collect();
+        for action in actions {
+            match action {
+                Action::SetPlanetType(to) => transitions.push(Transition {
+                    from,
+                    to,
+                    stage,
+                    guard: match guards.as_slice() {
+                        [] => None,
+                        [guard] => Some(guard.clone()),
+                        _ => Some(Condition::And(guards.clone())),
+                    },
+                }),
+                Action::Branch(b) => branch_transitions(from, stage, b, &guards, transitions),
+                Action::None
+                | Action::SetAsset(_)
+                | Action::SetStored(_, _)
+                | Action::SetStoredRandom(_, _) => {}
+            }
+        }
+    }
+}
+
+/// What has to hold for none of `arms` to be taken
+fn not_taken(arms: &[IfCondition]) -> Vec<Condition> {
+    arms.iter()
+        .map(|a| Condition::Not(Box::new(a.condition.clone())))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parse;
+
+    fn planet_types() -> ParseData {
+        parse(
+            "test.txt",
+            r#"#planet_types
+"barren"
+    class = rocky
+    set_asset = "barren"
+    on_terraform {
+        if water_level GT 0.5 {
+            set_planet_type = "ocean"
+        } else {
+            set_planet_type = "barren"
+        }
+    }
+"ocean"
+    class = atmospheric
+    set_asset = "ocean"
+    on_terraform {
+        if temperature_celsius LT 0 {
+            set_planet_type = "ice"
+        }
+    }
+"ice"
+    class = rocky
+    set_asset = "ice"
+    on_terraform {
+        if temperature_celsius GT 0 {
+            set_planet_type = "ocean"
+        }
+    }
+"lost"
+    class = rocky
+    set_asset = "lost"
+"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn else_arm_is_guarded_by_earlier_arms() {
+        let data = planet_types();
+        let graph = data.terraform_graph();
+        let from_barren: Vec<_> = graph.from("barren").collect();
+        assert_eq!(from_barren.len(), 2);
+        assert_eq!(from_barren[0].to, "ocean");
+        assert_eq!(from_barren[1].to, "barren");
+        assert!(matches!(from_barren[1].guard, Some(Condition::Not(_))));
+    }
+
+    #[test]
+    fn cycles_leave_out_planet_types_set_to_themselves() {
+        let data = planet_types();
+        let cycles = data.terraform_graph().cycles();
+        assert_eq!(cycles.len(), 1);
+        let mut cycle = cycles[0].clone();
+        cycle.sort();
+        assert_eq!(cycle, ["ice", "ocean"]);
+    }
+
+    #[test]
+    fn unreachable_planet_types() {
+        let data = planet_types();
+        let graph = data.terraform_graph();
+        assert_eq!(graph.unreachable_from(["barren"]), ["lost"]);
+        assert_eq!(graph.unreachable_from(["ice"]), ["barren", "lost"]);
+    }
+}
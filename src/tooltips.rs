@@ -3,13 +3,20 @@ use std::{collections::HashMap, fmt};
 use lalrpop_util::lalrpop_mod;
 use logos::Logos;
 
-use crate::{LexicalError, common::DataParser};
+use crate::{
+    LexicalError,
+    common::{self, DataParser, LexExtras, ParsedEntries, Patchable},
+    printer::{PrintScript, ScriptWriter, quoted},
+};
 
 #[derive(Logos, Clone, Debug, PartialEq)]
-#[logos(skip r"[\s\t\f]+", error = LexicalError)]
+#[logos(skip r"[\s\t\f]+", error = LexicalError, extras = LexExtras)]
 #[logos(skip r"//[^\n\r]*")]
+#[logos(skip(r"///([^/\n\r][^\n\r]*)?", common::doc_comment))]
+#[logos(skip(r"/\*", common::block_comment))]
 pub enum ToolTipsToken {
-    #[regex(r#""[^"]*""#, |lex| lex.slice().trim_matches('"').to_string())]
+    #[regex(r#""([^"\\]|\\.)*""#, common::string)]
+    #[regex(r#""""([^"]|"[^"]|""[^"])*""""#, common::string)]
     String(String),
 
     #[token(":")]
@@ -22,14 +29,18 @@ impl fmt::Display for ToolTipsToken {
     }
 }
 
-lalrpop_mod!(pub tooltips);
+lalrpop_mod!(#[allow(clippy::ptr_arg, clippy::type_complexity)] pub tooltips);
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ToolTipsData {
     pub map: HashMap<String, Vec<ToolTipsContent>>,
+    /// Doc comments written above where the entry is defined or replaced
+    pub doc: Option<String>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum ToolTipsContent {
     String(String),
     Term(String),
@@ -39,7 +50,7 @@ pub enum ToolTipsContent {
 /// Creates tooltip content from a string that interprets terms for definitions
 /// and highlighting
 // If this gets much more complicated will write a seperate parser
-pub fn create_tooltip_content(value: String) -> Vec<ToolTipsContent> {
+pub fn create_tooltip_content(value: String) -> Result<Vec<ToolTipsContent>, String> {
     let mut result = Vec::new();
     let mut current_string = String::new();
     let mut opened_backtick = false;
@@ -72,12 +83,33 @@ pub fn create_tooltip_content(value: String) -> Vec<ToolTipsContent> {
         }
     }
     if opened_backtick {
-        panic!("Trailing backtick");
+        return Err(format!("Trailing backtick in tooltip \"{value}\""));
     }
     if current_string.chars().count() > 0 {
         result.push(ToolTipsContent::String(current_string));
     }
-    result
+    Ok(result)
+}
+
+/// A tooltips section has no key of its own, its tooltips are merged with
+/// every other section's and checked for duplicates instead.
+/// The key is empty so it can be shown like any other
+impl Patchable for ToolTipsData {
+    type Key = &'static str;
+    type Field = (String, Vec<ToolTipsContent>);
+    fn key(&self) -> &Self::Key {
+        &""
+    }
+    fn set_key(&mut self, _key: Self::Key) {}
+    fn apply(&mut self, (key, content): Self::Field) {
+        self.map.insert(key, content);
+    }
+    fn doc(&self) -> Option<&str> {
+        self.doc.as_deref()
+    }
+    fn set_doc(&mut self, doc: Option<String>) {
+        self.doc = doc;
+    }
 }
 
 impl<'s> DataParser<'s> for ToolTipsData {
@@ -85,7 +117,27 @@ impl<'s> DataParser<'s> for ToolTipsData {
 
     fn parse_tokens(
         tokens: Vec<(usize, Self::Token, usize)>,
-    ) -> Result<Vec<Self>, lalrpop_util::ParseError<usize, Self::Token, String>> {
-        tooltips::ToolTipsDataParser::new().parse(tokens)
+        errors: &mut Vec<lalrpop_util::ErrorRecovery<usize, Self::Token, String>>,
+    ) -> ParsedEntries<Self, Self::Token> {
+        tooltips::ToolTipsDataParser::new().parse(errors, tokens)
+    }
+}
+
+impl PrintScript for ToolTipsData {
+    const SECTION: &'static str = "#tooltips";
+    fn print(&self, w: &mut ScriptWriter) {
+        let mut keys = self.map.keys().collect::<Vec<_>>();
+        keys.sort();
+        for key in keys {
+            let text = self.map[key]
+                .iter()
+                .map(|c| match c {
+                    ToolTipsContent::String(s) => s.clone(),
+                    ToolTipsContent::Term(t) => format!("`{t}`"),
+                    ToolTipsContent::Highlight(h) => format!("~{h}~"),
+                })
+                .collect::<String>();
+            w.line(format_args!("{} : {}", quoted(key), quoted(&text)));
+        }
     }
 }
@@ -0,0 +1,300 @@
+use std::collections::BTreeMap;
+
+use rust_decimal::Decimal;
+
+use crate::{
+    ParseData,
+    building::{BuildingData, CustomGood, PlanetFilter},
+    graph::{self, Edges},
+};
+
+/// What changes when a building is upgraded, positive when the upgrade has more
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct UpgradeDelta {
+    /// Amounts by good id, goods that don't change are left out
+    pub costs: BTreeMap<String, Decimal>,
+    pub consumes: BTreeMap<String, Decimal>,
+    pub upkeep: BTreeMap<String, Decimal>,
+    pub produces: BTreeMap<String, Decimal>,
+    pub energy: Decimal,
+    pub workers: i128,
+    pub housing: i128,
+}
+
+impl UpgradeDelta {
+    pub fn between(from: &BuildingData, to: &BuildingData) -> Self {
+        UpgradeDelta {
+            costs: goods_delta(&from.costs, &to.costs),
+            consumes: goods_delta(&from.consumes, &to.consumes),
+            upkeep: goods_delta(&from.upkeep, &to.upkeep),
+            produces: goods_delta(&from.produces, &to.produces),
+            energy: to.energy - from.energy,
+            workers: i128::from(to.workers) - i128::from(from.workers),
+            housing: i128::from(to.housing) - i128::from(from.housing),
+        }
+    }
+}
+
+/// A building along with everything that upgrades from it
+#[derive(Clone, Debug, PartialEq)]
+pub struct UpgradeTree<'d> {
+    pub building: &'d BuildingData,
+    /// Compared with the building this upgrades from, `None` at the root
+    pub delta: Option<UpgradeDelta>,
+    pub upgrades: Vec<UpgradeTree<'d>>,
+}
+
+/// Why an upgrade can't simply replace the building it upgrades from
+#[derive(Clone, Debug, PartialEq)]
+pub enum Mismatch<'d> {
+    /// Places the upgrade can't be built that the building before it can
+    PlanetFilters(Vec<&'d PlanetFilter>),
+    Category,
+}
+
+/// Every building and what it upgrades from
+#[derive(Clone, Debug, Default)]
+pub struct UpgradeChains<'d> {
+    /// In the order they were loaded
+    pub buildings: Vec<&'d BuildingData>,
+}
+
+impl ParseData {
+    /// The upgrade chains of all buildings
+    pub fn upgrade_chains(&self) -> UpgradeChains<'_> {
+        UpgradeChains::new(&self.building_data)
+    }
+}
+
+impl<'d> UpgradeChains<'d> {
+    pub fn new(buildings: &'d [BuildingData]) -> Self {
+        UpgradeChains {
+            buildings: buildings.iter().collect(),
+        }
+    }
+
+    pub fn get(&self, id: &str) -> Option<&'d BuildingData> {
+        self.buildings.iter().copied().find(|b| b.id == id)
+    }
+
+    /// Buildings that upgrade directly from `id`
+    pub fn upgrades_of(&self, id: &str) -> Vec<&'d BuildingData> {
+        self.buildings
+            .iter()
+            .copied()
+            .filter(|b| b.upgrades_from.as_deref() == Some(id))
+            .collect()
+    }
+
+    /// A tree for every building that doesn't upgrade from a known building,
+    /// buildings in a cycle are not part of any tree
+    pub fn trees(&self) -> Vec<UpgradeTree<'d>> {
+        self.buildings
+            .iter()
+            .copied()
+            .filter(|b| {
+                b.upgrades_from
+                    .as_deref()
+                    .and_then(|u| self.get(u))
+                    .is_none()
+            })
+            .map(|b| self.tree(b, None))
+            .collect()
+    }
+
+    /// Buildings that upgrade from themselves
+    pub fn self_upgrades(&self) -> Vec<&'d BuildingData> {
+        self.buildings
+            .iter()
+            .copied()
+            .filter(|b| b.upgrades_from.as_ref() == Some(&b.id))
+            .collect()
+    }
+
+    /// Groups of buildings that upgrade from each other, including self upgrades
+    pub fn cycles(&self) -> Vec<Vec<&'d str>> {
+        let edges: Edges = self
+            .buildings
+            .iter()
+            .filter_map(|b| Some((b.id.as_str(), [b.upgrades_from.as_deref()?].into())))
+            .collect();
+        graph::cycles(&edges)
+    }
+
+    /// Upgrades that can't replace the building they upgrade from,
+    /// along with that building
+    pub fn mismatches(&self) -> Vec<(&'d BuildingData, &'d BuildingData, Mismatch<'d>)> {
+        let mut mismatches = Vec::new();
+        for upgrade in &self.buildings {
+            let Some(from) = upgrade.upgrades_from.as_deref().and_then(|u| self.get(u)) else {
+                continue;
+            };
+            let uncovered = uncovered_filters(&from.planet_filters, &upgrade.planet_filters);
+            if !uncovered.is_empty() {
+                mismatches.push((from, *upgrade, Mismatch::PlanetFilters(uncovered)));
+            }
+            if from.category != upgrade.category {
+                mismatches.push((from, *upgrade, Mismatch::Category));
+            }
+        }
+        mismatches
+    }
+
+    fn tree(&self, building: &'d BuildingData, from: Option<&BuildingData>) -> UpgradeTree<'d> {
+        UpgradeTree {
+            building,
+            delta: from.map(|f| UpgradeDelta::between(f, building)),
+            upgrades: self
+                .upgrades_of(&building.id)
+                .into_iter()
+                .map(|u| self.tree(u, Some(building)))
+                .collect(),
+        }
+    }
+}
+
+/// Filters of the building upgraded from that the upgrade's filters don't allow,
+/// no filters allows anywhere
+fn uncovered_filters<'d>(
+    from: &'d [PlanetFilter],
+    upgrade: &[PlanetFilter],
+) -> Vec<&'d PlanetFilter> {
+    if upgrade.is_empty() {
+        return Vec::new();
+    }
+    const ANYWHERE: &[PlanetFilter] = &[PlanetFilter::AllPlanets, PlanetFilter::AllOrbitals];
+    let from = if from.is_empty() { ANYWHERE } else { from };
+    from.iter()
+        .filter(|f| {
+            let covered = upgrade.contains(f)
+                || match f {
+                    PlanetFilter::PlanetSide(_) => upgrade.contains(&PlanetFilter::AllPlanets),
+                    PlanetFilter::Orbital(_) => upgrade.contains(&PlanetFilter::AllOrbitals),
+                    PlanetFilter::AllOrbitals | PlanetFilter::AllPlanets => false,
+                };
+            !covered
+        })
+        .collect()
+}
+
+fn goods_delta(from: &[CustomGood], to: &[CustomGood]) -> BTreeMap<String, Decimal> {
+    let mut delta: BTreeMap<String, Decimal> = BTreeMap::new();
+    for good in to {
+        *delta.entry(good.id.clone()).or_default() += good.amount;
+    }
+    for good in from {
+        *delta.entry(good.id.clone()).or_default() -= good.amount;
+    }
+    delta.retain(|_, amount| !amount.is_zero());
+    delta
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+
+    use super::*;
+    use crate::parse;
+
+    fn buildings(contents: &str) -> ParseData {
+        parse("test.txt", &format!("#buildings\n{contents}")).unwrap()
+    }
+
+    #[test]
+    fn tree_holds_delta_of_each_tier() {
+        let data = buildings(
+            r#""mine"
+    name = "Mine"
+    costs = [ { "steel" 5.0 } ]
+    produces = [ { "ore" 3.0 } ]
+    workers = 20
+"deep_mine"
+    name = "Deep Mine"
+    upgrades_from = "mine"
+    costs = [ { "steel" 5.0 } ]
+    produces = [ { "ore" 5.0 } ]
+    workers = 30
+"#,
+        );
+        let trees = data.upgrade_chains().trees();
+        let [tree] = &trees[..] else {
+            panic!("expected one tree, found {trees:?}");
+        };
+        assert_eq!(tree.building.id, "mine");
+        assert!(tree.delta.is_none());
+        let [upgrade] = &tree.upgrades[..] else {
+            panic!("expected one upgrade, found {:?}", tree.upgrades);
+        };
+        let delta = upgrade.delta.as_ref().unwrap();
+        assert!(delta.costs.is_empty());
+        assert_eq!(
+            delta.produces,
+            BTreeMap::from([("ore".to_string(), dec!(2))])
+        );
+        assert_eq!(delta.workers, 10);
+    }
+
+    #[test]
+    fn upgrade_must_fit_where_building_was() {
+        let data = buildings(
+            r#""mine"
+    name = "Mine"
+    build_planets = [ "rocky" "ice" ]
+    category = misc
+"deep_mine"
+    name = "Deep Mine"
+    upgrades_from = "mine"
+    build_planets = [ "rocky" ]
+    category = misc
+"dorm"
+    name = "Dorm"
+    upgrades_from = "mine"
+    category = housing
+"#,
+        );
+        let mismatches: Vec<_> = data
+            .upgrade_chains()
+            .mismatches()
+            .into_iter()
+            .map(|(from, upgrade, mismatch)| (from.id.as_str(), upgrade.id.as_str(), mismatch))
+            .collect();
+        let ice = PlanetFilter::PlanetSide("ice".to_string());
+        assert_eq!(
+            mismatches,
+            [
+                ("mine", "deep_mine", Mismatch::PlanetFilters(vec![&ice])),
+                ("mine", "dorm", Mismatch::Category),
+            ]
+        );
+    }
+
+    #[test]
+    fn cycles_are_left_out_of_trees() {
+        let data = buildings(
+            r#""a"
+    name = "A"
+    upgrades_from = "b"
+"b"
+    name = "B"
+    upgrades_from = "a"
+"c"
+    name = "C"
+    upgrades_from = "c"
+"#,
+        );
+        let chains = data.upgrade_chains();
+        assert!(chains.trees().is_empty());
+        let self_upgrades: Vec<_> = chains.self_upgrades().iter().map(|b| &b.id).collect();
+        assert_eq!(self_upgrades, ["c"]);
+        let mut cycles: Vec<_> = chains
+            .cycles()
+            .into_iter()
+            .map(|mut c| {
+                c.sort();
+                c
+            })
+            .collect();
+        cycles.sort();
+        assert_eq!(cycles, [vec!["a", "b"], vec!["c"]]);
+    }
+}
@@ -0,0 +1,970 @@
+use std::{
+    collections::{BTreeSet, HashMap, hash_map::Entry},
+    fmt,
+    hash::Hash,
+    sync::Arc,
+};
+
+use miette::{Diagnostic, NamedSource, SourceSpan};
+use thiserror::Error;
+
+use crate::{
+    ParseData,
+    augmentations::Effect,
+    common::{Entry as Change, Location, Patchable, field_name, word_positions},
+    graph::{self, Edges},
+    planet_types::{Action, Branch, PlanetTypeData, Value, condition_text},
+    printer::planet_filter,
+    rule_check,
+    stellar_system::{StellarData, StellarObject},
+    tooltips::ToolTipsData,
+    upgrades::Mismatch,
+};
+
+/// The kinds of entries that can be referred to by id from other sections
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ReferenceKind {
+    Building,
+    Good,
+    PlanetType,
+    SpeciesTrait,
+    Tech,
+}
+
+impl fmt::Display for ReferenceKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            ReferenceKind::Building => "building",
+            ReferenceKind::Good => "good",
+            ReferenceKind::PlanetType => "planet type",
+            ReferenceKind::SpeciesTrait => "species trait",
+            ReferenceKind::Tech => "tech",
+        };
+        write!(f, "{name}")
+    }
+}
+
+pub use diagnostics::*;
+
+mod diagnostics {
+    // Kept apart for the same reason as the diagnostics in the crate root
+    #![allow(unused_assignments)]
+
+    use super::*;
+
+    /// An id that does not match any entry of the kind it refers to
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("Unknown {kind} `{id}`")]
+    #[diagnostic(code(pronytic::unknown_id))]
+    pub struct UnknownIdError {
+        #[source_code]
+        pub src: Option<NamedSource<Arc<str>>>,
+        pub kind: ReferenceKind,
+        pub id: String,
+        #[label("No {kind} has this id")]
+        pub bad_bit: Option<SourceSpan>,
+        #[help]
+        pub advice: Option<String>,
+    }
+
+    /// An entry defined with the same id, level or key as an entry before it
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("Duplicate {kind} `{id}`")]
+    #[diagnostic(code(pronytic::duplicate_id))]
+    pub struct DuplicateIdError {
+        #[source_code]
+        pub src: Option<NamedSource<Arc<str>>>,
+        pub kind: &'static str,
+        pub id: String,
+        #[label("Defined again here")]
+        pub duplicate: Option<SourceSpan>,
+        /// Only labelled here when both are in the same file
+        #[label("First defined here")]
+        pub first: Option<SourceSpan>,
+        /// The first definition when it is in another file
+        #[related]
+        pub first_definition: Vec<miette::Report>,
+    }
+
+    /// Where a duplicated entry was first defined, when that is a different file
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("`{id}` was first defined in {}", src.name())]
+    #[diagnostic(severity(Advice))]
+    pub struct FirstDefinition {
+        #[source_code]
+        pub src: NamedSource<Arc<str>>,
+        pub id: String,
+        #[label("First defined here")]
+        pub span: SourceSpan,
+    }
+
+    /// A replacement, patch, deletion or extension with no entry loaded before it to change
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("Nothing to {action}, no {kind} `{key}` was loaded before this")]
+    #[diagnostic(
+        code(pronytic::unmatched_change),
+        help("Entries can only change entries loaded before them, check the load order")
+    )]
+    pub struct UnmatchedChangeError {
+        #[source_code]
+        pub src: NamedSource<Arc<str>>,
+        pub action: &'static str,
+        pub kind: &'static str,
+        pub key: String,
+        #[label("Nothing loaded before this has this key")]
+        pub bad_bit: SourceSpan,
+    }
+
+    /// Entries or templates that extend each other in a loop,
+    /// none of them can be loaded since each waits on another
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("{message}")]
+    #[diagnostic(
+        code(pronytic::extends::cycle),
+        help("One of them has to extend something outside the loop, or nothing at all")
+    )]
+    pub struct ExtensionCycleError {
+        #[source_code]
+        pub src: NamedSource<Arc<str>>,
+        pub message: String,
+        #[label("Part of the loop")]
+        pub bad_bit: SourceSpan,
+    }
+
+    /// A stored string read by a planet type's rules before any rule could have stored it
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("Nothing stores `{key}` before it is read")]
+    #[diagnostic(
+        code(pronytic::unset_stored),
+        help("Store a value under this key in an earlier rule")
+    )]
+    pub struct UnsetStoredError {
+        #[source_code]
+        pub src: Option<NamedSource<Arc<str>>>,
+        pub key: String,
+        #[label("Read here")]
+        pub bad_bit: Option<SourceSpan>,
+    }
+
+    /// An `else if` that is never taken because an arm before it holds whenever it does
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("`else if {condition}` can never be taken")]
+    #[diagnostic(
+        code(pronytic::unreachable_arm),
+        help("An earlier arm of the same if already holds whenever this one does")
+    )]
+    pub struct UnreachableArmError {
+        #[source_code]
+        pub src: Option<NamedSource<Arc<str>>>,
+        pub condition: String,
+        #[label("This arm")]
+        pub bad_bit: Option<SourceSpan>,
+    }
+
+    /// A building that upgrades from itself
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("`{building}` upgrades from itself")]
+    #[diagnostic(code(pronytic::upgrade::self_upgrade))]
+    pub struct SelfUpgradeError {
+        #[source_code]
+        pub src: Option<NamedSource<Arc<str>>>,
+        pub building: String,
+        #[label("Upgrades from itself here")]
+        pub bad_bit: Option<SourceSpan>,
+    }
+
+    /// Buildings that upgrade from each other in a loop,
+    /// none of them can be reached by upgrading from outside it
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("Buildings upgrade from each other in a loop: {}", buildings.join(", "))]
+    #[diagnostic(code(pronytic::upgrade::cycle))]
+    pub struct UpgradeCycleError {
+        #[source_code]
+        pub src: Option<NamedSource<Arc<str>>>,
+        pub buildings: Vec<String>,
+        #[label("Part of the loop")]
+        pub bad_bit: Option<SourceSpan>,
+    }
+
+    /// An upgrade that can't be built everywhere the building it upgrades from can,
+    /// or that is in a different category
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("`{building}` can't replace `{from}`, {reason}")]
+    #[diagnostic(code(pronytic::upgrade::mismatch))]
+    pub struct UpgradeMismatchError {
+        #[source_code]
+        pub src: Option<NamedSource<Arc<str>>>,
+        pub building: String,
+        pub from: String,
+        pub reason: String,
+        #[label("Upgrades from here")]
+        pub bad_bit: Option<SourceSpan>,
+    }
+
+    /// Every problem found while validating
+    #[derive(Error, Debug, Diagnostic)]
+    #[error("{} problems found while validating", errors.len())]
+    #[diagnostic(code(pronytic::validate))]
+    pub struct ValidationReport {
+        #[related]
+        pub errors: Vec<miette::Report>,
+    }
+}
+
+/// Reports the extensions among `changes` that extend each other in a loop,
+/// returning the errors along with the key of every extension in a loop
+pub fn extension_cycles<T: Patchable>(
+    changes: &[(Change<T>, Location)],
+) -> (Vec<miette::Report>, BTreeSet<String>)
+where
+    T::Key: fmt::Display,
+{
+    let extensions: Vec<(String, String, &Location)> = changes
+        .iter()
+        .filter_map(|(change, location)| match change {
+            Change::Extend(e) => Some((e.key.to_string(), e.parent.to_string(), location)),
+            _ => None,
+        })
+        .collect();
+    let edges: Edges = extensions
+        .iter()
+        .map(|(key, parent, _)| (key.as_str(), [parent.as_str()].into()))
+        .collect();
+    let cycles = graph::cycles(&edges);
+    let mut errors = Vec::new();
+    for cycle in &cycles {
+        let Some((_, _, location)) = extensions.iter().find(|(key, ..)| key == cycle[0]) else {
+            continue;
+        };
+        let message = match cycle.as_slice() {
+            [key] => format!("`{key}` extends itself"),
+            _ => {
+                let keys: Vec<_> = cycle.iter().map(|k| format!("`{k}`")).collect();
+                format!("{} extend each other in a loop", keys.join(", "))
+            }
+        };
+        errors.push(
+            ExtensionCycleError {
+                src: location.source(),
+                message,
+                bad_bit: location.span.id,
+            }
+            .into(),
+        );
+    }
+    let in_cycle = cycles.iter().flatten().map(|k| k.to_string()).collect();
+    (errors, in_cycle)
+}
+
+impl ParseData {
+    /// Checks that no id is defined twice, that every replace, patch and delete
+    /// found an entry to change, that every id referenced from another entry exists,
+    /// that planet type rules only read what was stored and have no dead arms
+    /// and that building upgrades don't loop and can replace what they upgrade from,
+    /// this should be called once all files have been combined
+    pub fn validate(&self) -> Result<(), ValidationReport> {
+        let mut validator = Validator::new(self);
+        let locations = &self.locations;
+
+        // Duplicates are checked along with changes that found nothing to change
+        macro_rules! check_entries {
+            ($kind:literal, $field:ident, $key:expr) => {
+                validator.check_duplicates($kind, &self.$field, &locations.$field, $key);
+                validator.check_changes($kind, &self.changes.$field);
+            };
+        }
+        check_entries!("asteroid mining level", asteroid_mining, |a| a.level);
+        check_entries!("augmentation", augmentations, |a| &a.id);
+        check_entries!("building", building_data, |b| &b.id);
+        check_entries!("constant", constants, |c| &c.id);
+        check_entries!("designation", designation_data, |d| &d.id);
+        check_entries!("good", goods_data, |g| &g.id);
+        check_entries!("orbital level", orbital_data, |o| o.level);
+        check_entries!("planet type", planet_type_data, |p| &p.name);
+        check_entries!("rank level", rank_data, |r| r.level);
+        check_entries!("ship", ships, |s| &s.id);
+        check_entries!("shipyard level", shipyard, |s| s.level);
+        check_entries!("shipyard building", shipyard_buildings, |s| &s.id);
+        check_entries!("species trait", species_trait, |s| &s.id);
+        check_entries!("stapledon swarm level", stapledon, |s| s.level);
+        check_entries!("stellar system", stellar_system, |s| s.id);
+        check_entries!("tech", tech_data, |t| &t.id);
+        validator.check_tooltips(&self.tooltips, &locations.tooltips);
+
+        validator.check_references(&self.asteroid_mining, &locations.asteroid_mining, |a| {
+            let goods = a.costs.iter().chain(&a.produces);
+            goods
+                .map(|g| (ReferenceKind::Good, g.id.as_str()))
+                .collect()
+        });
+        validator.check_references(&self.augmentations, &locations.augmentations, |a| {
+            let goods = a
+                .consumes
+                .iter()
+                .map(|g| (ReferenceKind::Good, g.id.as_str()));
+            let traits = a.effects.iter().filter_map(|e| match e {
+                Effect::AddTrait(id) | Effect::RemoveTrait(id) => {
+                    Some((ReferenceKind::SpeciesTrait, id.as_str()))
+                }
+                Effect::AdaptStarType => None,
+            });
+            goods.chain(traits).collect()
+        });
+        validator.check_references(&self.building_data, &locations.building_data, |b| {
+            let goods = b
+                .costs
+                .iter()
+                .chain(&b.consumes)
+                .chain(&b.upkeep)
+                .chain(&b.produces)
+                .map(|g| (ReferenceKind::Good, g.id.as_str()));
+            let tech = b
+                .tech_needed
+                .iter()
+                .map(|t| (ReferenceKind::Tech, t.as_str()));
+            let upgrades = b
+                .upgrades_from
+                .iter()
+                .map(|u| (ReferenceKind::Building, u.as_str()));
+            goods.chain(tech).chain(upgrades).collect()
+        });
+        validator.check_upgrades(self);
+        validator.check_references(&self.designation_data, &locations.designation_data, |d| {
+            let tech = d.tech_required.iter();
+            tech.map(|t| (ReferenceKind::Tech, t.as_str())).collect()
+        });
+        validator.check_references(&self.orbital_data, &locations.orbital_data, |o| {
+            let goods = o.costs.iter();
+            goods
+                .map(|g| (ReferenceKind::Good, g.id.as_str()))
+                .collect()
+        });
+        validator.check_references(&self.planet_type_data, &locations.planet_type_data, |p| {
+            let mut references: Vec<_> = p
+                .abundances
+                .iter()
+                .map(|g| (ReferenceKind::Good, g.id.as_str()))
+                .collect();
+            for branch in p.setup_conditions.iter().chain(&p.terraform_conditions) {
+                branch_references(branch, &mut references);
+            }
+            references
+        });
+        validator.check_rules(&self.planet_type_data, &locations.planet_type_data);
+        validator.check_references(&self.shipyard, &locations.shipyard, |s| {
+            let goods = s.costs.iter();
+            goods
+                .map(|g| (ReferenceKind::Good, g.id.as_str()))
+                .collect()
+        });
+        validator.check_references(
+            &self.shipyard_buildings,
+            &locations.shipyard_buildings,
+            |s| {
+                let goods = s.costs.iter();
+                goods
+                    .map(|g| (ReferenceKind::Good, g.id.as_str()))
+                    .collect()
+            },
+        );
+        validator.check_references(&self.species_trait, &locations.species_trait, |s| {
+            let goods = s.consumes.iter();
+            goods
+                .map(|g| (ReferenceKind::Good, g.id.as_str()))
+                .collect()
+        });
+        validator.check_references(&self.stapledon, &locations.stapledon, |s| {
+            let goods = s.costs.iter().chain(&s.upkeep);
+            goods
+                .map(|g| (ReferenceKind::Good, g.id.as_str()))
+                .collect()
+        });
+        validator.check_references(&self.tech_data, &locations.tech_data, |t| {
+            let prerequisites = t.prerequisites.iter();
+            prerequisites
+                .map(|p| (ReferenceKind::Tech, p.as_str()))
+                .collect()
+        });
+        validator.check_references(
+            &self.stellar_system,
+            &locations.stellar_system,
+            stellar_references,
+        );
+
+        if validator.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(ValidationReport {
+                errors: validator.errors,
+            })
+        }
+    }
+}
+
+struct Validator<'d> {
+    known: HashMap<ReferenceKind, BTreeSet<&'d str>>,
+    errors: Vec<miette::Report>,
+}
+
+impl<'d> Validator<'d> {
+    fn new(data: &'d ParseData) -> Self {
+        let known = HashMap::from([
+            (
+                ReferenceKind::Building,
+                data.building_data.iter().map(|b| b.id.as_str()).collect(),
+            ),
+            (
+                ReferenceKind::Good,
+                data.goods_data.iter().map(|g| g.id.as_str()).collect(),
+            ),
+            (
+                ReferenceKind::PlanetType,
+                data.planet_type_data
+                    .iter()
+                    .map(|p| p.name.as_str())
+                    .collect(),
+            ),
+            (
+                ReferenceKind::SpeciesTrait,
+                data.species_trait.iter().map(|s| s.id.as_str()).collect(),
+            ),
+            (
+                ReferenceKind::Tech,
+                data.tech_data.iter().map(|t| t.id.as_str()).collect(),
+            ),
+        ]);
+        Validator {
+            known,
+            errors: Vec::new(),
+        }
+    }
+
+    /// Reports every reference made by `entries` that does not resolve,
+    /// an id referenced several times by one entry is only reported once
+    fn check_references<T>(
+        &mut self,
+        entries: &'d [T],
+        locations: &[Location],
+        references: impl Fn(&'d T) -> Vec<(ReferenceKind, &'d str)>,
+    ) {
+        for (i, entry) in entries.iter().enumerate() {
+            let location = locations.get(i);
+            let mut reported = Vec::new();
+            for (kind, id) in references(entry) {
+                // Missing ids are left for the schema checks
+                if id.is_empty() || self.known[&kind].contains(id) || reported.contains(&(kind, id))
+                {
+                    continue;
+                }
+                reported.push((kind, id));
+                let location = location.map(|l| l.written_with(id));
+                self.errors.push(
+                    UnknownIdError {
+                        src: location.map(Location::source),
+                        kind,
+                        id: id.to_string(),
+                        bad_bit: location.map(|l| l.find_reference(id)),
+                        advice: self
+                            .suggestion(kind, id)
+                            .map(|s| format!("did you mean `{s}`?")),
+                    }
+                    .into(),
+                );
+            }
+        }
+    }
+
+    /// Reports stored strings read before they could be stored
+    /// and arms of an if that can never be taken
+    fn check_rules(&mut self, planet_types: &[PlanetTypeData], locations: &[Location]) {
+        let stored_anywhere = planet_types
+            .iter()
+            .flat_map(rule_check::stored_keys)
+            .collect();
+        for (i, planet_type) in planet_types.iter().enumerate() {
+            let location = locations.get(i);
+            for key in rule_check::unset_reads(planet_type, &stored_anywhere) {
+                let location = location.map(|l| l.written_with(key));
+                self.errors.push(
+                    UnsetStoredError {
+                        src: location.map(Location::source),
+                        key: key.to_string(),
+                        bad_bit: location.map(|l| find_read(l, key)),
+                    }
+                    .into(),
+                );
+            }
+            for unreachable in rule_check::unreachable_arms(planet_type) {
+                let arm = location.map(|l| find_arm(l, unreachable.field, unreachable.index));
+                self.errors.push(
+                    UnreachableArmError {
+                        src: arm.map(|(l, _)| l.source()),
+                        condition: condition_text(&unreachable.arm.condition),
+                        bad_bit: arm.map(|(_, span)| span),
+                    }
+                    .into(),
+                );
+            }
+        }
+    }
+
+    /// Reports buildings that upgrade from themselves or from each other in a loop,
+    /// along with upgrades that can't replace what they upgrade from
+    fn check_upgrades(&mut self, data: &ParseData) {
+        let chains = data.upgrade_chains();
+        let location = |id: &str| {
+            let i = data.building_data.iter().position(|b| b.id == id)?;
+            data.locations.building_data.get(i)
+        };
+        for building in chains.self_upgrades() {
+            let location = location(&building.id).map(|l| l.written_with(&building.id));
+            self.errors.push(
+                SelfUpgradeError {
+                    src: location.map(Location::source),
+                    building: building.id.clone(),
+                    // The last mention, the first is the building's own id
+                    bad_bit: location.and_then(|l| {
+                        let quoted = format!("\"{}\"", building.id);
+                        let i = l.text().rfind(&quoted)?;
+                        Some((l.span.entry.offset() + i, quoted.len()).into())
+                    }),
+                }
+                .into(),
+            );
+        }
+        // Self upgrades are already reported
+        for cycle in chains.cycles().into_iter().filter(|c| c.len() > 1) {
+            let location = location(cycle[0]);
+            self.errors.push(
+                UpgradeCycleError {
+                    src: location.map(Location::source),
+                    buildings: cycle.iter().map(|b| b.to_string()).collect(),
+                    bad_bit: location.map(|l| l.span.id),
+                }
+                .into(),
+            );
+        }
+        for (from, upgrade, mismatch) in chains.mismatches() {
+            let reason = match mismatch {
+                Mismatch::Category => "they are in different categories".to_string(),
+                Mismatch::PlanetFilters(filters) => {
+                    let filters: Vec<_> = filters.into_iter().map(planet_filter).collect();
+                    format!("it can't be built on {}", filters.join(", "))
+                }
+            };
+            let location = location(&upgrade.id).map(|l| l.written_with(&from.id));
+            self.errors.push(
+                UpgradeMismatchError {
+                    src: location.map(Location::source),
+                    building: upgrade.id.clone(),
+                    from: from.id.clone(),
+                    reason,
+                    bad_bit: location.map(|l| l.find_reference(&from.id)),
+                }
+                .into(),
+            );
+        }
+    }
+
+    /// Reports entries whose key matches an entry before them
+    fn check_duplicates<T, K>(
+        &mut self,
+        kind: &'static str,
+        entries: &'d [T],
+        locations: &[Location],
+        key: impl Fn(&'d T) -> K,
+    ) where
+        K: Eq + Hash + ToString,
+    {
+        let mut seen = HashMap::new();
+        for (i, entry) in entries.iter().enumerate() {
+            let location = locations.get(i);
+            let definition = (location, location.map(|l| l.span.id));
+            match seen.entry(key(entry)) {
+                Entry::Occupied(first) => {
+                    self.duplicate(kind, first.key().to_string(), *first.get(), definition)
+                }
+                Entry::Vacant(v) => {
+                    v.insert(definition);
+                }
+            }
+        }
+    }
+
+    /// Reports replacements, patches and deletions
+    /// that had no entry loaded before them to change
+    fn check_changes<T: Patchable>(&mut self, kind: &'static str, changes: &[(Change<T>, Location)])
+    where
+        T::Key: fmt::Display,
+    {
+        let (cycles, in_cycle) = extension_cycles(changes);
+        self.errors.extend(cycles);
+
+        for (change, location) in changes {
+            let (key, bad_bit) = match change {
+                Change::Extend(e) if in_cycle.contains(&e.key.to_string()) => continue,
+                Change::Extend(e) => {
+                    let parent = e.parent.to_string();
+                    let span = location.find_reference(&parent);
+                    (parent, span)
+                }
+                _ => {
+                    let id = location.span.id;
+                    let key = &location.file.contents[id.offset()..id.offset() + id.len()];
+                    (key.trim_matches('"').to_string(), id)
+                }
+            };
+            self.errors.push(
+                UnmatchedChangeError {
+                    src: location.source(),
+                    action: change.keyword(),
+                    kind,
+                    key,
+                    bad_bit,
+                }
+                .into(),
+            );
+        }
+    }
+
+    /// Reports tooltip keys that were already defined in an earlier section,
+    /// keys repeated within a section are reported while parsing
+    fn check_tooltips(&mut self, tooltips: &[ToolTipsData], locations: &[Location]) {
+        let mut seen = HashMap::new();
+        for (i, section) in tooltips.iter().enumerate() {
+            let location = locations.get(i);
+            let keys: Vec<(String, Option<SourceSpan>)> = match location {
+                Some(l) => l
+                    .span
+                    .fields
+                    .iter()
+                    .map(|field| {
+                        let key = field_name(&l.file.contents, *field);
+                        let span = (field.offset(), key.len()).into();
+                        (key.trim_matches('"').to_string(), Some(span))
+                    })
+                    .collect(),
+                None => {
+                    let mut keys: Vec<_> = section.map.keys().map(|k| (k.clone(), None)).collect();
+                    keys.sort();
+                    keys
+                }
+            };
+            for (key, span) in keys {
+                match seen.entry(key) {
+                    Entry::Occupied(first) => {
+                        let (first_section, first_location, first_span) = *first.get();
+                        if first_section != i {
+                            let id = first.key().clone();
+                            self.duplicate(
+                                "tooltip",
+                                id,
+                                (first_location, first_span),
+                                (location, span),
+                            );
+                        }
+                    }
+                    Entry::Vacant(v) => {
+                        v.insert((i, location, span));
+                    }
+                }
+            }
+        }
+    }
+
+    fn duplicate(
+        &mut self,
+        kind: &'static str,
+        id: String,
+        (first_location, first): (Option<&Location>, Option<SourceSpan>),
+        (location, duplicate): (Option<&Location>, Option<SourceSpan>),
+    ) {
+        let same_file = match (first_location, location) {
+            (Some(a), Some(b)) => Arc::ptr_eq(&a.file, &b.file),
+            _ => false,
+        };
+        let first_definition = match (first_location, first) {
+            (Some(l), Some(span)) if !same_file => vec![
+                FirstDefinition {
+                    src: l.source(),
+                    id: id.clone(),
+                    span,
+                }
+                .into(),
+            ],
+            _ => Vec::new(),
+        };
+        self.errors.push(
+            DuplicateIdError {
+                src: location.map(Location::source),
+                kind,
+                id,
+                duplicate,
+                first: first.filter(|_| same_file),
+                first_definition,
+            }
+            .into(),
+        );
+    }
+
+    /// The closest known id of the same kind, if any is close enough to be a typo
+    fn suggestion(&self, kind: ReferenceKind, id: &str) -> Option<&'d str> {
+        let max_distance = (id.chars().count() / 3).max(1);
+        self.known[&kind]
+            .iter()
+            .map(|known| (edit_distance(id, known), *known))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .min_by_key(|(distance, _)| *distance)
+            .map(|(_, known)| known)
+    }
+}
+
+fn branch_references<'d>(branch: &'d Branch, references: &mut Vec<(ReferenceKind, &'d str)>) {
+    for arm in &branch.if_conditions {
+        for value in arm.condition.values() {
+            if let Value::GoodsAbundance(id) = value {
+                references.push((ReferenceKind::Good, id));
+            }
+        }
+    }
+    let actions = branch
+        .if_conditions
+        .iter()
+        .flat_map(|c| &c.actions)
+        .chain(&branch.else_actions);
+    for action in actions {
+        match action {
+            Action::SetPlanetType(id) => references.push((ReferenceKind::PlanetType, id)),
+            Action::Branch(b) => branch_references(b, references),
+            Action::None
+            | Action::SetAsset(_)
+            | Action::SetStored(_, _)
+            | Action::SetStoredRandom(_, _) => {}
+        }
+    }
+}
+
+/// Where the `index`th `if` of `field` is written, counting through the entry
+/// then its patches since their branches are added after the entry's.
+/// Falls back to the entry's id when it can't be found
+fn find_arm<'l>(location: &'l Location, field: &str, index: usize) -> (&'l Location, SourceSpan) {
+    let fields = std::iter::once(location)
+        .chain(&location.patches)
+        .flat_map(|l| l.span.fields.iter().map(move |f| (l, *f)))
+        .filter(|(l, f)| field_name(&l.file.contents, *f) == field);
+    let arm = fields
+        .flat_map(|(l, f)| {
+            let text = &l.file.contents[f.offset()..f.offset() + f.len()];
+            word_positions(text, "if").map(move |i| (l, text, f.offset(), i))
+        })
+        .nth(index);
+    let Some((l, text, offset, i)) = arm else {
+        return (location, location.span.id);
+    };
+    let start = text[..i]
+        .trim_end()
+        .strip_suffix("else")
+        .map_or(i, str::len);
+    let end = text[i..]
+        .find('{')
+        .map_or(text.len(), |e| i + text[i..e + i].trim_end().len());
+    (l, (offset + start, end - start).into())
+}
+
+/// Where `key` is first read rather than stored inside the entry
+fn find_read(location: &Location, key: &str) -> SourceSpan {
+    let text = location.text();
+    let quoted = format!("\"{key}\"");
+    let read = text.match_indices(&quoted).find(|(i, _)| {
+        let after = text[i + quoted.len()..].trim_start();
+        let after = after.strip_prefix(']').unwrap_or(after);
+        !after.trim_start().starts_with('=')
+    });
+    match read {
+        Some((i, _)) => (location.span.entry.offset() + i, quoted.len()).into(),
+        None => location.find_reference(key),
+    }
+}
+
+fn stellar_references(stellar: &StellarData) -> Vec<(ReferenceKind, &str)> {
+    let planets = stellar.orbiting.iter().flat_map(|o| match o {
+        StellarObject::PlanetData(p) => std::slice::from_ref(p),
+        StellarObject::AsteroidBelt(planets) => planets.as_slice(),
+    });
+    let mut references = Vec::new();
+    for planet in planets {
+        references.push((ReferenceKind::PlanetType, planet.planet_type.as_str()));
+        references.extend(
+            planet
+                .natural_resources
+                .iter()
+                .map(|n| (ReferenceKind::Good, n.id.as_str())),
+        );
+        for moon in &planet.moons {
+            references.push((ReferenceKind::PlanetType, moon.planet_type.as_str()));
+            references.extend(
+                moon.natural_resources
+                    .iter()
+                    .map(|n| (ReferenceKind::Good, n.id.as_str())),
+            );
+        }
+    }
+    references
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DuplicateIdError, FirstDefinition};
+    use crate::parse;
+
+    /// Where the first label of each validation error starts
+    fn labelled(contents: &str) -> Vec<usize> {
+        let data = parse("test.txt", contents).unwrap();
+        let Err(report) = data.validate() else {
+            return Vec::new();
+        };
+        report
+            .errors
+            .iter()
+            .filter_map(|e| e.labels()?.next())
+            .map(|l| l.offset())
+            .collect()
+    }
+
+    #[test]
+    fn unknown_id_suggests_close_match() {
+        let contents = r#"#goods
+"iron_ore"
+    name = "Iron ore"
+    icon = "ore"
+#buildings
+"mine"
+    name = "Mine"
+    produces = [ { "iron_ore" 1.0 } ]
+    consumes = [ { "iron_oer" 1.0 } { "coal" 1.0 } ]
+"#;
+        let report = parse("test.txt", contents).unwrap().validate().unwrap_err();
+        let found: Vec<_> = report
+            .errors
+            .iter()
+            .map(|e| (e.to_string(), e.help().map(|h| h.to_string())))
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (
+                    "Unknown good `iron_oer`".to_string(),
+                    Some("did you mean `iron_ore`?".to_string())
+                ),
+                ("Unknown good `coal`".to_string(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn known_ids_are_valid() {
+        let contents = r#"#goods
+"ore"
+    name = "Ore"
+    icon = "ore"
+#buildings
+"mine"
+    name = "Mine"
+    produces = [ { "ore" 1.0 } ]
+"#;
+        assert!(parse("test.txt", contents).unwrap().validate().is_ok());
+    }
+
+    #[test]
+    fn duplicate_across_files_points_at_both() {
+        let first = "#goods\n\"ore\"\n    name = \"Ore\"\n    icon = \"ore\"\n";
+        let second = "#goods\n\"rock\"\n    name = \"Rock\"\n    icon = \"rock\"\n\"ore\"\n    name = \"Ore\"\n    icon = \"ore\"\n";
+        let mut data = parse("a.txt", first).unwrap();
+        assert!(data.combine(parse("b.txt", second).unwrap()).is_empty());
+
+        let report = data.validate().unwrap_err();
+        let [error] = &report.errors[..] else {
+            panic!("expected one error, found {:?}", report.errors);
+        };
+        let duplicate = error.downcast_ref::<DuplicateIdError>().unwrap();
+        assert_eq!(duplicate.id, "ore");
+        assert_eq!(duplicate.src.as_ref().unwrap().name(), "b.txt");
+        assert_eq!(
+            duplicate.duplicate.map(|s| s.offset()),
+            second.find("\"ore\"")
+        );
+        // The first definition is in another file so it is labelled there instead
+        assert!(duplicate.first.is_none());
+        let [first_definition] = &duplicate.first_definition[..] else {
+            panic!("expected the first definition");
+        };
+        let first_definition = first_definition.downcast_ref::<FirstDefinition>().unwrap();
+        assert_eq!(first_definition.src.name(), "a.txt");
+        assert_eq!(Some(first_definition.span.offset()), first.find("\"ore\""));
+    }
+
+    #[test]
+    fn patched_reference_points_at_patch() {
+        let contents = r#"#buildings
+"mine"
+    name = "Mine"
+    produces = [ { "ore" 1.0 } ]
+patch "mine"
+    consumes = [ { "coal" 1.0 } ]
+"#;
+        let mut expected = [
+            contents.find("\"ore\"").unwrap(),
+            contents.find("\"coal\"").unwrap(),
+        ];
+        let mut found = labelled(contents);
+        found.sort();
+        expected.sort();
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn unreachable_arm_points_at_arm() {
+        let contents = r#"#planet_types
+"hot"
+    class = rocky
+    set_asset = "planet"
+    setup {
+        if temperature_celsius GT 50 {
+            set_asset = "hot"
+        } else if temperature_celsius GT 80 {
+            set_asset = "hotter"
+        }
+    }
+patch "hot"
+    setup {
+        if water_level LT 1 {
+            if oxygen_level GT 2 {
+                set_asset = "dry"
+            } else if oxygen_level GT 3 {
+                set_asset = "drier"
+            }
+        }
+    }
+"#;
+        let expected = [
+            contents.find("else if temperature_celsius").unwrap(),
+            contents.find("else if oxygen_level").unwrap(),
+        ];
+        assert_eq!(labelled(contents), expected);
+    }
+}